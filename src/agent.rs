@@ -1,74 +1,629 @@
 //! Agent Struct
 //!
 //! Used for handling the main logic of each agent
-use crate::communication::{ABORT, ACK, COMMIT, PAYMENT_ERR, PAYMENT_OK, PREPARE};
+use crate::communication::{ABORT, ACK, COMMIT, PAYMENT_ERR, PAYMENT_OK, PREPARE, STALE_EPOCH};
 use crate::logger::Logger;
+use crate::replica_msg::{
+    ReplicaMsg, REPLICA_ACK, REPLICA_FINISH, REPLICA_HEARTBEAT, REPLICA_MSG_MAX, REPLICA_POLL,
+    REPLICA_STATE,
+};
 use rand::Rng;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+/// Most journal changes kept to be sent again to the backups that didn't
+/// acknowledge them, as a backup that is down never does
+const MAX_UNACKED: usize = 1000;
+/// Time the primary waits for its backups to acknowledge a journal change
+/// before answering the coordinator without them
+const REPLICA_ACK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time after which the primary sends a journal change again to the backups
+/// that didn't acknowledge it
+const REPLICA_RETRY: Duration = Duration::from_millis(100);
+/// Time a backup about to take over waits for the other replicas to answer its
+/// poll. A primary answers along with its heartbeats, so this is longer than
+/// the interval between them.
+const POLL_TIMEOUT: Duration = Duration::from_secs(1);
 
 /// Agent Struct
 pub struct Agent {
     /// Name of the agent used for logging purposes
     pub name: String,
-    /// TCP port used by the agent
-    pub port: u16,
+    /// Address the agent binds its TCP listener and UDP replica socket to
+    pub bind: SocketAddr,
     /// Success rate of each request sent to the agent
     pub success_rate: f64,
     /// Logger used by the agent
     pub logger: Logger,
-    /// All transaction states handled by the agent
-    transactions_state: HashMap<u32, u8>,
+    /// Index of this replica in the agent ports list, where 0 is the original primary
+    pub replica: usize,
+    /// Addresses of the replicas that come after this one, which act as its backups
+    backups: Vec<SocketAddr>,
+    /// Addresses of every replica of the agent, including this one
+    replicas: Vec<SocketAddr>,
+    /// UDP socket, on the same address, used to talk with the other replicas
+    pub replica_socket: UdpSocket,
+    /// Journal of every payment handled by the agent, by id: its last state
+    /// and the vote given when it was prepared
+    journal: HashMap<String, (u8, u8)>,
+    /// Highest coordinator epoch seen, messages from older epochs are rejected
+    epoch: u64,
+    /// Number of the last journal change made as primary, or applied as backup
+    seq: u64,
+    /// Replica whose changes a backup applies, as each primary numbers its own
+    primary: u32,
+    /// Journal changes sent to the backups that not all of them acknowledged,
+    /// by number
+    unacked: VecDeque<(u64, Vec<u8>)>,
+    /// Number of the last change acknowledged by each backup
+    acked: Vec<u64>,
+    /// Whether each backup is lagging behind, as it didn't acknowledge a
+    /// change in time, so the primary doesn't wait for it until it catches up
+    lagging: Vec<bool>,
 }
 
 impl Agent {
-    pub fn new(name: String, port: u16, success_rate: f64) -> Self {
+    /// Creates the replica number `replica` of the agent, out of all its advertised
+    /// `addrs`, binding to its address in `binds`
+    pub fn new(
+        name: String,
+        addrs: &[SocketAddr],
+        binds: &[SocketAddr],
+        replica: usize,
+        success_rate: f64,
+    ) -> Self {
+        let bind = binds[replica];
+        let logger_name = if replica == 0 {
+            name.clone()
+        } else {
+            format!("{}-{}", name, replica)
+        };
         Agent {
-            name: name.clone(),
-            port,
+            name,
+            bind,
             success_rate,
-            logger: Logger::new(name),
-            transactions_state: HashMap::new(),
+            logger: Logger::new(logger_name),
+            replica,
+            backups: addrs[replica + 1..].to_vec(),
+            replicas: addrs.to_vec(),
+            replica_socket: UdpSocket::bind(bind)
+                .unwrap_or_else(|_| panic!("replica socket on {} failed", bind)),
+            journal: HashMap::new(),
+            epoch: 0,
+            seq: 0,
+            primary: 0,
+            unacked: VecDeque::new(),
+            acked: vec![0; addrs.len() - replica - 1],
+            lagging: vec![false; addrs.len() - replica - 1],
+        }
+    }
+
+    /// Checks the epoch of a coordinator message, keeping it if it is the
+    /// highest seen. Returns STALE_EPOCH if it is older than that, or None if
+    /// the message can be handled.
+    pub fn check_epoch(&mut self, payment_id: &str, epoch: u64) -> Option<u8> {
+        if epoch < self.epoch {
+            self.logger.info(format!(
+                "Transaction {} | Rejected from stale epoch {} (current is {})",
+                payment_id, epoch, self.epoch
+            ));
+            return Some(STALE_EPOCH);
         }
+        self.epoch = epoch;
+        None
     }
 
     /// Handles the PREPARE phase, simulating the transaction result
     /// and printing the result to the logger.
-    /// Returns PAYMENT_OK if the transaction was successful and PAYMENT_ERR otherwise
-    pub fn prepare(&mut self, transaction_id: u32, data: u32) -> u8 {
+    /// Returns PAYMENT_OK if the transaction was successful and PAYMENT_ERR otherwise.
+    /// A payment that was already prepared or committed gets the same vote
+    /// again, so it is never charged twice.
+    pub fn prepare(&mut self, payment_id: &str, data: u32) -> u8 {
+        if let Some(&(state, vote)) = self.journal.get(payment_id) {
+            if state != ABORT {
+                self.logger.trace(format!(
+                    "Transaction {} | PREPARE again, already {}",
+                    payment_id, state as char
+                ));
+                return vote;
+            }
+        }
         self.logger
-            .trace(format!("Transaction {} | PREPARE", transaction_id));
-        self.transactions_state.insert(transaction_id, PREPARE);
+            .trace(format!("Transaction {} | PREPARE", payment_id));
 
         let success = rand::thread_rng().gen_bool(self.success_rate);
-        if success {
-            self.logger.info(format!("Payment of ${} | OK", data));
-            PAYMENT_OK
-        } else {
-            self.logger.info(format!("Payment of ${} | ERR", data));
-            PAYMENT_ERR
-        }
+        let vote = if success { PAYMENT_OK } else { PAYMENT_ERR };
+        self.set_state(payment_id, PREPARE, vote);
+        self.logger.info(format!(
+            "Payment {} of ${} | {}",
+            payment_id,
+            data,
+            if success { "OK" } else { "ERR" }
+        ));
+        vote
     }
 
     /// Handles the COMMIT phase, logging the transaction and
-    /// adding the state to the states HashMap. Returns ACK
-    pub fn commit(&mut self, transaction_id: u32) -> u8 {
+    /// adding the state to the journal. Returns ACK
+    pub fn commit(&mut self, payment_id: &str) -> u8 {
         self.logger
-            .trace(format!("Transaction {} | COMMIT", transaction_id));
-        self.transactions_state.insert(transaction_id, COMMIT);
+            .trace(format!("Transaction {} | COMMIT", payment_id));
+        self.set_state(payment_id, COMMIT, PAYMENT_OK);
         ACK
     }
 
     /// Handles the ABORT phase, logging the transaction and
-    /// adding the state to the states HashMap. Returns ACK
-    pub fn abort(&mut self, transaction_id: u32) -> u8 {
-        self.logger
-            .trace(format!("Transaction {} | ABORT", transaction_id));
-        self.transactions_state.insert(transaction_id, ABORT);
+    /// adding the state to the journal. Returns ACK. A payment that was
+    /// already committed stays that way, as the coordinator disagrees with a
+    /// decision it already took.
+    pub fn abort(&mut self, payment_id: &str) -> u8 {
+        match self.journal.get(payment_id) {
+            Some(&(COMMIT, _)) => self.logger.info(format!(
+                "Transaction {} | Disagreement: ABORT of a committed payment, keeping the COMMIT",
+                payment_id
+            )),
+            _ => {
+                self.logger
+                    .trace(format!("Transaction {} | ABORT", payment_id));
+                self.set_state(payment_id, ABORT, PAYMENT_ERR);
+            }
+        }
         ACK
     }
 
-    /// Returns ACK
+    /// Tells the backups to stop and returns ACK
     pub fn finish(&mut self) -> u8 {
+        let msg = self.replica_msg(REPLICA_FINISH, "", 0, 0);
+        for addr in &self.backups {
+            let _ignore = self.replica_socket.send_to(&msg, addr);
+        }
         ACK
     }
+
+    /// Sends a heartbeat to the backups, so they know this replica is alive,
+    /// along with the journal changes each one didn't acknowledge yet
+    pub fn heartbeat(&mut self) {
+        self.receive_acks();
+        let heartbeat = self.replica_msg(REPLICA_HEARTBEAT, "", 0, 0);
+        for (backup, addr) in self.backups.iter().enumerate() {
+            for (_, msg) in self
+                .unacked
+                .iter()
+                .filter(|(seq, _)| *seq > self.acked[backup])
+            {
+                let _ignore = self.replica_socket.send_to(msg, addr);
+            }
+            let _ignore = self.replica_socket.send_to(&heartbeat, addr);
+        }
+    }
+
+    /// Keeps the coordinator epoch received from the primary
+    pub fn apply_epoch(&mut self, epoch: u64) {
+        self.epoch = self.epoch.max(epoch);
+    }
+
+    /// Applies a journal change or a heartbeat received from the replica acting
+    /// as primary, and acknowledges the last change applied. Changes are only
+    /// applied in order, so the ones after a missed change are dropped until
+    /// the primary sends them again.
+    pub fn apply(&mut self, msg: &ReplicaMsg, from: SocketAddr) {
+        if msg.sender != self.primary {
+            // A backup that took over numbers its changes from where it was
+            self.primary = msg.sender;
+            self.seq = match msg.kind {
+                REPLICA_STATE => msg.seq.saturating_sub(1),
+                _ => msg.seq,
+            };
+        }
+        match msg.kind {
+            REPLICA_STATE if msg.seq == self.seq + 1 => {
+                self.logger.trace(format!(
+                    "Replicated transaction {} | {}",
+                    msg.payment_id, msg.state as char
+                ));
+                self.journal
+                    .insert(msg.payment_id.clone(), (msg.state, msg.vote));
+                self.seq = msg.seq;
+            }
+            REPLICA_STATE | REPLICA_HEARTBEAT if msg.seq > self.seq => {
+                self.logger.trace(format!(
+                    "Missed journal changes, got {} after {}",
+                    msg.seq, self.seq
+                ));
+            }
+            _ => {}
+        }
+        let ack = self.replica_msg(REPLICA_ACK, "", 0, 0);
+        let _ignore = self.replica_socket.send_to(&ack, from);
+    }
+
+    /// Answers the poll of a backup about to take over with the last change
+    /// applied by this backup
+    pub fn answer_poll(&self, from: SocketAddr) {
+        let ack = self.replica_msg(REPLICA_ACK, "", 0, 0);
+        let _ignore = self.replica_socket.send_to(&ack, from);
+    }
+
+    /// Polls the other replicas before taking over, as a backup that lagged
+    /// behind misses answers the coordinator got. Returns the address of a
+    /// replica that has to be primary instead: one still acting as primary,
+    /// which answers with a heartbeat, or a backup that applied more changes.
+    /// Otherwise returns None, and this replica can take over.
+    pub fn poll_replicas(&mut self) -> Option<SocketAddr> {
+        let poll = self.replica_msg(REPLICA_POLL, "", 0, 0);
+        for (replica, addr) in self.replicas.iter().enumerate() {
+            if replica != self.replica {
+                let _ignore = self.replica_socket.send_to(&poll, addr);
+            }
+        }
+
+        let deadline = Instant::now() + POLL_TIMEOUT;
+        let mut buffer = [0; REPLICA_MSG_MAX];
+        let mut now = Instant::now();
+        while now < deadline {
+            self.replica_socket
+                .set_read_timeout(Some(deadline - now))
+                .expect("Unable to set read timeout");
+            if let Ok((len, from)) = self.replica_socket.recv_from(&mut buffer) {
+                match ReplicaMsg::from_bytes(&buffer[..len]) {
+                    Ok(msg) if msg.kind == REPLICA_POLL => self.answer_poll(from),
+                    Ok(msg) if msg.kind == REPLICA_ACK && msg.seq > self.seq => return Some(from),
+                    Ok(msg) if msg.kind == REPLICA_STATE || msg.kind == REPLICA_HEARTBEAT => {
+                        if (msg.sender as usize) < self.replica {
+                            self.apply(&msg, from);
+                        }
+                        return Some(from);
+                    }
+                    _ => {}
+                }
+            }
+            now = Instant::now();
+        }
+        None
+    }
+
+    /// Takes over as primary, sending the whole journal to the backups as new
+    /// changes, as they may have missed changes of the previous primary that
+    /// this replica applied
+    pub fn take_over(&mut self) {
+        let mut journal: Vec<(String, (u8, u8))> = self
+            .journal
+            .iter()
+            .map(|(payment_id, change)| (payment_id.clone(), *change))
+            .collect();
+        journal.sort();
+        for (payment_id, (state, vote)) in journal {
+            self.set_state(&payment_id, state, vote);
+        }
+    }
+
+    /// Stores the new state of a payment and replicates it to the backups.
+    /// Only returns once the backups that aren't lagging acknowledged it, so a
+    /// backup that takes over knows about every answer the coordinator got.
+    /// A lagging backup never takes over, as it polls the others first.
+    fn set_state(&mut self, payment_id: &str, state: u8, vote: u8) {
+        self.journal.insert(payment_id.to_string(), (state, vote));
+        if self.backups.is_empty() {
+            return;
+        }
+        self.seq += 1;
+        let msg = self.replica_msg(REPLICA_STATE, payment_id, state, vote);
+        for addr in &self.backups {
+            let _ignore = self.replica_socket.send_to(&msg, addr);
+        }
+        self.unacked.push_back((self.seq, msg.clone()));
+        if self.unacked.len() > MAX_UNACKED {
+            if let Some((seq, _)) = self.unacked.pop_front() {
+                self.logger.info(format!(
+                    "Journal change {} was not acknowledged by every backup, giving up on it",
+                    seq
+                ));
+            }
+        }
+        self.wait_for_acks(&msg);
+    }
+
+    /// Waits for the backups that aren't lagging to acknowledge the last
+    /// change, sending it again to the ones that didn't. The ones that don't
+    /// acknowledge it in time are lagging from then on.
+    fn wait_for_acks(&mut self, msg: &[u8]) {
+        let deadline = Instant::now() + REPLICA_ACK_TIMEOUT;
+        let mut retry = Instant::now() + REPLICA_RETRY;
+        let mut buffer = [0; REPLICA_MSG_MAX];
+        loop {
+            let waiting: Vec<usize> = (0..self.backups.len())
+                .filter(|backup| !self.lagging[*backup] && self.acked[*backup] < self.seq)
+                .collect();
+            if waiting.is_empty() {
+                return;
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                for backup in waiting {
+                    self.logger.info(format!(
+                        "Backup {} didn't acknowledge journal change {}, not waiting for it",
+                        self.backups[backup], self.seq
+                    ));
+                    self.lagging[backup] = true;
+                }
+                return;
+            }
+            if now >= retry {
+                for backup in &waiting {
+                    let _ignore = self.replica_socket.send_to(msg, self.backups[*backup]);
+                }
+                retry = now + REPLICA_RETRY;
+            }
+
+            self.replica_socket
+                .set_read_timeout(Some(retry.min(deadline) - now))
+                .expect("Unable to set read timeout");
+            if let Ok((len, from)) = self.replica_socket.recv_from(&mut buffer) {
+                self.handle_ack(&buffer[..len], from);
+            }
+        }
+    }
+
+    /// Keeps the acknowledgements the backups sent, without waiting for them,
+    /// and forgets the changes every backup acknowledged
+    fn receive_acks(&mut self) {
+        self.replica_socket
+            .set_nonblocking(true)
+            .expect("Unable to set non-blocking");
+        let mut buffer = [0; REPLICA_MSG_MAX];
+        loop {
+            match self.replica_socket.recv_from(&mut buffer) {
+                Ok((len, from)) => self.handle_ack(&buffer[..len], from),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(_) => continue,
+            }
+        }
+        self.replica_socket
+            .set_nonblocking(false)
+            .expect("Unable to set blocking");
+
+        let acked = self.acked.iter().copied().min().unwrap_or(self.seq);
+        while self.unacked.front().is_some_and(|(seq, _)| *seq <= acked) {
+            self.unacked.pop_front();
+        }
+    }
+
+    /// Keeps the last change acknowledged by a backup, which stops lagging
+    /// once it acknowledges every change, and answers the polls of the backups
+    /// with a heartbeat, as this replica is still acting as primary
+    fn handle_ack(&mut self, bytes: &[u8], from: SocketAddr) {
+        let msg = match ReplicaMsg::from_bytes(bytes) {
+            Ok(msg) => msg,
+            Err(err) => {
+                self.logger
+                    .info(format!("Discarded replica message: {}", err));
+                return;
+            }
+        };
+        if msg.kind == REPLICA_POLL {
+            let heartbeat = self.replica_msg(REPLICA_HEARTBEAT, "", 0, 0);
+            let _ignore = self.replica_socket.send_to(&heartbeat, from);
+            return;
+        }
+        let backup = match (msg.sender as usize).checked_sub(self.replica + 1) {
+            Some(backup) if backup < self.backups.len() && msg.kind == REPLICA_ACK => backup,
+            _ => return,
+        };
+        self.acked[backup] = self.acked[backup].max(msg.seq);
+        if self.lagging[backup] && self.acked[backup] >= self.seq {
+            self.logger.info(format!(
+                "Backup {} caught up with the journal",
+                self.backups[backup]
+            ));
+            self.lagging[backup] = false;
+        }
+    }
+
+    /// Replica message of this replica with its epoch and last change
+    fn replica_msg(&self, kind: u8, payment_id: &str, state: u8, vote: u8) -> Vec<u8> {
+        ReplicaMsg::to_bytes(&ReplicaMsg {
+            kind,
+            sender: self.replica as u32,
+            payment_id: payment_id.to_string(),
+            state,
+            vote,
+            epoch: self.epoch,
+            seq: self.seq,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    /// Address on any free port of localhost
+    fn any_addr() -> SocketAddr {
+        "127.0.0.1:0".parse().expect("Valid address")
+    }
+
+    /// Socket standing for another replica, on any free port
+    fn replica_socket() -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind(any_addr()).expect("Free port");
+        socket
+            .set_read_timeout(Some(Duration::from_millis(200)))
+            .expect("Unable to set read timeout");
+        let addr = socket.local_addr().expect("Bound socket");
+        (socket, addr)
+    }
+
+    fn msg(kind: u8, sender: u32, payment_id: &str, seq: u64) -> ReplicaMsg {
+        ReplicaMsg {
+            kind,
+            sender,
+            payment_id: payment_id.to_string(),
+            state: PREPARE,
+            vote: PAYMENT_OK,
+            epoch: 0,
+            seq,
+        }
+    }
+
+    /// Every replica message received by a socket until it times out
+    fn received(socket: &UdpSocket) -> Vec<ReplicaMsg> {
+        let mut buffer = [0; REPLICA_MSG_MAX];
+        let mut msgs = vec![];
+        while let Ok((len, _)) = socket.recv_from(&mut buffer) {
+            msgs.push(ReplicaMsg::from_bytes(&buffer[..len]).expect("Valid replica message"));
+        }
+        msgs
+    }
+
+    #[test]
+    fn payments_are_prepared_once() {
+        let mut agent = Agent::new(
+            "test-dedup".to_string(),
+            &[any_addr()],
+            &[any_addr()],
+            0,
+            0.5,
+        );
+        let vote = agent.prepare("PAY-001", 121);
+        for _ in 0..10 {
+            assert_eq!(agent.prepare("PAY-001", 121), vote);
+        }
+    }
+
+    #[test]
+    fn commits_are_never_undone() {
+        let mut agent = Agent::new(
+            "test-commit".to_string(),
+            &[any_addr()],
+            &[any_addr()],
+            0,
+            1.0,
+        );
+        assert_eq!(agent.prepare("PAY-001", 121), PAYMENT_OK);
+        assert_eq!(agent.commit("PAY-001"), ACK);
+        assert_eq!(agent.abort("PAY-001"), ACK);
+        assert_eq!(agent.journal["PAY-001"], (COMMIT, PAYMENT_OK));
+        assert_eq!(agent.prepare("PAY-001", 121), PAYMENT_OK);
+        assert_eq!(agent.journal["PAY-001"], (COMMIT, PAYMENT_OK));
+
+        assert_eq!(agent.abort("PAY-002"), ACK);
+        assert_eq!(agent.prepare("PAY-002", 152), PAYMENT_OK);
+        assert_eq!(agent.journal["PAY-002"], (PREPARE, PAYMENT_OK));
+    }
+
+    #[test]
+    fn backups_apply_changes_in_order() {
+        let (primary, primary_addr) = replica_socket();
+        let addrs = [primary_addr, any_addr()];
+        let mut backup = Agent::new("test-backup".to_string(), &addrs, &addrs, 1, 1.0);
+        let mut apply = |payment_id: &str, seq: u64| {
+            backup.apply(&msg(REPLICA_STATE, 0, payment_id, seq), primary_addr);
+            let acks = received(&primary);
+            assert_eq!(acks.len(), 1);
+            assert_eq!(acks[0].kind, REPLICA_ACK);
+            acks[0].seq
+        };
+        assert_eq!(apply("PAY-001", 1), 1);
+        // The third change is dropped until the second one arrives
+        assert_eq!(apply("PAY-003", 3), 1);
+        assert_eq!(apply("PAY-002", 2), 2);
+        assert_eq!(apply("PAY-003", 3), 3);
+        assert_eq!(apply("PAY-002", 2), 3);
+        assert_eq!(backup.journal.len(), 3);
+    }
+
+    #[test]
+    fn unacknowledged_changes_are_sent_again() {
+        let (backup, backup_addr) = replica_socket();
+        let addrs = [any_addr(), backup_addr];
+        let mut primary = Agent::new("test-primary".to_string(), &addrs, &addrs, 0, 1.0);
+        let primary_addr = primary.replica_socket.local_addr().expect("Bound socket");
+
+        // The backup doesn't acknowledge the change, so the primary sends it
+        // again until it gives up waiting for it
+        assert_eq!(primary.prepare("PAY-001", 121), PAYMENT_OK);
+        assert!(primary.lagging[0]);
+        let changes = received(&backup);
+        assert!(changes.len() > 1);
+        assert!(changes
+            .iter()
+            .all(|change| change.kind == REPLICA_STATE && change.seq == 1));
+
+        primary.heartbeat();
+        let kinds: Vec<(u8, u64)> = received(&backup)
+            .iter()
+            .map(|msg| (msg.kind, msg.seq))
+            .collect();
+        assert_eq!(kinds, vec![(REPLICA_STATE, 1), (REPLICA_HEARTBEAT, 1)]);
+
+        let ack = ReplicaMsg::to_bytes(&msg(REPLICA_ACK, 1, "", 1));
+        backup.send_to(&ack, primary_addr).expect("Ack sent");
+        primary.heartbeat();
+        assert!(!primary.lagging[0]);
+        assert!(primary.unacked.is_empty());
+        let kinds: Vec<u8> = received(&backup).iter().map(|msg| msg.kind).collect();
+        assert_eq!(kinds, vec![REPLICA_HEARTBEAT]);
+    }
+
+    /// Answers the first poll a replica gets with a message of the given kind
+    fn answer_poll(socket: UdpSocket, kind: u8, seq: u64) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let mut buffer = [0; REPLICA_MSG_MAX];
+            let (len, from) = socket.recv_from(&mut buffer).expect("Poll received");
+            let poll = ReplicaMsg::from_bytes(&buffer[..len]).expect("Valid replica message");
+            assert_eq!(poll.kind, REPLICA_POLL);
+            let answer = ReplicaMsg::to_bytes(&msg(kind, 2, "", seq));
+            socket.send_to(&answer, from).expect("Answer sent");
+        })
+    }
+
+    #[test]
+    fn backups_only_take_over_if_no_replica_is_ahead() {
+        let (_primary, primary_addr) = replica_socket();
+        let (other, other_addr) = replica_socket();
+        let addrs = [primary_addr, any_addr(), other_addr];
+        let mut backup = Agent::new("test-poll".to_string(), &addrs, &addrs, 1, 1.0);
+        backup.apply(&msg(REPLICA_STATE, 0, "PAY-001", 1), primary_addr);
+
+        let answer = answer_poll(other.try_clone().expect("Socket cloned"), REPLICA_ACK, 2);
+        assert_eq!(backup.poll_replicas(), Some(other_addr));
+        answer.join().expect("Poll answered");
+
+        // A replica acting as primary answers with a heartbeat, whatever its change
+        let answer = answer_poll(
+            other.try_clone().expect("Socket cloned"),
+            REPLICA_HEARTBEAT,
+            0,
+        );
+        assert_eq!(backup.poll_replicas(), Some(other_addr));
+        answer.join().expect("Poll answered");
+
+        let answer = answer_poll(other, REPLICA_ACK, 1);
+        assert_eq!(backup.poll_replicas(), None);
+        answer.join().expect("Poll answered");
+    }
+
+    #[test]
+    fn the_whole_journal_is_sent_on_take_over() {
+        let (_primary, primary_addr) = replica_socket();
+        let (next, next_addr) = replica_socket();
+        let addrs = [primary_addr, any_addr(), next_addr];
+        let mut backup = Agent::new("test-take-over".to_string(), &addrs, &addrs, 1, 1.0);
+        backup.apply(&msg(REPLICA_STATE, 0, "PAY-001", 1), primary_addr);
+        backup.apply(&msg(REPLICA_STATE, 0, "PAY-002", 2), primary_addr);
+
+        backup.take_over();
+        let mut changes: Vec<(String, u64)> = received(&next)
+            .into_iter()
+            .filter(|msg| msg.kind == REPLICA_STATE)
+            .map(|msg| (msg.payment_id, msg.seq))
+            .collect();
+        changes.dedup();
+        assert_eq!(
+            changes,
+            vec![("PAY-001".to_string(), 3), ("PAY-002".to_string(), 4)]
+        );
+    }
 }
@@ -7,41 +7,97 @@
 //! Each agent will be listening on the configured TCP port, and will log and
 //! return the transaction states.
 //!
+//! Every message names the payment by the id it has in the payments file, and
+//! each agent keeps a journal of the payments it handled by that id. A payment
+//! that is prepared again while it is still prepared or already committed gets
+//! the same vote without being charged again, and an abort doesn't undo a
+//! commit, so a payment that reaches an agent twice is only charged once.
+//!
+//! An agent can have backups: the first port is used by the primary and the
+//! rest by its backups. The primary sends every transaction state change and a
+//! periodic heartbeat to its backups via UDP (on the same port number). Changes
+//! are numbered, and each backup acknowledges the last one it applied in order.
+//! The primary only answers the coordinator once its backups acknowledged the
+//! change, so a backup that takes over keeps the journal's promise. A backup
+//! that doesn't acknowledge it within half a second is lagging, and it isn't
+//! waited for until it catches up with the missed changes, which the primary
+//! sends again along with its heartbeats. If a backup stops hearing from every
+//! replica before it, it polls the other replicas for their last change, and
+//! only takes over if none of them is alive before it or more up to date. It
+//! then sends its whole journal to its own backups and starts listening on its
+//! own port, which the coordinator will try next.
+//!
 //! The agents.yaml file is defined as a list of items like
 //! ```yaml
 //! - name: "bank" // the name of the agent
 //!   successrate: 0.9 // the rate on which they accept payments
-//!   port: 1024 // the port to listen
+//!   ports: [1024, 1034] // the port of the primary, followed by the ports of its backups
+//! ```
+//!
+//! A single `port: 1024` can be used instead of `ports` for an agent without backups.
+//! Those ports are on localhost. To run agents on other hosts, use a list of
+//! host:port `addrs` instead, where hostnames are resolved at startup, and
+//! optionally the `binds` addresses each replica binds to:
+//! ```yaml
+//! - name: "bank"
+//!   successrate: 0.9
+//!   addrs: ["bank.lab:1024", "bank-backup.lab:1034"] // the addresses the others use
+//!   binds: ["0.0.0.0:1024", "0.0.0.0:1034"] // the addresses each replica binds to
 //! ```
+//!
+//! By default every replica of every agent runs in this process. Use
+//! `cargo run --bin agents -- [--agent <name>] [--replica <index>]` to only run
+//! some of them, e.g. a single replica on each host.
+//!
+//! Typing the number of an agent kills its current primary.
 
 #![forbid(unsafe_code)]
 #![allow(dead_code)]
 mod agent;
+mod agents_args;
 mod communication;
+mod frame_error;
+mod frame_reader;
 pub mod logger;
+mod replica_msg;
 mod utils;
 use agent::Agent;
-use communication::{DataMsg, DataMsgBytes, ABORT, COMMIT, FINISH, PREPARE};
+use agents_args::AgentsArgs;
+use communication::{DataMsg, ABORT, COMMIT, FINISH, PREPARE, STALE_EPOCH};
+use replica_msg::{
+    ReplicaMsg, REPLICA_FINISH, REPLICA_HEARTBEAT, REPLICA_MSG_MAX, REPLICA_POLL, REPLICA_STATE,
+};
 use std::io::{self, BufRead, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
-use std::{
-    io::{BufReader, Read},
-    net::{SocketAddr, TcpListener},
-    thread,
-};
-use utils::{agent_get_name, agent_get_port, agent_get_success_rate, get_agents};
+use std::time::{Duration, Instant};
+use std::{env, io::BufReader, net::TcpListener, thread};
+use utils::{agent_get_addrs, agent_get_binds, agent_get_name, agent_get_success_rate, get_agents};
 
-/// Starts the agent killer in a new thread, killing agents via keyboard input
-fn psycho_agent_killer(is_agent_alive: Vec<Arc<AtomicBool>>) {
+/// Interval between heartbeats sent by a primary to its backups
+const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(300);
+/// Time the agent waits for the coordinator to send a whole message
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+/// Time a backup waits without hearing from the replicas before it, multiplied
+/// by its replica index so that backups take over one at a time
+const BACKUP_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Starts the agent killer in a new thread, killing agents via keyboard input.
+/// Each agent has a flag for every replica, and the first one still alive is
+/// the one acting as primary.
+fn psycho_agent_killer(is_agent_alive: Vec<Vec<Arc<AtomicBool>>>) {
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
         match line {
             Ok(line) => match line.trim().parse::<usize>() {
                 Ok(number) => {
                     if (0..is_agent_alive.len()).contains(&number) {
-                        is_agent_alive[number].store(false, Ordering::SeqCst);
+                        if let Some(primary) = is_agent_alive[number]
+                            .iter()
+                            .find(|is_alive| is_alive.load(Ordering::SeqCst))
+                        {
+                            primary.store(false, Ordering::SeqCst);
+                        }
                     }
                 }
                 Err(_) => continue,
@@ -51,23 +107,109 @@ fn psycho_agent_killer(is_agent_alive: Vec<Arc<AtomicBool>>) {
     }
 }
 
+/// Waits as a backup, applying the state changes sent by the primary.
+/// Returns true if the backup has to take over as primary, because the replicas
+/// before it stopped sending messages and no other replica is more up to date,
+/// and false if it has to stop.
+fn wait_as_backup(agent: &mut Agent, is_alive: &Arc<AtomicBool>) -> bool {
+    agent
+        .logger
+        .info(format!("Started as backup on {}", agent.bind));
+    agent
+        .replica_socket
+        .set_read_timeout(Some(HEARTBEAT_INTERVAL))
+        .expect("Unable to set read timeout");
+
+    let timeout = BACKUP_TIMEOUT * agent.replica as u32;
+    let mut last_heard = Instant::now();
+    let mut deferred_to = None;
+    while is_alive.load(Ordering::SeqCst) {
+        let mut buffer = [0; REPLICA_MSG_MAX];
+        if let Ok((len, from)) = agent.replica_socket.recv_from(&mut buffer) {
+            let msg = match ReplicaMsg::from_bytes(&buffer[..len]) {
+                Ok(msg) => msg,
+                Err(err) => {
+                    agent
+                        .logger
+                        .info(format!("Discarded replica message from {}: {}", from, err));
+                    continue;
+                }
+            };
+            if msg.kind == REPLICA_POLL {
+                agent.answer_poll(from);
+                continue;
+            }
+            if msg.sender as usize >= agent.replica {
+                continue;
+            }
+            last_heard = Instant::now();
+            agent.apply_epoch(msg.epoch);
+            match msg.kind {
+                REPLICA_STATE | REPLICA_HEARTBEAT => agent.apply(&msg, from),
+                REPLICA_FINISH => {
+                    agent.finish();
+                    agent.logger.info("Stop".to_string());
+                    return false;
+                }
+                _ => {}
+            }
+        } else if last_heard.elapsed() > timeout {
+            let ahead = agent.poll_replicas();
+            agent
+                .replica_socket
+                .set_read_timeout(Some(HEARTBEAT_INTERVAL))
+                .expect("Unable to set read timeout");
+            match ahead {
+                Some(addr) => {
+                    if deferred_to != Some(addr) {
+                        agent.logger.info(format!(
+                            "Primary is down, but {} is more up to date, not taking over",
+                            addr
+                        ));
+                        deferred_to = Some(addr);
+                    }
+                    last_heard = Instant::now();
+                }
+                None => {
+                    agent
+                        .logger
+                        .info("Primary is down, taking over".to_string());
+                    agent.take_over();
+                    return true;
+                }
+            }
+        }
+    }
+    agent.logger.info("Got killed".to_string());
+    false
+}
+
 /// Constantly listens to TCP connections
 /// Handles different 2-phase transaction messages like PREPARE and COMMIT
 /// Stops listening on a F
 fn create_listener(mut agent: Agent, is_alive: Arc<AtomicBool>) {
-    let addr = SocketAddr::from(([127, 0, 0, 1], agent.port));
-    let listener = TcpListener::bind(addr)
-        .unwrap_or_else(|_| panic!("listener on port {} failed", agent.port));
+    if agent.replica > 0 && !wait_as_backup(&mut agent, &is_alive) {
+        return;
+    }
+
+    let listener = TcpListener::bind(agent.bind)
+        .unwrap_or_else(|_| panic!("listener on {} failed", agent.bind));
     listener
         .set_nonblocking(true)
         .expect("Cannot set non-blocking");
 
     agent.logger.info(format!(
-        "Started on port {} with sucess rate {}",
-        agent.port, agent.success_rate
+        "Started on {} with sucess rate {}",
+        agent.bind, agent.success_rate
     ));
 
+    let mut last_heartbeat = Instant::now();
+    agent.heartbeat();
     for stream in listener.incoming() {
+        if last_heartbeat.elapsed() > HEARTBEAT_INTERVAL && is_alive.load(Ordering::SeqCst) {
+            agent.heartbeat();
+            last_heartbeat = Instant::now();
+        }
         let mut stream = match stream {
             Ok(s) => s,
             Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
@@ -75,54 +217,80 @@ fn create_listener(mut agent: Agent, is_alive: Arc<AtomicBool>) {
                     agent.logger.info("Got killed".to_string());
                     break;
                 } else {
-                    thread::sleep(Duration::from_millis(300));
+                    thread::sleep(HEARTBEAT_INTERVAL);
                     continue;
                 }
             }
-            Err(e) => panic!("accept failed: {}", e),
+            Err(e) => {
+                agent
+                    .logger
+                    .info(format!("Couldn't accept connection: {}", e));
+                continue;
+            }
         };
 
-        let mut reader = BufReader::new(stream.try_clone().expect("Couldn't clone stream"));
-
-        let mut buffer: DataMsgBytes = Default::default();
-
-        reader
-            .read_exact(&mut buffer)
-            .expect("Couldn't read from stream");
-
-        let data_msg = DataMsg::from_bytes(buffer);
+        // A coordinator that dies halfway through a message doesn't block the agent
+        let data_msg = stream
+            .set_nonblocking(false)
+            .and_then(|_| stream.set_read_timeout(Some(READ_TIMEOUT)))
+            .and_then(|_| stream.try_clone())
+            .and_then(|clone| DataMsg::read_from(&mut BufReader::new(clone)));
+        let data_msg = match data_msg {
+            Ok(data_msg) => data_msg,
+            Err(err) => {
+                agent.logger.info(format!("Couldn't read message: {}", err));
+                continue;
+            }
+        };
 
-        let result = match data_msg.opcode {
-            PREPARE => agent.prepare(data_msg.transaction_id, data_msg.data),
-            COMMIT => agent.commit(data_msg.transaction_id),
-            ABORT => agent.abort(data_msg.transaction_id),
-            FINISH => agent.finish(),
-            _ => panic!("Unknown opcode"),
+        let result = match agent.check_epoch(&data_msg.payment_id, data_msg.epoch) {
+            Some(stale) => stale,
+            None => match data_msg.opcode {
+                PREPARE => agent.prepare(&data_msg.payment_id, data_msg.data),
+                COMMIT => agent.commit(&data_msg.payment_id),
+                ABORT => agent.abort(&data_msg.payment_id),
+                FINISH => agent.finish(),
+                opcode => {
+                    agent.logger.info(format!(
+                        "Transaction {} | Unknown opcode {}",
+                        data_msg.payment_id, opcode
+                    ));
+                    continue;
+                }
+            },
         };
 
-        stream
-            .write_all(&[result])
-            .expect("Couldn't write to stream");
+        if let Err(err) = stream.write_all(&[result]) {
+            agent.logger.info(format!(
+                "Transaction {} | Couldn't answer: {}",
+                data_msg.payment_id, err
+            ));
+        }
 
-        if data_msg.opcode == FINISH {
+        if data_msg.opcode == FINISH && result != STALE_EPOCH {
             agent.logger.info("Stop".to_string());
             break;
         };
 
-        stream
-            .shutdown(std::net::Shutdown::Both)
-            .expect("Couldn't shutdown stream");
+        let _ignore = stream.shutdown(std::net::Shutdown::Both);
     }
 }
 
 /// Main function. Starts the agents from the .yaml configuration file
 /// and the agent killer. Finishes when all the agents are killed.
 fn main() {
+    let args = AgentsArgs::parse(env::args().skip(1));
     let agents = get_agents();
 
+    // Replicas that run in other processes are never alive in this one, so the
+    // killer only picks the ones started here
     let mut is_agent_alive = vec![];
-    for _ in 0..agents.len() {
-        is_agent_alive.push(Arc::new(AtomicBool::new(true)));
+    for agent in agents.iter() {
+        let name = agent_get_name(agent);
+        let replicas = (0..agent_get_addrs(agent).len())
+            .map(|replica| Arc::new(AtomicBool::new(args.runs(&name, replica))))
+            .collect::<Vec<_>>();
+        is_agent_alive.push(replicas);
     }
 
     let is_agent_alive_clone = is_agent_alive.clone();
@@ -134,21 +302,30 @@ fn main() {
 
     let mut agents_threads = vec![];
     for (i, agent) in agents.iter().enumerate() {
-        let agent = Agent::new(
-            agent_get_name(agent),
-            agent_get_port(agent),
-            agent_get_success_rate(agent),
-        );
-
-        let is_alive = is_agent_alive_clone[i].clone();
-        agents_threads.push(
-            thread::Builder::new()
-                .name(agent.name.clone())
-                .spawn(move || {
-                    create_listener(agent, is_alive);
-                })
-                .expect("agent thread creation failed"),
-        )
+        let addrs = agent_get_addrs(agent);
+        let binds = agent_get_binds(agent);
+        for (replica, is_alive) in is_agent_alive_clone[i].iter().enumerate() {
+            if !args.runs(&agent_get_name(agent), replica) {
+                continue;
+            }
+            let agent = Agent::new(
+                agent_get_name(agent),
+                &addrs,
+                &binds,
+                replica,
+                agent_get_success_rate(agent),
+            );
+
+            let is_alive = is_alive.clone();
+            agents_threads.push(
+                thread::Builder::new()
+                    .name(format!("{}-{}", agent.name, replica))
+                    .spawn(move || {
+                        create_listener(agent, is_alive);
+                    })
+                    .expect("agent thread creation failed"),
+            )
+        }
     }
 
     for thread in agents_threads {
@@ -0,0 +1,46 @@
+//! AgentsArgs struct
+//!
+//! Command line arguments of the agents program.
+
+/// AgentsArgs struct
+#[derive(Debug, Clone)]
+pub struct AgentsArgs {
+    /// Name of the only agent to run in this process, or None to run every agent
+    pub agent: Option<String>,
+    /// Index of the only replica to run of each agent, or None to run all of them
+    pub replica: Option<usize>,
+}
+
+impl AgentsArgs {
+    /// Parses the arguments of the program, where `args` doesn't include the
+    /// program name. Accepts `--agent <name>` and `--replica <index>`.
+    pub fn parse(args: impl Iterator<Item = String>) -> AgentsArgs {
+        let mut parsed = AgentsArgs {
+            agent: None,
+            replica: None,
+        };
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--agent" => parsed.agent = Some(args.next().expect("--agent needs a value")),
+                "--replica" => {
+                    parsed.replica = Some(
+                        args.next()
+                            .expect("--replica needs a value")
+                            .parse()
+                            .expect("--replica must be an unsigned integer"),
+                    )
+                }
+                _ => panic!("Unknown argument {}", arg),
+            }
+        }
+        parsed
+    }
+
+    /// Whether the given replica of the agent runs in this process
+    pub fn runs(&self, name: &str, replica: usize) -> bool {
+        self.agent.as_deref().is_none_or(|agent| agent == name)
+            && self.replica.is_none_or(|only| only == replica)
+    }
+}
@@ -1,73 +1,354 @@
 //! AlGlobo.com - Process payments
 //! ---
-//! This program sets up `N_NODES` which will process all of the payments from
-//! the payments csv file. It will send the request to all the designated agents,
-//! using the configuration in the agents.yaml file.
-//!
-//! If the leader node is killed, another one is elected using the ring election
-//! agorithm.
+//! This program sets up every node of the cluster.yaml file, which will process
+//! all of the payments from the payments file. The leader sends each payment to
+//! the agents of the agents.yaml file with a two phase commit, and if it's
+//! killed another node is elected with the ring, bully or raft algorithm, as set
+//! in the cluster.yaml file.
 //!
 //! Start the program with `cargo run --bin alglobo <payments_file>.csv` (or
-//! default to a csv if not provided)
+//! default to `src/prices.csv` if not provided). The options are:
+//! - `--format <csv|jsonl>`: the format of the payments, picked by the extension
+//!   of the file if not given.
+//! - `--cluster <cluster.yaml>`: the cluster config, `src/cluster.yaml` if not
+//!   given.
+//! - `--node-id <id>`: runs only that node, so that it can crash or be killed
+//!   on its own. By default every node runs as a thread of the same process.
+//!   Every process must use the same cluster config.
+//! - `--listen <addr>`: receives payments on a TCP address, or on a Unix socket
+//!   with `unix:<path>`, and stores them in the spool directory. Each connection
+//!   sends a header and then a payment per line, and gets `OK <id>` or
+//!   `ERR <reason>` for each one. Only one process of the cluster should listen.
+//!
+//! A CSV payments file starts with a header with an `id` column and a column
+//! per agent with the price to charge it (for example `id,bank,airline,hotel`),
+//! leaving empty the agents a payment doesn't involve. A `.jsonl` file has an
+//! object per line with the `id`, the `amounts` by agent name, and optionally
+//! a `currency` and any `metadata` (see `src/prices.jsonl`). Instead of a file,
+//! the program can get a spool directory, in which case the cluster keeps
+//! running and processes the payments of its `.csv` and `.jsonl` files, in
+//! name order, as they show up. Each payment id is only processed once.
+//!
+//! Every line is validated before processing any payment. The program prints
+//! the line number and reason of each invalid line, and then refuses to process
+//! the file, or skips the whole file if it's part of a spool directory. If the
+//! cluster config sets `invalid_payments: "quarantine"`, it skips only those
+//! lines instead, and appends them to `src/prices-quarantine.csv`.
+//!
+//! The leader writes the aborted payments to `src/prices-retry.csv`, and an
+//! outcome ledger to `src/prices-outcomes.csv`, with a row per decided payment
+//! (both in `.jsonl` for JSON Lines payments). `cargo run --bin reconcile`
+//! checks the outcome ledger against the agents. With raft, each node keeps its
+//! log in the `raft/` directory, which must be deleted before processing a new
+//! payments file when running one process per node.
+//!
+//! The cluster.yaml file lists the size of the cluster and every node with the
+//! addresses of its control and data sockets:
+//! ```yaml
+//! size: 5 // the amount of nodes
+//! election: "ring" // the leader election algorithm: "ring", "bully" or "raft"
+//! quorum: 3 // nodes that must store each state update, a majority if not set
+//! degraded_quorum: false // whether to only wait for the reachable nodes when fewer than the quorum are
+//! heartbeat_ms: 500 // interval between the heartbeats of the leader
+//! phi_threshold: 8.0 // suspicion level above which the leader is considered dead
+//! nodes:
+//!   - id: 0 // the id of the node, which decides its place in the ring
+//!     ctrl: "127.0.0.1:1100" // the address for leader election messages
+//!     data: "127.0.0.1:1200" // the address for transaction results
+//!     ctrl_bind: "0.0.0.0:1100" // optional, the address the control socket binds to
+//!     data_bind: "0.0.0.0:1200" // optional, the address the data socket binds to
+//!     priority: 0 // optional, the priority to lead among equally up to date nodes
+//! ```
+//!
+//! While the program runs, typing:
+//! - `<id>` kills that node.
+//! - `r <id>` restarts a killed node, when every node runs in the same process.
+//! - `s <id>` prints the coordinator state of a node.
+//! - `d <leader id> [<target id>]` makes the leader step down after the
+//!   transaction in flight, handing over to the target or to the most up to
+//!   date replica.
+//!
+//! When built with `--features crash-points`, setting `ALGLOBO_CRASH_AT` to
+//! `before-decision`, `before-phase-two`, `during-phase-two` or
+//! `after-phase-two` makes the first leader crash at that point of the commit
+//! of a transaction. `cargo test --features crash-points` runs the payments
+//! with a crash at each point, and checks that every agent got the same
+//! decision for every transaction.
 
 #![forbid(unsafe_code)]
 #![allow(dead_code)]
 use std::io::BufRead;
-use std::thread;
-use std::{io, net::UdpSocket};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{env, fs, io, net::UdpSocket, process};
 
+mod alglobo_args;
 mod alglobo_node;
+mod bully_election;
+mod cluster_config;
 mod communication;
+mod control_msg;
+mod control_msg_error;
+mod coordinator_state;
+mod crash_point;
+mod election;
+mod frame_error;
+mod frame_reader;
+mod invalid_line;
+mod ledgers;
 pub mod logger;
+mod node_config;
+mod outcome;
+mod payment;
+mod payment_columns;
+mod payment_error;
+mod payment_intake;
+mod payments_format;
+mod phi_accrual_detector;
+mod raft_election;
+mod raft_entry;
+mod raft_state;
+mod ring_election;
+mod snapshot_parts;
+mod spool_reader;
+mod state_update;
 mod utils;
+mod validation_report;
+
+use alglobo_args::AlgloboArgs;
+use alglobo_node::{
+    AlgloboNode, MAX_DATAGRAM, MSG_KILL, MSG_STATUS, MSG_STEPDOWN, QUARANTINE_FILE,
+};
+use cluster_config::ClusterConfig;
+use coordinator_state::CoordinatorState;
+use crash_point::{CRASH_AT_VAR, CRASH_POINTS};
+use payment::Payment;
+use payment_intake::PaymentIntake;
+use payments_format::PaymentsFormat;
+use raft_state::RAFT_DIR;
+use utils::get_agents_names;
 
-use alglobo_node::{id_to_ctrladdr, AlgloboNode, MSG_KILL, N_NODES};
+/// Time to wait for a node to answer a status query
+const STATUS_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Node threads of the process, shared so that restarted nodes are also joined
+type NodeThreads = Arc<Mutex<Vec<JoinHandle<()>>>>;
+
+/// Starts a node of the cluster on a new thread
+fn spawn_node(
+    id: usize,
+    cluster: Arc<ClusterConfig>,
+    payments_file: String,
+    format: PaymentsFormat,
+) -> JoinHandle<()> {
+    thread::Builder::new()
+        .name(format!("Alglobo Node {}", id))
+        .spawn(move || {
+            let mut node = AlgloboNode::new(id, cluster, payments_file, format);
+            node.loop_node()
+        })
+        .expect("alglobo node thread creation failed")
+}
 
 /// Starts the thread designated to kill each node via keyboard input.
-fn psycho_node_killer() {
+/// If the nodes run as threads of this process, it can also restart them.
+fn psycho_node_killer(
+    cluster: Arc<ClusterConfig>,
+    payments_file: String,
+    format: PaymentsFormat,
+    node_threads: Option<NodeThreads>,
+) {
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
-        match line {
-            Ok(line) => match line.trim().parse::<usize>() {
-                Ok(number) => {
-                    if (0..N_NODES).contains(&number) {
-                        let addr = id_to_ctrladdr(number);
-                        let socket =
-                            UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to address");
-                        socket
-                            .send_to(&[MSG_KILL], addr)
-                            .expect("Couldn't send KILL message");
-                    }
-                }
-                Err(_) => continue,
-            },
+        let line = match line {
+            Ok(line) => line,
             Err(_) => panic!("Failed to read stdin"),
+        };
+        let line = line.trim();
+        if let Some(args) = line.strip_prefix('d') {
+            step_down(&cluster, args);
+            continue;
+        }
+        let (command, number) = match line.strip_prefix(|c| c == 'r' || c == 's') {
+            Some(number) => (line.chars().next(), number.trim().parse::<usize>()),
+            None => (None, line.parse::<usize>()),
+        };
+        let number = match number {
+            Ok(number) if cluster.contains(number) => number,
+            _ => continue,
+        };
+
+        if command == Some('s') {
+            print_status(&cluster, number);
+        } else if command.is_none() {
+            let addr = cluster.ctrl_addr(number);
+            let socket = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to address");
+            socket
+                .send_to(&AlgloboNode::ids_to_msg(MSG_KILL, &[]), addr)
+                .expect("Couldn't send KILL message");
+        } else if let Some(node_threads) = &node_threads {
+            node_threads
+                .lock()
+                .expect("Unable to lock node threads")
+                .push(spawn_node(
+                    number,
+                    cluster.clone(),
+                    payments_file.clone(),
+                    format,
+                ));
         }
     }
 }
 
-/// Starts the main process, starting the node killer and each node process
-fn main() {
+/// Asks the leader to step down, handing over to the given node or else to
+/// the best replica. The arguments are the id of the leader and optionally
+/// the id of the node to hand over to.
+fn step_down(cluster: &ClusterConfig, args: &str) {
+    let ids: Vec<usize> = args
+        .split_whitespace()
+        .filter_map(|id| id.parse().ok())
+        .filter(|id| cluster.contains(*id))
+        .collect();
+    if let Some((leader, target)) = ids.split_first() {
+        let socket = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to address");
+        socket
+            .send_to(
+                &AlgloboNode::ids_to_msg(MSG_STEPDOWN, target),
+                cluster.ctrl_addr(*leader),
+            )
+            .expect("Couldn't send STEPDOWN message");
+    }
+}
+
+/// Asks a node for its coordinator state and prints it
+fn print_status(cluster: &ClusterConfig, id: usize) {
+    let socket = UdpSocket::bind("0.0.0.0:0").expect("couldn't bind to address");
+    socket
+        .set_read_timeout(Some(STATUS_TIMEOUT))
+        .expect("Couldn't set read timeout");
+    socket
+        .send_to(
+            &AlgloboNode::ids_to_msg(MSG_STATUS, &[]),
+            cluster.ctrl_addr(id),
+        )
+        .expect("Couldn't send STATUS message");
+    let mut buf = vec![0; MAX_DATAGRAM];
+    match socket.recv_from(&mut buf) {
+        Ok((size, _)) => match CoordinatorState::from_bytes(&buf[..size]) {
+            Ok(state) => println!("Node {}: {}", id, state),
+            Err(err) => println!("Node {} sent an invalid state: {}", id, err),
+        },
+        Err(_) => println!("Node {} didn't answer", id),
+    }
+}
+
+/// Starts the psycho killer loop on a new thread
+fn start_psycho_killer(
+    args: &AlgloboArgs,
+    cluster: &Arc<ClusterConfig>,
+    node_threads: Option<NodeThreads>,
+) {
+    let cluster = cluster.clone();
+    let payments_file = args.payments_file.clone();
+    let format = args.format();
     thread::Builder::new()
         .name("psycho killer".to_string())
-        .spawn(psycho_node_killer)
+        .spawn(move || psycho_node_killer(cluster, payments_file, format, node_threads))
         .expect("Couldn't create psycho killer loop");
+}
 
-    let mut node_threads = vec![];
-
-    for id in 0..N_NODES {
-        node_threads.push(
-            thread::Builder::new()
-                .name(format!("Alglobo Node {}", id))
-                .spawn(move || {
-                    let mut node = AlgloboNode::new(id);
-                    node.loop_node()
-                })
-                .expect("alglobo node thread creation failed"),
-        );
+/// Starts the main process, starting the node killer and either every node
+/// of the cluster or only the one given by `--node-id`
+fn main() {
+    let args = AlgloboArgs::parse(env::args().skip(1));
+    let cluster = Arc::new(ClusterConfig::from_file(&args.cluster_file));
+    if let Ok(point) = env::var(CRASH_AT_VAR) {
+        if !cfg!(feature = "crash-points") {
+            panic!(
+                "{} needs a build with the crash-points feature",
+                CRASH_AT_VAR
+            );
+        }
+        if !CRASH_POINTS.contains(&point.as_str()) {
+            panic!(
+                "Unknown crash point {}, use one of {:?}",
+                point, CRASH_POINTS
+            );
+        }
+    }
+    // The payments file is checked before any node starts, so a bad file
+    // stops the program instead of only the leader. The files of a spool are
+    // skipped instead, as the rest of them can still be processed
+    let report = Payment::read(&args.payments_file, args.format(), &get_agents_names());
+    if !report.is_valid() {
+        match (
+            cluster.quarantine(),
+            Path::new(&args.payments_file).is_dir(),
+        ) {
+            (true, _) => println!(
+                "Quarantined invalid payments of {} into {}: {}",
+                args.payments_file, QUARANTINE_FILE, report
+            ),
+            (false, true) => println!(
+                "Skipping the files of {} with invalid payments: {}",
+                args.payments_file, report
+            ),
+            (false, false) => {
+                eprintln!("Rejected payments {}: {}", args.payments_file, report);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(addr) = &args.listen {
+        if !Path::new(&args.payments_file).is_dir() {
+            panic!("--listen needs a spool directory instead of a payments file");
+        }
+        PaymentIntake::new(&args.payments_file, get_agents_names()).listen(addr);
     }
 
-    for thread in node_threads {
-        thread.join().expect("alglobo node thread join failed");
+    if let Some(id) = args.node_id {
+        if !cluster.contains(id) {
+            panic!("Node {} is not part of the cluster", id);
+        }
+        start_psycho_killer(&args, &cluster, None);
+        let format = args.format();
+        let mut node = AlgloboNode::new(id, cluster, args.payments_file, format);
+        node.loop_node();
+        return;
+    }
+
+    // Every node starts from scratch when the whole cluster runs in this process
+    if cluster.election() == "raft" {
+        let _ignore = fs::remove_dir_all(RAFT_DIR);
+    }
+
+    let node_threads: NodeThreads = Arc::new(Mutex::new(vec![]));
+    start_psycho_killer(&args, &cluster, Some(node_threads.clone()));
+
+    for id in cluster.ids() {
+        node_threads
+            .lock()
+            .expect("Unable to lock node threads")
+            .push(spawn_node(
+                id,
+                cluster.clone(),
+                args.payments_file.clone(),
+                args.format(),
+            ));
+    }
+
+    // Restarted nodes push their threads while we wait, so we keep joining
+    // until there are no threads left
+    loop {
+        let thread = node_threads
+            .lock()
+            .expect("Unable to lock node threads")
+            .pop();
+        match thread {
+            Some(thread) => thread.join().expect("alglobo node thread join failed"),
+            None => break,
+        }
     }
 }
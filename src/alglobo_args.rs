@@ -0,0 +1,79 @@
+//! AlgloboArgs struct
+//!
+//! Command line arguments of the alglobo program.
+
+use crate::cluster_config::CLUSTER_FILE;
+use crate::payments_format::PaymentsFormat;
+
+/// Default payments file, used if none is provided
+const PAYMENTS_FILE: &str = "src/prices.csv";
+
+/// AlgloboArgs struct
+#[derive(Debug, Clone)]
+pub struct AlgloboArgs {
+    /// Id of the only node to run in this process, or None to run every node of
+    /// the cluster as threads of this process
+    pub node_id: Option<usize>,
+    /// Path to the cluster config file, shared by every node
+    pub cluster_file: String,
+    /// Path to the payments file to process, or to a spool directory
+    pub payments_file: String,
+    /// TCP address, or `unix:<path>` socket, where this process receives
+    /// payments for the spool directory
+    pub listen: Option<String>,
+    /// Format of the payments file and of the ledgers, or None to pick it by
+    /// the extension of the payments file
+    pub format: Option<PaymentsFormat>,
+}
+
+impl AlgloboArgs {
+    /// Parses the arguments of the program, where `args` doesn't include the
+    /// program name. Accepts `--node-id <id>`, `--cluster <file>`,
+    /// `--listen <addr>`, `--format <csv|jsonl>` and an optional payments file
+    /// or spool directory.
+    pub fn parse(args: impl Iterator<Item = String>) -> AlgloboArgs {
+        let mut parsed = AlgloboArgs {
+            node_id: None,
+            cluster_file: CLUSTER_FILE.to_string(),
+            payments_file: PAYMENTS_FILE.to_string(),
+            listen: None,
+            format: None,
+        };
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--node-id" => {
+                    parsed.node_id = Some(
+                        args.next()
+                            .expect("--node-id needs a value")
+                            .parse()
+                            .expect("--node-id must be an unsigned integer"),
+                    )
+                }
+                "--cluster" => {
+                    parsed.cluster_file = args.next().expect("--cluster needs a value");
+                }
+                "--listen" => {
+                    parsed.listen = Some(args.next().expect("--listen needs a value"));
+                }
+                "--format" => {
+                    parsed.format = Some(PaymentsFormat::from_name(
+                        &args.next().expect("--format needs a value"),
+                    ));
+                }
+                flag if flag.starts_with("--") => panic!("Unknown flag {}", flag),
+                _ => parsed.payments_file = arg,
+            }
+        }
+        parsed
+    }
+
+    /// Format of the payments file and of the ledgers: the one given with
+    /// `--format`, or else the one of the extension of the payments file,
+    /// which is CSV for a spool directory
+    pub fn format(&self) -> PaymentsFormat {
+        self.format
+            .unwrap_or_else(|| PaymentsFormat::from_path(&self.payments_file))
+    }
+}
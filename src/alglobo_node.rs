@@ -1,7 +1,8 @@
 //! AlgloboNode struct
 //!
-//! Handles the logic of each Algobo.com node, where the leader is chosen with the ring
-//! election algorithm. The leader will send each transaction result to the replicas.
+//! Handles the logic of each Algobo.com node, where the leader is chosen with the
+//! election algorithm set in the cluster config (ring or bully). The leader will send
+//! each transaction result to the replicas.
 //!
 //! The communication between nodes is done via UDP, where each node will have two
 //! sockets. One for receiving leader election messages and the other for receiving
@@ -9,42 +10,67 @@
 //!
 //! If the replicas reach a timeout without messages from the leader, a new leader is
 //! coordinated.
+//!
+//! A node that starts (or restarts after being killed) first announces itself with a
+//! JOIN message. If there is a leader, it answers with its id and sends the node a
+//! snapshot of the coordinator state, so it becomes a replica without a new election.
 
-use std::mem::size_of;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::net::UdpSocket;
+use std::path::Path;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
-use std::time::Duration;
-
-use std::convert::TryInto;
+use std::time::{Duration, Instant};
 
-use std::env;
 use std::io::Read;
 use std::io::Write;
 use std::net::TcpStream;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread::sleep;
 
-use crate::communication::{DataMsg, ABORT, COMMIT, FINISH, PAYMENT_ERR, PAYMENT_OK, PREPARE};
+use crate::cluster_config::ClusterConfig;
+use crate::communication::{
+    DataMsg, ABORT, COMMIT, FINISH, PAYMENT_ERR, PAYMENT_OK, PREPARE, STALE_EPOCH,
+};
+use crate::control_msg::ControlMsg;
+use crate::control_msg_error::ControlMsgError;
+use crate::coordinator_state::CoordinatorState;
+use crate::crash_point::{
+    crash_at, AFTER_PHASE_TWO, BEFORE_DECISION, BEFORE_PHASE_TWO, DURING_PHASE_TWO,
+};
+use crate::election::{new_election, rank, Election};
+use crate::frame_error::FrameError;
+use crate::frame_reader::FrameReader;
 use crate::logger::Logger;
+use crate::phi_accrual_detector::PhiAccrualDetector;
 
 use std::net::SocketAddr;
 
-use crate::utils::{create_empty_csv, csv_to_prices, get_agents_ports, write_to_csv};
-
-/// Socket used for receiving leader election/coordination messages
-pub fn id_to_ctrladdr(id: usize) -> SocketAddr {
-    let port = (1100 + id) as u16;
-    SocketAddr::from(([127, 0, 0, 1], port))
-}
-/// Socket used for receiving transaction information from the leader
-pub fn id_to_dataaddr(id: usize) -> SocketAddr {
-    let port = (1200 + id) as u16;
-    SocketAddr::from(([127, 0, 0, 1], port))
+use crate::invalid_line::InvalidLine;
+use crate::ledgers::{ledger_payment, Ledgers};
+use crate::payment::Payment;
+use crate::payments_format::PaymentsFormat;
+use crate::snapshot_parts::SnapshotParts;
+use crate::spool_reader::SpoolReader;
+use crate::state_update::{StateUpdate, SOURCE, VOTES};
+use crate::utils::{
+    create_empty_csv, fingerprint, get_agents_addrs, get_agents_names, open_csv_to_append,
+    write_csv_line,
+};
+use crate::validation_report::ValidationReport;
+
+/// Connects to the current primary of an agent, trying each of its replicas
+/// in order. Only the primary listens for TCP connections, so the first
+/// replica that accepts the connection is the one to talk to.
+/// Replicas on hosts that are down may not refuse the connection, so each
+/// attempt has a timeout.
+fn connect_to_agent(agent_addrs: &[SocketAddr]) -> Option<TcpStream> {
+    agent_addrs
+        .iter()
+        .find_map(|addr| TcpStream::connect_timeout(addr, TIMEOUT / 4).ok())
 }
 
-/// The amount of nodes to launch the program with
-pub const N_NODES: usize = 5;
 /// Control message for ACKs
 pub const MSG_ACK: u8 = b'A';
 /// Control message for election messages
@@ -53,25 +79,101 @@ pub const MSG_ELECTION: u8 = b'E';
 pub const MSG_COORDINATOR: u8 = b'C';
 /// Control message for kill messages
 pub const MSG_KILL: u8 = b'K';
-/// Timeout for receiving transaction information in the replicas
+/// Control message sent by a node that (re)starts, to find the current leader
+pub const MSG_JOIN: u8 = b'J';
+/// Control message with which the leader answers a MSG_JOIN
+pub const MSG_LEADER: u8 = b'L';
+/// Control message with which a replica rejects a data message from an older epoch
+pub const MSG_STALE: u8 = b'T';
+/// Control message asking a node for its coordinator state, which it answers
+/// with the state bytes
+pub const MSG_STATUS: u8 = b'Z';
+/// Control message asking the leader to hand over its place once the transaction
+/// in flight finishes, to the given node if any or else to the best replica
+pub const MSG_STEPDOWN: u8 = b'D';
+/// Control message with which the leader hands over its place to a replica
+/// that stored its state up to the given sequence number
+pub const MSG_TAKEOVER: u8 = b'G';
+/// Control message with which a replica acknowledges a data message
+pub const MSG_LOG_ACK: u8 = b'Y';
+/// Control message with which a replica that missed data messages asks for them again
+pub const MSG_RESEND: u8 = b'Q';
+/// Data message with the new status of a transaction
+pub const LOG_UPDATE: u8 = b'U';
+/// Data message sent periodically by the leader, so replicas know it is alive
+pub const LOG_HEARTBEAT: u8 = b'H';
+/// Data message with a part of the whole coordinator state, sent to nodes that join
+pub const LOG_SNAPSHOT: u8 = b'S';
+/// Biggest part of a snapshot sent in a LOG_SNAPSHOT, so that it fits in a
+/// datagram along with its header
+const SNAPSHOT_PART: usize = 60000;
+/// Most parts a snapshot can be split in
+pub const MAX_SNAPSHOT_PARTS: usize = 1024;
+/// Timeout for the agents to answer and for control messages to be acknowledged
 pub const TIMEOUT: Duration = Duration::from_secs(5);
+/// Timeout for the replicas to acknowledge a data message before it is sent again
+pub const LOG_ACK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Longest the leader waits for the quorum to store an update before stepping
+/// down, unless `degraded_quorum` is set
+pub const QUORUM_TIMEOUT: Duration = Duration::from_secs(10);
+/// Timeout for a joining node to hear from the leader before starting an election
+pub const JOIN_TIMEOUT: Duration = Duration::from_secs(2);
+/// Interval between calls to the periodic work of the election algorithm
+const TICK: Duration = Duration::from_millis(100);
+/// Biggest possible UDP payload, used for receiving snapshots and election messages
+pub const MAX_DATAGRAM: usize = 65507;
+
+/// Attempts of the leader to hand over its place to a replica before giving up
+const HANDOVER_ATTEMPTS: usize = 3;
+/// File with the invalid lines of the payments, if they're quarantined
+pub const QUARANTINE_FILE: &str = "src/prices-quarantine.csv";
+/// Time the leader waits before looking for new payments in a spool directory
+const SPOOL_POLL: Duration = Duration::from_millis(500);
+
+/// Last sequence number acknowledged by each replica, and when
+type LogAcks = HashMap<usize, (usize, Instant)>;
 
 /// AlgloboNode struct
 pub struct AlgloboNode {
     /// The id of the node
     id: usize,
+    /// Membership of the cluster the node belongs to
+    cluster: Arc<ClusterConfig>,
+    /// Path to the payments file processed when leading
+    payments_file: String,
+    /// Format of the payments file and of the ledgers
+    format: PaymentsFormat,
+    /// Name of every agent of the agents config file
+    agents_names: Vec<String>,
+    /// Leader election algorithm
+    election: Arc<dyn Election>,
     /// The UDP socket of the node
     socket: UdpSocket,
+    /// The UDP socket for receiving transaction information from the leader
+    data_socket: UdpSocket,
     /// The id of the leader, with a lock and a condvar
     leader_id: Arc<(Mutex<Option<usize>>, Condvar)>,
     /// Flag for reliable data transfer via UDP, with a lock and a condvar
     got_ack: Arc<(Mutex<Option<usize>>, Condvar)>,
+    /// Last sequence number acknowledged by each replica in the current epoch
+    /// and when, with a lock and a condvar
+    log_acks: Arc<(Mutex<LogAcks>, Condvar)>,
+    /// Whether the cluster is degraded because fewer replicas than the quorum
+    /// are reachable, as last logged by the leader, with a lock
+    degraded: Arc<Mutex<bool>>,
     /// Stop flag to end the node's threads
     stop: Arc<AtomicBool>,
-    /// Last processed id of the transaction, with a lock
-    last_id: Arc<Mutex<usize>>,
-    /// Last processed id of the transaction, with a lock
-    last_status: Arc<Mutex<u8>>,
+    /// Replicated coordinator state, with a lock
+    state: Arc<Mutex<CoordinatorState>>,
+    /// Parts received of the last snapshot sent by the leader, with a lock
+    snapshot_parts: Arc<Mutex<SnapshotParts>>,
+    /// Failure detector for the messages of the leader, with a lock
+    detector: Arc<Mutex<PhiAccrualDetector>>,
+    /// Last time the node sent a heartbeat as leader, with a lock
+    last_heartbeat: Arc<Mutex<Instant>>,
+    /// Pending request to step down as leader, with the node to hand over to
+    /// if one was chosen, with a lock
+    stepdown: Arc<Mutex<Option<Option<usize>>>>,
     /// Logger of the node
     logger: Logger,
 }
@@ -79,17 +181,38 @@ pub struct AlgloboNode {
 // Mutexes are more explicit than atomic stuff when controlling threads!
 #[allow(clippy::mutex_atomic)]
 impl AlgloboNode {
-    /// Creates the AlgoboNode and starts the control responder thread
-    pub fn new(id: usize) -> AlgloboNode {
+    /// Creates the AlgoboNode, starts the control responder thread and joins the
+    /// cluster, looking for a new leader if there is none
+    pub fn new(
+        id: usize,
+        cluster: Arc<ClusterConfig>,
+        payments_file: String,
+        format: PaymentsFormat,
+    ) -> AlgloboNode {
+        let logger = Logger::new(format!("node-{}", id));
         let mut ret = AlgloboNode {
             id,
-            socket: UdpSocket::bind(id_to_ctrladdr(id)).expect("Unable to bind socket"),
-            leader_id: Arc::new((Mutex::new(Some(id)), Condvar::new())),
+            socket: UdpSocket::bind(cluster.ctrl_bind_addr(id)).expect("Unable to bind socket"),
+            data_socket: UdpSocket::bind(cluster.data_bind_addr(id))
+                .expect("Unable to bind socket"),
+            election: new_election(cluster.election(), id, &logger),
+            detector: Arc::new(Mutex::new(PhiAccrualDetector::new(
+                cluster.heartbeat_interval(),
+            ))),
+            cluster,
+            payments_file,
+            format,
+            agents_names: get_agents_names(),
+            leader_id: Arc::new((Mutex::new(None), Condvar::new())),
             got_ack: Arc::new((Mutex::new(None), Condvar::new())),
+            log_acks: Arc::new((Mutex::new(HashMap::new()), Condvar::new())),
+            degraded: Arc::new(Mutex::new(false)),
             stop: Arc::new(AtomicBool::new(false)),
-            last_id: Arc::new(Mutex::new(0)),
-            last_status: Arc::new(Mutex::new(0)),
-            logger: Logger::new(format!("node-{}", id)),
+            state: Arc::new(Mutex::new(CoordinatorState::default())),
+            snapshot_parts: Arc::new(Mutex::new(SnapshotParts::default())),
+            last_heartbeat: Arc::new(Mutex::new(Instant::now())),
+            stepdown: Arc::new(Mutex::new(None)),
+            logger,
         };
 
         let mut clone = ret.clone();
@@ -98,16 +221,66 @@ impl AlgloboNode {
             .spawn(move || clone.responder())
             .expect("node responder thread creation failed");
 
-        ret.find_new();
+        let clone = ret.clone();
+        thread::Builder::new()
+            .name(format!("Node{}-Ticker", id))
+            .spawn(move || clone.ticker())
+            .expect("node ticker thread creation failed");
+
+        if !ret.join() {
+            ret.find_new();
+        }
         ret
     }
 
-    /// Control responder function. Handles receiving MSG_ACK, MSG_ELECTION,
-    /// MSG_COORDINATOR and MSG_KILL messages from the control socket.
-    /// It uses the ring election algorithm.
+    /// Announces the node to the rest of the cluster with a MSG_JOIN, and waits
+    /// for the leader to answer. Returns true if there is a leader to follow.
+    fn join(&mut self) -> bool {
+        self.logger.info("Joining the cluster".to_string());
+        *self.leader_id.0.lock().expect("Unable to get lock") = None;
+
+        let msg = AlgloboNode::ids_to_msg(MSG_JOIN, &[self.id]);
+        for i in self.cluster.ids() {
+            if i != self.id {
+                let _ignore = self.socket.send_to(&msg, self.cluster.ctrl_addr(i));
+            }
+        }
+
+        let (leader_id, _timeout) = self
+            .leader_id
+            .1
+            .wait_timeout_while(
+                self.leader_id.0.lock().expect("Unable to get lock"),
+                JOIN_TIMEOUT,
+                |leader_id| leader_id.is_none(),
+            )
+            .expect("Unable to wait for condvar");
+        match *leader_id {
+            Some(leader_id) => {
+                self.detector.lock().expect("Unable to get lock").reset();
+                self.logger
+                    .info(format!("Joined the cluster with leader {}", leader_id));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Ticker function. Runs the periodic work of the election algorithm until
+    /// the node stops.
+    fn ticker(&self) {
+        while !self.stop.load(Ordering::SeqCst) {
+            self.election.tick(self);
+            sleep(TICK.min(self.cluster.heartbeat_interval()));
+        }
+    }
+
+    /// Control responder function. Handles receiving MSG_ACK, MSG_JOIN,
+    /// MSG_LEADER and MSG_KILL messages from the control socket. Any other
+    /// message is handled by the election algorithm.
     fn responder(&mut self) {
         while !self.stop.load(Ordering::SeqCst) {
-            let mut buf = [0; 1 + size_of::<usize>() + (N_NODES + 1) * size_of::<usize>()];
+            let mut buf = vec![0; MAX_DATAGRAM];
             self.socket
                 .set_read_timeout(Some(TIMEOUT / 4))
                 .expect("Unable to set read timeout");
@@ -116,8 +289,16 @@ impl AlgloboNode {
             if res.is_err() {
                 continue;
             }
-            let (_size, from) = res.expect("Unable to get size and from");
-            let (msg_type, mut ids) = self.parse_message(&buf);
+            let (size, from) = res.expect("Unable to get size and from");
+            let (msg_type, ids) =
+                match ControlMsg::from_bytes(&buf[..size]).and_then(|msg| self.check_fields(msg)) {
+                    Ok(msg) => (msg.kind, msg.fields),
+                    Err(err) => {
+                        self.logger
+                            .info(format!("Discarded control message from {}: {}", from, err));
+                        continue;
+                    }
+                };
 
             match msg_type {
                 MSG_ACK => {
@@ -125,174 +306,388 @@ impl AlgloboNode {
                     *self.got_ack.0.lock().expect("Unable to get stop lock") = Some(ids[0]);
                     self.got_ack.1.notify_all();
                 }
-                MSG_ELECTION => {
+                MSG_JOIN => {
+                    self.logger
+                        .trace(format!("Got JOIN from {} with ids {:?}", from, ids));
+                    let is_leader =
+                        *self.leader_id.0.lock().expect("Unable to get lock") == Some(self.id);
+                    if is_leader && self.cluster.contains(ids[0]) {
+                        self.election.catch_up(self, ids[0]);
+                        self.send_to(&AlgloboNode::ids_to_msg(MSG_LEADER, &[self.id]), from);
+                    }
+                }
+                MSG_LEADER => {
+                    self.logger
+                        .trace(format!("Got LEADER from {} with ids {:?}", from, ids));
+                    let mut leader_id = self.leader_id.0.lock().expect("Unable to get lock");
+                    if leader_id.is_none() {
+                        *leader_id = Some(ids[0]);
+                        self.leader_id.1.notify_all();
+                    }
+                }
+                MSG_STALE => {
+                    self.logger
+                        .trace(format!("Got STALE from {} with ids {:?}", from, ids));
+                    if ids[0] > self.epoch() && self.is_leader() {
+                        self.step_down(Some(ids[0]));
+                    }
+                }
+                MSG_LOG_ACK => {
+                    self.logger
+                        .trace(format!("Got LOG_ACK from {} with ids {:?}", from, ids));
+                    let (epoch, seq, id) = (ids[0], ids[1], ids[2]);
+                    if epoch == self.epoch() {
+                        let mut log_acks = self.log_acks.0.lock().expect("Unable to get lock");
+                        let acked = log_acks.entry(id).or_insert((0, Instant::now()));
+                        *acked = (seq.max(acked.0), Instant::now());
+                        self.log_acks.1.notify_all();
+                    }
+                }
+                MSG_RESEND => {
                     self.logger
-                        .trace(format!("Got ELECTION from {} with ids {:?}", from, ids));
-                    self.socket
-                        .send_to(&self.ids_to_msg(MSG_ACK, &[self.id]), from)
-                        .expect("Unable to send data");
-                    if ids.contains(&self.id) {
-                        let winner = *ids.iter().max().expect("Unable to get winner");
-                        self.socket
-                            .send_to(&self.ids_to_msg(MSG_COORDINATOR, &[winner]), from)
-                            .expect("Unable to send data");
-                    } else {
-                        ids.push(self.id);
-                        let msg = self.ids_to_msg(MSG_ELECTION, &ids);
-                        let clone = self.clone();
-
-                        thread::Builder::new()
-                            .name(format!("Node{}-Sender", self.id))
-                            .spawn(move || clone.safe_send_next(&msg, clone.id))
-                            .expect("node sender thread creation failed");
+                        .trace(format!("Got RESEND from {} with ids {:?}", from, ids));
+                    if self.is_leader() && self.cluster.contains(ids[0]) {
+                        self.send_snapshot(ids[0]);
                     }
                 }
-                MSG_COORDINATOR => {
+                MSG_STEPDOWN => {
                     self.logger
-                        .trace(format!("Got COORDINATOR from {} with ids {:?}", from, ids));
-                    *self.leader_id.0.lock().expect("Unable to get lock") = Some(ids[0]);
-                    self.leader_id.1.notify_all();
-                    self.socket
-                        .send_to(&self.ids_to_msg(MSG_ACK, &[self.id]), from)
-                        .expect("Unable to send message");
+                        .info(format!("Got STEPDOWN from {} with ids {:?}", from, ids));
+                    if self.is_leader() {
+                        *self.stepdown.lock().expect("Unable to get lock") =
+                            Some(ids.first().copied());
+                    }
+                }
+                MSG_TAKEOVER => {
                     self.logger
-                        .trace(format!("Sent ACK to {} with ids {:?}", from, ids));
-                    if !ids[1..].contains(&self.id) {
-                        ids.push(self.id);
-                        let msg = self.ids_to_msg(MSG_COORDINATOR, &ids);
-                        let clone = self.clone();
-
-                        thread::Builder::new()
-                            .name(format!("Node{}-Sender", self.id))
-                            .spawn(move || clone.safe_send_next(&msg, clone.id))
-                            .expect("node sender thread creation failed");
+                        .info(format!("Got TAKEOVER from {} with ids {:?}", from, ids));
+                    let (epoch, seq) = (ids[0], ids[1]);
+                    let up_to_date = {
+                        let state = self.state.lock().expect("Unable to get lock");
+                        epoch >= state.epoch && state.seq >= seq
+                    };
+                    // A replica that didn't store the whole state yet doesn't
+                    // answer, so the leader sends it again
+                    if up_to_date {
+                        self.send_to(&AlgloboNode::ids_to_msg(MSG_ACK, &[self.id]), from);
+                        if !self.is_leader() {
+                            self.set_leader(self.id);
+                            let clone = self.clone();
+                            thread::Builder::new()
+                                .name(format!("Node{}-Announcer", self.id))
+                                .spawn(move || clone.election.announce(&clone))
+                                .expect("node announcer thread creation failed");
+                        }
                     }
                 }
+                MSG_STATUS => {
+                    // Only the counts of the outcomes are reported, so the
+                    // state fits in a datagram
+                    let mut state = self.state.lock().expect("Unable to get lock").clone();
+                    state.compact(0);
+                    self.send_to(&state.to_bytes(), from);
+                }
                 MSG_KILL => {
                     self.logger.info("Got killed".to_string());
                     self.stop();
                     break;
                 }
                 _ => {
-                    self.logger
-                        .info(format!("Got unknown message from {}", from));
+                    if !self.election.handle(self, msg_type, ids, from) {
+                        self.logger
+                            .info(format!("Got unknown message from {}", from));
+                    }
                 }
             }
         }
     }
 
-    /// Parses the array of bytes into the message, returning its type and a
-    /// vector of the ids associated with it.
-    fn parse_message(&self, buf: &[u8]) -> (u8, Vec<usize>) {
-        let mut ids = vec![];
-
-        let count = usize::from_le_bytes(
-            buf[1..1 + size_of::<usize>()]
-                .try_into()
-                .expect("Unable to convert to bytes"),
-        );
-
-        let mut pos = 1 + size_of::<usize>();
-        for _id in 0..count {
-            ids.push(usize::from_le_bytes(
-                buf[pos..pos + size_of::<usize>()]
-                    .try_into()
-                    .expect("Unable to convert to bytes"),
-            ));
-            pos += size_of::<usize>();
+    /// Checks that a control message has the amount of fields its kind needs,
+    /// leaving the messages of the election algorithm to it
+    fn check_fields(&self, msg: ControlMsg) -> Result<ControlMsg, ControlMsgError> {
+        let valid = match msg.kind {
+            MSG_ACK | MSG_JOIN | MSG_LEADER | MSG_STALE | MSG_RESEND => msg.fields.len() == 1,
+            MSG_LOG_ACK => msg.fields.len() == 3,
+            MSG_TAKEOVER => msg.fields.len() == 2,
+            MSG_STEPDOWN => msg.fields.len() <= 1,
+            MSG_STATUS | MSG_KILL => msg.fields.is_empty(),
+            kind => self.election.valid_fields(kind, &msg.fields),
+        };
+        if !valid {
+            return Err(ControlMsgError::FieldCount {
+                kind: msg.kind,
+                count: msg.fields.len(),
+            });
         }
-
-        (buf[0], ids)
+        Ok(msg)
     }
 
     /// Returns an array of bytes representing the message from
     /// the message type and the ids.
-    fn ids_to_msg(&self, header: u8, ids: &[usize]) -> Vec<u8> {
-        let mut msg = vec![header];
-        msg.extend_from_slice(&ids.len().to_le_bytes());
-        for id in ids {
-            msg.extend_from_slice(&id.to_le_bytes());
+    pub fn ids_to_msg(header: u8, ids: &[usize]) -> Vec<u8> {
+        ControlMsg {
+            kind: header,
+            fields: ids.to_vec(),
         }
-        msg
+        .to_bytes()
     }
 
-    /// Sends the message to the next node in the ring. It waits for an ACK,
-    /// setting the got_ack flag, and if it doesn't receive one it will
-    /// try to send the message to the next node in the ring.
-    fn safe_send_next(&self, msg: &[u8], id: usize) {
-        if self.stop.load(Ordering::SeqCst) {
-            return;
-        }
-        self.logger
-            .trace(format!("Running safe_send_next for id {}", id));
-        let next_id = self.next(id);
-        if next_id == self.id {
-            self.logger
-                .trace(format!("Sent message {} to {}", msg[0], id));
-            panic!("Complete ring, sent message to itself with no response");
-        }
+    /// Sends a control message to the given address. Nodes that are down
+    /// simply miss it.
+    pub fn send_to(&self, msg: &[u8], addr: SocketAddr) {
+        let _ignore = self.socket.send_to(msg, addr);
+    }
+
+    /// Sends a control message to a node and waits for its ACK, setting the
+    /// got_ack flag. Returns false if the ACK didn't arrive before the timeout.
+    pub fn send_with_ack(&self, msg: &[u8], id: usize, timeout: Duration) -> bool {
         *self.got_ack.0.lock().expect("Unable to get stop lock") = None;
-        let _ignore = self.socket.send_to(msg, id_to_ctrladdr(next_id));
+        self.send_to(msg, self.cluster.ctrl_addr(id));
         let got_ack = self.got_ack.1.wait_timeout_while(
             self.got_ack.0.lock().expect("Unable to get stop lock"),
-            TIMEOUT,
-            |got_it| got_it.is_none() || got_it.expect("Unable to get lock value") != next_id,
+            timeout,
+            |got_it| got_it.is_none() || got_it.expect("Unable to get lock value") != id,
         );
-        if got_ack.expect("Unable to get condvar value").1.timed_out() {
-            self.safe_send_next(msg, next_id)
+        !got_ack.expect("Unable to get condvar value").1.timed_out()
+    }
+
+    /// Sets the id of the leader, waking up everyone waiting for it
+    pub fn set_leader(&self, id: usize) {
+        *self.leader_id.0.lock().expect("Unable to get lock") = Some(id);
+        self.leader_id.1.notify_all();
+    }
+
+    /// Forgets the id of the leader, as an election is going on
+    pub fn clear_leader(&self) {
+        *self.leader_id.0.lock().expect("Unable to get lock") = None;
+    }
+
+    /// Returns the leader id if it is known, without waiting for an election
+    /// to finish
+    fn leader(&self) -> Option<usize> {
+        *self.leader_id.0.lock().expect("Unable to get lock")
+    }
+
+    /// Whether the node currently believes it is the leader
+    fn is_leader(&self) -> bool {
+        self.leader() == Some(self.id)
+    }
+
+    /// Epoch of the current leader, as known by the node
+    fn epoch(&self) -> usize {
+        self.state.lock().expect("Unable to get lock").epoch
+    }
+
+    /// Returns the epoch the node takes when it becomes the leader. Epochs are
+    /// numbered so that each node has its own ones, and two leaders never share one.
+    pub fn next_epoch(&self) -> usize {
+        let rank = self
+            .cluster
+            .ids()
+            .iter()
+            .position(|id| *id == self.id)
+            .expect("Node not in the cluster");
+        (self.epoch() / self.cluster.size() + 1) * self.cluster.size() + rank
+    }
+
+    /// Stops being the leader after seeing a higher epoch (if known), which
+    /// means another node took over. The node finds out who the new leader is
+    /// with a MSG_JOIN.
+    fn step_down(&self, epoch: Option<usize>) {
+        let mut state = self.state.lock().expect("Unable to get lock");
+        match epoch {
+            Some(epoch) => {
+                self.logger
+                    .info(format!("Deposed by epoch {}, stepping down", epoch));
+                state.epoch = state.epoch.max(epoch);
+            }
+            None => self
+                .logger
+                .info("Deposed by a newer epoch, stepping down".to_string()),
+        }
+        self.clear_leader();
+    }
+
+    /// Hands over the place of the leader to the given node, or else to the
+    /// most up to date replica. Returns true if the node is no longer the leader.
+    fn step_down_to(&self, target: Option<usize>) -> bool {
+        let target = match target.filter(|id| *id != self.id && self.cluster.contains(*id)) {
+            Some(target) => Some(target),
+            None => self.election.best_replica(self),
+        };
+        let target = match target {
+            Some(target) => target,
+            None => {
+                self.logger
+                    .info("No replica to hand over to, still leading".to_string());
+                return false;
+            }
+        };
+
+        self.logger.info(format!("Handing over to node {}", target));
+        if !self.election.hand_over(self, target) {
+            self.logger
+                .info(format!("Node {} didn't take over, still leading", target));
+            return false;
         }
+        self.logger
+            .info(format!("Handed over to node {}, now a replica", target));
+        self.detector.lock().expect("Unable to get lock").reset();
+        true
+    }
+
+    /// The replica that acknowledged the latest update while still reachable,
+    /// ranked as in an election
+    pub fn best_replica(&self) -> Option<usize> {
+        let log_acks = self.log_acks.0.lock().expect("Unable to get lock");
+        log_acks
+            .iter()
+            .filter(|(_, (_, acked_at))| acked_at.elapsed() < TIMEOUT)
+            .max_by_key(|(id, (seq, _))| rank(&[**id, *seq, self.cluster.priority(**id)]))
+            .map(|(id, _)| *id)
+    }
+
+    /// Sends the whole state to the target and asks it to take over as leader,
+    /// until it acknowledges. Once it does, this node follows it as a replica.
+    /// Returns false if the target never took over.
+    pub fn hand_over_state(&self, target: usize) -> bool {
+        let (epoch, seq) = self.state.lock().expect("Unable to get lock").version();
+        let msg = AlgloboNode::ids_to_msg(MSG_TAKEOVER, &[epoch, seq]);
+        for _attempt in 0..HANDOVER_ATTEMPTS {
+            self.send_snapshot(target);
+            if self.send_with_ack(&msg, target, LOG_ACK_TIMEOUT) {
+                self.set_leader(target);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Waits for a leader to be set, up to the given timeout
+    pub fn wait_leader(&self, timeout: Duration) -> Option<usize> {
+        *self
+            .leader_id
+            .1
+            .wait_timeout_while(
+                self.leader_id.0.lock().expect("Unable to get lock"),
+                timeout,
+                |leader_id| leader_id.is_none(),
+            )
+            .expect("Unable to wait for condvar")
+            .0
+    }
+
+    /// Starts an election with the configured algorithm, without waiting for it
+    /// to finish.
+    pub fn start_election(&self) {
+        self.election.start(self);
     }
 
-    /// Get the id of next node in the ring.
-    fn next(&self, id: usize) -> usize {
-        (id + 1) % N_NODES
+    /// The candidacy of the node to become the leader: its id, the sequence
+    /// number of its last state update and its priority
+    pub fn candidacy(&self) -> Vec<usize> {
+        let seq = self.state.lock().expect("Unable to get lock").seq;
+        vec![self.id, seq, self.cluster.priority(self.id)]
     }
 
-    /// Start a new leader election sending a new MSG_ELECTION to the next node.
-    /// Blocks until a new leader is found.
+    /// The id of the node
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Membership of the cluster the node belongs to
+    pub fn cluster(&self) -> &ClusterConfig {
+        &self.cluster
+    }
+
+    /// Logger of the node
+    pub fn logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    /// Whether the node was stopped
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::SeqCst)
+    }
+
+    /// Start a new leader election with the configured algorithm.
+    /// Blocks until a new leader is found, and logs how long it took. If the
+    /// election gets lost, like when a node dies while passing it on, it is
+    /// started again.
     fn find_new(&mut self) {
         if self.stop.load(Ordering::SeqCst) {
             return;
         }
         self.logger.info("Looking for new leader".to_string());
-        *self.leader_id.0.lock().expect("Unable to get lock") = None;
+        self.clear_leader();
+        let start = Instant::now();
 
-        self.safe_send_next(&self.ids_to_msg(MSG_ELECTION, &[self.id]), self.id);
+        self.start_election();
 
-        let _ignore = self.leader_id.1.wait_while(
-            self.leader_id.0.lock().expect("Unable to get lock"),
-            |leader_id| leader_id.is_none(),
-        );
+        let leader_id = loop {
+            // Enough time for a message to go around the whole ring
+            match self.wait_leader(TIMEOUT * self.cluster.size() as u32) {
+                Some(leader_id) => break leader_id,
+                None if self.is_stopped() => return,
+                None => {
+                    self.logger
+                        .info("Election got no leader, starting it again".to_string());
+                    self.start_election();
+                }
+            }
+        };
+        self.detector.lock().expect("Unable to get lock").reset();
+        self.logger.info(format!(
+            "Election ({}) finished in {} ms with leader {}",
+            self.election.name(),
+            start.elapsed().as_millis(),
+            leader_id
+        ));
     }
 
     /// Clone the AlgloboNode struct.
-    fn clone(&self) -> AlgloboNode {
+    pub fn clone(&self) -> AlgloboNode {
         AlgloboNode {
             id: self.id,
+            cluster: self.cluster.clone(),
+            election: self.election.clone(),
+            payments_file: self.payments_file.clone(),
+            format: self.format,
+            agents_names: self.agents_names.clone(),
             socket: self.socket.try_clone().expect("Unable to clone socket"),
+            data_socket: self
+                .data_socket
+                .try_clone()
+                .expect("Unable to clone socket"),
             leader_id: self.leader_id.clone(),
             got_ack: self.got_ack.clone(),
+            log_acks: self.log_acks.clone(),
+            degraded: self.degraded.clone(),
             stop: self.stop.clone(),
-            last_id: self.last_id.clone(),
-            last_status: self.last_status.clone(),
+            state: self.state.clone(),
+            snapshot_parts: self.snapshot_parts.clone(),
+            detector: self.detector.clone(),
+            last_heartbeat: self.last_heartbeat.clone(),
+            stepdown: self.stepdown.clone(),
             logger: self.logger.clone(),
         }
     }
 
-    /// Broadcast a message to all agents at the same time, returning
-    /// a condvar and a vector of the results.
+    /// Broadcast a message to all agents at the same time, returning the
+    /// response of each agent (PAYMENT_ERR if it didn't answer) and whether
+    /// any of them timed out.
     fn broadcast(
         &self,
-        transaction_id: usize,
+        payment_id: &str,
         transaction_prices: &[u32],
         operation: u8,
-        agents_ports: &[u16],
+        agents_addrs: &[Vec<SocketAddr>],
         im_alive: &Arc<AtomicBool>,
-    ) -> (Vec<[u8; 1]>, bool) {
-        let responses = Arc::new((Mutex::new(vec![]), Condvar::new()));
+    ) -> (Vec<u8>, bool) {
+        let responses = Arc::new((Mutex::new(vec![None; agents_addrs.len()]), Condvar::new()));
 
-        for (i, agent_port) in agents_ports.iter().enumerate() {
-            let addr = SocketAddr::from(([127, 0, 0, 1], *agent_port));
+        for (i, agent_addrs) in agents_addrs.iter().enumerate() {
+            let agent_addrs = agent_addrs.clone();
 
             let im_alive_clone = im_alive.clone();
             let logger_clone = self.logger.clone();
@@ -300,24 +695,25 @@ impl AlgloboNode {
             let responses_clone = responses.clone();
 
             let msg = DataMsg {
-                transaction_id: transaction_id as u32,
+                payment_id: payment_id.to_string(),
                 opcode: operation,
                 data: transaction_prices[i],
+                epoch: self.epoch() as u64,
             };
 
             thread::Builder::new()
-                .name(format!("Transaction {}", transaction_id))
+                .name(format!("Transaction {}", payment_id))
                 .spawn(move || {
-                    let client_conn_result = TcpStream::connect(addr);
+                    let client_conn_result = connect_to_agent(&agent_addrs);
                     let (lock, cvar) = &*responses_clone;
                     let mut response: [u8; 1] = Default::default();
 
-                    if client_conn_result.is_err() {
-                        logger_clone.info("Could not connect to agent".to_string());
-                        response[0] = PAYMENT_ERR;
-                        lock.lock()
-                            .expect("Unable to lock responses")
-                            .push(response);
+                    if client_conn_result.is_none() {
+                        logger_clone.info(format!(
+                            "Transaction {} | Could not connect to agent",
+                            msg.payment_id
+                        ));
+                        lock.lock().expect("Unable to lock responses")[i] = Some(PAYMENT_ERR);
                         cvar.notify_all();
                         return;
                     }
@@ -334,13 +730,13 @@ impl AlgloboNode {
                     });
 
                     if !im_alive_clone.load(Ordering::SeqCst) {
-                        logger_clone.info("Connection with agent suddenly closed".to_string())
+                        logger_clone.info(format!(
+                            "Transaction {} | Connection with agent suddenly closed",
+                            msg.payment_id
+                        ))
                     }
 
-                    lock.lock()
-                        .expect("Unable to lock responses")
-                        .push(response);
-
+                    lock.lock().expect("Unable to lock responses")[i] = Some(response[0]);
                     cvar.notify_all();
                 })
                 .expect("thread creation failed");
@@ -351,173 +747,633 @@ impl AlgloboNode {
             .wait_timeout_while(
                 lock.lock().expect("Unable to lock responses"),
                 TIMEOUT,
-                |responses| responses.len() != agents_ports.len(),
+                |responses| responses.iter().any(Option::is_none),
             )
             .expect("Error on wait condvar");
 
-        (all_responses.to_vec(), timeout.timed_out())
+        let all_responses = all_responses
+            .iter()
+            .map(|response| response.unwrap_or(PAYMENT_ERR))
+            .collect();
+        (all_responses, timeout.timed_out())
     }
 
     /// Finishes the transaction acording to the results of the broadcast
-    /// and its last status obtained.
+    /// and its last status obtained. Only the agents involved in the payment
+    /// get the decision.
     fn finish_transaction(
         &self,
         operation: u8,
         transaction_id: usize,
-        transaction_prices: &[u32],
-        agents_ports: &[u16],
+        payment: &Payment,
+        agents_addrs: &[Vec<SocketAddr>],
         im_alive: &Arc<AtomicBool>,
-        retry_file: &std::fs::File,
-    ) {
+        ledgers: &mut Ledgers,
+    ) -> bool {
+        if crash_at(BEFORE_DECISION) {
+            return self.crash(BEFORE_DECISION);
+        }
+        // The decision is replicated before any agent gets it, so a new leader
+        // sends the same one if this node dies during phase two
+        let update = StateUpdate {
+            data: vec![self.id as u32],
+            ..StateUpdate::new(operation, transaction_id, &payment.id)
+        };
+        if !self.update_state(update) {
+            return false;
+        }
         self.logger.info(format!(
-            "Payment of {:?} | {}",
-            transaction_prices,
+            "Payment {} of {} | {}",
+            payment.id,
+            payment.describe(&self.agents_names),
             if operation == COMMIT { "OK" } else { "ERR" },
         ));
         self.logger.trace(format!(
             "Transaction {} | {}",
-            transaction_id,
+            payment.id,
             if operation == COMMIT {
-                "ABORT"
-            } else {
                 "COMMIT"
+            } else {
+                "ABORT"
             },
         ));
-        if operation == ABORT {
-            write_to_csv(retry_file, transaction_prices);
+        let outcome = self
+            .state
+            .lock()
+            .expect("Unable to get lock")
+            .outcomes
+            .iter()
+            .rev()
+            .find(|outcome| outcome.payment == payment.id)
+            .cloned();
+        // The outcome is the decision of the cluster once it's replicated, so
+        // it's recorded before phase two
+        if let Some(outcome) = outcome {
+            ledgers.record(&outcome, payment);
+        }
+        if crash_at(BEFORE_PHASE_TWO) {
+            return self.crash(BEFORE_PHASE_TWO);
         }
 
-        let (_all_responses, _is_timeout) = self.broadcast(
-            transaction_id,
+        if !self.send_decision(
+            operation,
+            &payment.id,
+            &payment.prices,
+            &payment.select(agents_addrs),
+            im_alive,
+        ) {
+            return false;
+        }
+        if crash_at(AFTER_PHASE_TWO) {
+            return self.crash(AFTER_PHASE_TWO);
+        }
+        true
+    }
+
+    /// Sends the replicated decision of a transaction to all agents (phase two).
+    /// Returns false if the node is no longer the leader.
+    fn send_decision(
+        &self,
+        operation: u8,
+        payment_id: &str,
+        transaction_prices: &[u32],
+        agents_addrs: &[Vec<SocketAddr>],
+        im_alive: &Arc<AtomicBool>,
+    ) -> bool {
+        if crash_at(DURING_PHASE_TWO) {
+            // Only the first agent gets the decision
+            self.broadcast(
+                payment_id,
+                &transaction_prices[..1],
+                operation,
+                &agents_addrs[..1],
+                im_alive,
+            );
+            return self.crash(DURING_PHASE_TWO);
+        }
+
+        let (all_responses, _is_timeout) = self.broadcast(
+            payment_id,
             transaction_prices,
             operation,
-            agents_ports as &[u16],
+            agents_addrs,
             im_alive,
         );
+        !self.is_fenced(&all_responses)
+    }
 
-        self.broadcast_last_log(operation, transaction_id);
-        *self.last_id.lock().expect("Unable to get lock") += 1;
-        *self.last_status.lock().expect("Unable to get lock") = operation;
+    /// Stops the node as if it crashed at the given point. Returns false, as the
+    /// node can't go on with the transaction.
+    fn crash(&self, point: &str) -> bool {
+        self.logger.info(format!("Crash injected {}", point));
+        self.stop.store(true, Ordering::SeqCst);
+        false
+    }
+
+    /// Checks if an agent rejected a message because it saw a newer epoch,
+    /// in which case the node steps down
+    fn is_fenced(&self, responses: &[u8]) -> bool {
+        if responses.contains(&STALE_EPOCH) {
+            self.step_down(None);
+            return true;
+        }
+        false
     }
 
     /// Function used by the leader for handling the payments. It sends
     /// the payment information in the prices.csv to all the agents and logs
     /// their results.
-    /// It will stop processing payments if the stop flag is set to true.
-    /// It will send a KILL message to all nodes if all payments finished processing.
-    fn process_payments(&self) {
+    /// It will stop processing payments if the stop flag is set to true, or if
+    /// it stops being the leader.
+    /// It will send a KILL message to all nodes if all payments finished processing,
+    /// and only then returns true. If the payments come from a spool directory,
+    /// it waits for new ones instead.
+    fn process_payments(&self) -> bool {
         let im_alive = Arc::new(AtomicBool::new(true));
-        let agents_ports = get_agents_ports();
+        let agents_addrs = get_agents_addrs();
 
-        let prices_file = match env::args().nth(1) {
-            Some(val) => val,
-            None => "src/prices.csv".to_string(),
+        let mut spool = if Path::new(&self.payments_file).is_dir() {
+            Some(SpoolReader::new(&self.payments_file, &self.agents_names))
+        } else {
+            None
         };
-        let prices = csv_to_prices(&prices_file);
+        let report = match spool.as_mut() {
+            Some(spool) => {
+                spool.read();
+                spool.report().clone()
+            }
+            None => Payment::read(&self.payments_file, self.format, &self.agents_names),
+        };
+        let mut payments = report.accepted(self.cluster.quarantine());
+        let source = fingerprint(&self.payments_file);
+
+        let epoch = self.election.new_epoch(self);
+        self.state.lock().expect("Unable to get lock").epoch = epoch;
+        self.log_acks.0.lock().expect("Unable to get lock").clear();
+        *self.degraded.lock().expect("Unable to get lock") = false;
+        self.logger.info(format!("Leading with epoch {}", epoch));
+
+        let state = self.state.lock().expect("Unable to get lock").clone();
+        if state.source != 0 && state.source != source {
+            return self.refuse_to_lead();
+        }
+        // The first leader of a run starts the quarantine file, and the next
+        // ones append the lines it misses
+        if state.source == 0 && self.cluster.quarantine() {
+            let quarantine_file = create_empty_csv(QUARANTINE_FILE);
+            write_csv_line(&quarantine_file, &InvalidLine::csv_header());
+        }
+        let mut reported = self.report_invalid(&report, 0);
+        if !self.checkpoint_source(source, payments.len()) {
+            return false;
+        }
 
-        let retry_file = create_empty_csv("src/prices-retry.csv");
+        // The ledgers get the replicated outcomes they miss, so that the rows
+        // written by previous leaders are kept
+        let mut ledgers = Ledgers::new(self.format, &self.agents_names, &state, &payments);
+        if ledgers.recorded() < state.next_id() {
+            self.logger.info(format!(
+                "Only {} of the {} decided payments are in the outcome ledger, the compacted ones are missing from it",
+                ledgers.recorded(),
+                state.next_id()
+            ));
+        }
 
-        let last_status = *self.last_status.lock().expect("Unable to get lock");
-        if last_status == PREPARE {
-            // If the last transaction was a PREPARE, we need to ABORT it
-            let transaction_id = *self.last_id.lock().expect("Unable to get lock");
-            self.finish_transaction(
-                ABORT,
+        if let Some(transaction_id) = state.in_flight() {
+            // If every agent voted for the last transaction, the decision follows
+            // from their votes. Otherwise we need to ABORT it
+            let all_oks = state.votes.len() == state.prices.len()
+                && state.votes.iter().all(|vote| *vote == PAYMENT_OK);
+            if !self.finish_transaction(
+                if all_oks { COMMIT } else { ABORT },
                 transaction_id,
-                &prices[transaction_id].clone(),
-                &agents_ports as &[u16],
+                &ledger_payment(
+                    &payments,
+                    &state.last_payment,
+                    &state.participants,
+                    &state.prices,
+                ),
+                &agents_addrs as &[Vec<SocketAddr>],
                 &im_alive,
-                &retry_file,
-            );
-        } else if last_status == COMMIT || last_status == ABORT {
-            // If the last transaction was a COMMIT or ABORT, we need to start a new one
-            *self.last_id.lock().expect("Unable to get lock") += 1;
+                &mut ledgers,
+            ) {
+                return false;
+            }
+        } else if let Some((_, operation)) = state.decided() {
+            // The previous leader could have died during phase two, so the agents
+            // get its decision again. Finishing a transaction twice is harmless
+            self.logger.trace(format!(
+                "Transaction {} | Sending the decision {} again",
+                state.last_payment, operation as char
+            ));
+            if !self.send_decision(
+                operation,
+                &state.last_payment,
+                &state.prices,
+                &state
+                    .participants
+                    .iter()
+                    .map(|agent| agents_addrs[*agent].clone())
+                    .collect::<Vec<Vec<SocketAddr>>>(),
+                &im_alive,
+            ) {
+                return false;
+            }
         }
 
-        while *self.last_id.lock().expect("Unable to get lock") < prices.len() {
+        let mut next = 0;
+        loop {
             if self.stop.load(Ordering::SeqCst) {
                 self.logger
                     .trace("Leader stopped before PREPARE msg".to_string());
-                return;
+                return false;
             }
 
-            let transaction_id = *self.last_id.lock().expect("Unable to get lock");
-            let transaction_prices = prices[transaction_id].clone();
+            // A planned handover only happens between transactions, so none
+            // of them gets aborted because of it
+            let stepdown = self.stepdown.lock().expect("Unable to get lock").take();
+            if let Some(target) = stepdown {
+                if self.step_down_to(target) {
+                    return false;
+                }
+            }
+
+            // Payments are decided once by their id, wherever they are in the
+            // input, so the next one is the first that wasn't decided yet. The
+            // ones before `next` were all decided, so they aren't looked at again
+            while next < payments.len() && ledgers.is_decided(&payments[next].id) {
+                next += 1;
+            }
+            let payment = match (payments.get(next), spool.as_mut()) {
+                (Some(payment), _) => payment.clone(),
+                (None, None) => break,
+                (None, Some(spool)) => {
+                    sleep(SPOOL_POLL);
+                    if !self.is_leader() {
+                        return false;
+                    }
+                    // Only the lines added to the spool are read. Every payment
+                    // listed was decided, so the accepted ones are listed again,
+                    // without the ones of a file rejected since
+                    if spool.read() {
+                        let report = spool.report();
+                        reported = self.report_invalid(report, reported);
+                        payments = report.accepted(self.cluster.quarantine());
+                        next = 0;
+                    }
+                    if !self.checkpoint_source(source, payments.len()) {
+                        return false;
+                    }
+                    continue;
+                }
+            };
+            let transaction_id = self.next_id();
 
             if !im_alive.load(Ordering::SeqCst) {
-                break;
+                if spool.is_none() {
+                    break;
+                }
+                // The cluster keeps receiving payments, which may reach the agents
+                im_alive.store(true, Ordering::SeqCst);
             };
 
+            // The PREPARE is replicated before contacting the agents, so a new
+            // leader knows it has to finish this transaction
+            let update = StateUpdate {
+                data: payment.to_prepare_data(),
+                ..StateUpdate::new(PREPARE, transaction_id, &payment.id)
+            };
+            if !self.update_state(update) {
+                self.logger
+                    .trace("Leader deposed before PREPARE msg".to_string());
+                return false;
+            }
+
             let im_alive_clone_agents = im_alive.clone();
             self.logger
-                .trace(format!("Transaction {} | PREPARE", transaction_id));
+                .trace(format!("Transaction {} | PREPARE", payment.id));
 
             let (all_responses, is_timeout) = self.broadcast(
-                transaction_id,
-                &transaction_prices,
+                &payment.id,
+                &payment.prices,
                 PREPARE,
-                &agents_ports as &[u16],
+                &payment.select(&agents_addrs),
                 &im_alive_clone_agents,
             );
-            self.broadcast_last_log(PREPARE, transaction_id);
 
             if self.stop.load(Ordering::SeqCst) {
                 self.logger
                     .trace("Leader stopped after PREPARE msg".to_string());
-                return;
+                return false;
+            }
+            if self.is_fenced(&all_responses) {
+                return false;
             }
 
-            // Wait for all agents to respond or timeout
-            // let all_oks = all_respo
-            let all_oks = all_responses.iter().all(|&opt| opt[0] == PAYMENT_OK);
+            // The votes are replicated, so a new leader can decide with them
+            let update = StateUpdate {
+                data: all_responses.iter().map(|vote| *vote as u32).collect(),
+                ..StateUpdate::new(VOTES, transaction_id, &payment.id)
+            };
+            if !self.update_state(update) {
+                self.logger
+                    .trace("Leader deposed before deciding the transaction".to_string());
+                return false;
+            }
+
+            let all_oks = all_responses.iter().all(|vote| *vote == PAYMENT_OK);
 
             let operation = if all_oks && !is_timeout && im_alive.load(Ordering::SeqCst) {
                 COMMIT
             } else {
                 ABORT
             };
-            self.finish_transaction(
+            if !self.finish_transaction(
                 operation,
                 transaction_id,
-                &transaction_prices,
-                &agents_ports as &[u16],
+                &payment,
+                &agents_addrs as &[Vec<SocketAddr>],
                 &im_alive,
-                &retry_file,
-            );
+                &mut ledgers,
+            ) {
+                self.logger
+                    .trace("Leader deposed before finishing the transaction".to_string());
+                return false;
+            }
+            self.logger.info(format!(
+                "Status: {}",
+                self.state.lock().expect("Unable to get lock")
+            ));
             // This sleep is only for debugging purposes
             sleep(Duration::from_millis(1000));
         }
 
         self.logger
             .trace("Sending finish command to agents".to_string());
-        let dummy_data = vec![0; agents_ports.len()];
-        let (_all_responses, _is_timeout) =
-            self.broadcast(0, &dummy_data, FINISH, &agents_ports as &[u16], &im_alive);
+        let dummy_data = vec![0; agents_addrs.len()];
+        let (_all_responses, _is_timeout) = self.broadcast(
+            "",
+            &dummy_data,
+            FINISH,
+            &agents_addrs as &[Vec<SocketAddr>],
+            &im_alive,
+        );
 
         self.logger.info("Killing all replicas".to_string());
-        for i in 0..N_NODES {
+        for i in self.cluster.ids() {
             if i == self.id {
                 continue;
             }
-            self.socket
-                .send_to(&[MSG_KILL], id_to_ctrladdr(i))
-                .expect("Unable to send kill");
+            // Nodes that are already down don't need it
+            let _ignore = self.socket.send_to(
+                &AlgloboNode::ids_to_msg(MSG_KILL, &[]),
+                self.cluster.ctrl_addr(i),
+            );
         }
+        true
     }
 
-    /// Sends the status of the last broadcast to all the replicas.
-    pub fn broadcast_last_log(&self, status: u8, id: usize) {
-        let mut bytes = vec![status];
-        bytes.extend(id.to_be_bytes());
+    /// Hands over the place of the leader to a replica, as the payments file
+    /// of the node is not the one the cluster is processing, or stops the node
+    /// if no replica can take over. Returns false, as the node can't lead.
+    fn refuse_to_lead(&self) -> bool {
+        self.logger.info(format!(
+            "Refusing to lead: payments file {} is not the one the cluster is processing",
+            self.payments_file
+        ));
+        if !self.step_down_to(None) {
+            self.logger
+                .info("No replica can take over, stopping".to_string());
+            self.stop.store(true, Ordering::SeqCst);
+        }
+        false
+    }
 
-        for i in 0..N_NODES {
-            if i == self.get_leader_id() {
-                continue;
+    /// Logs the invalid lines of the payments after the first `reported` ones,
+    /// and appends the quarantined ones missing from the quarantine file.
+    /// Returns the amount of invalid lines reported.
+    fn report_invalid(&self, report: &ValidationReport, reported: usize) -> usize {
+        if report.invalid.len() <= reported {
+            return reported;
+        }
+        for line in &report.invalid[reported..] {
+            self.logger.info(format!(
+                "{} {}, line {}: {}",
+                if self.cluster.quarantine() {
+                    "Quarantined"
+                } else {
+                    "Rejected the whole file for"
+                },
+                line.file,
+                line.number,
+                line.error
+            ));
+        }
+        if self.cluster.quarantine() {
+            // Previous leaders may have written some of the lines already
+            let written = fs::read_to_string(QUARANTINE_FILE).unwrap_or_default();
+            let written: HashSet<&str> = written.lines().collect();
+            let quarantine_file = open_csv_to_append(QUARANTINE_FILE);
+            if written.is_empty() {
+                write_csv_line(&quarantine_file, &InvalidLine::csv_header());
             }
-            self.socket
-                .send_to(&bytes, id_to_dataaddr(i))
-                .expect("Unable to send message");
+            for line in &report.invalid[reported..] {
+                let line = line.to_csv_line();
+                if !written.contains(line.as_str()) {
+                    write_csv_line(&quarantine_file, &line);
+                }
+            }
+        }
+        report.invalid.len()
+    }
+
+    /// Replicates the fingerprint of the payments being processed and their
+    /// amount, if it changed, so that the input is checkpointed along with the
+    /// payments already decided. Returns false if the node is no longer the leader.
+    fn checkpoint_source(&self, source: u32, rows: usize) -> bool {
+        let (known_source, known_rows) = {
+            let state = self.state.lock().expect("Unable to get lock");
+            (state.source, state.rows)
+        };
+        if known_source == source && known_rows == rows {
+            return true;
+        }
+        if known_source == source && rows > known_rows {
+            self.logger
+                .info(format!("{} new payments received", rows - known_rows));
         }
+        self.update_state(StateUpdate {
+            data: vec![source],
+            ..StateUpdate::new(SOURCE, rows, "")
+        })
+    }
+
+    /// Returns the number of the next transaction the leader has to start
+    fn next_id(&self) -> usize {
+        self.state.lock().expect("Unable to get lock").next_id()
+    }
+
+    /// Replicates an update of the coordinator state with the configured algorithm.
+    /// Returns false if the node is no longer the leader, and so it can't act on it.
+    fn update_state(&self, update: StateUpdate) -> bool {
+        self.is_leader() && self.election.replicate(self, &update)
+    }
+
+    /// Applies an update of the coordinator state to the local state
+    pub fn apply_state(&self, update: &StateUpdate) {
+        let mut state = self.state.lock().expect("Unable to get lock");
+        state.apply(update);
+        state.compact(self.cluster.history());
+    }
+
+    /// The whole coordinator state, as a snapshot
+    pub fn state_bytes(&self) -> Vec<u8> {
+        self.state.lock().expect("Unable to get lock").to_bytes()
+    }
+
+    /// Replaces the coordinator state with a snapshot
+    pub fn restore_state(&self, snapshot: CoordinatorState) {
+        *self.state.lock().expect("Unable to get lock") = snapshot;
+    }
+
+    /// Sends the whole coordinator state to a node that joined the cluster,
+    /// split in parts that fit in a datagram. Each part has the epoch and the
+    /// sequence number of the state, its index and the amount of parts.
+    pub fn send_snapshot(&self, id: usize) {
+        let (epoch, seq, snapshot) = {
+            let state = self.state.lock().expect("Unable to get lock");
+            (state.epoch, state.seq, state.to_bytes())
+        };
+        let parts: Vec<&[u8]> = snapshot.chunks(SNAPSHOT_PART).collect();
+        if parts.len() > MAX_SNAPSHOT_PARTS {
+            self.logger.info(format!(
+                "Snapshot of {} bytes is too big to send to node {}",
+                snapshot.len(),
+                id
+            ));
+            return;
+        }
+        self.logger.trace(format!(
+            "Sending snapshot to node {} in {} parts",
+            id,
+            parts.len()
+        ));
+        for (i, part) in parts.iter().enumerate() {
+            let mut bytes = vec![LOG_SNAPSHOT];
+            bytes.extend((epoch as u64).to_be_bytes());
+            bytes.extend((seq as u64).to_be_bytes());
+            bytes.extend((i as u32).to_be_bytes());
+            bytes.extend((parts.len() as u32).to_be_bytes());
+            bytes.extend(*part);
+            if let Err(err) = self.socket.send_to(&bytes, self.cluster.data_addr(id)) {
+                self.logger
+                    .info(format!("Couldn't send snapshot to node {}: {}", id, err));
+                return;
+            }
+        }
+    }
+
+    /// Sends the last update of the state to all the replicas, with the
+    /// sequence number of the update. Sends it again to the replicas that didn't
+    /// acknowledge it until the quorum of the cluster stored it, and then returns
+    /// true. Returns false if the node stops being the leader meanwhile.
+    ///
+    /// If fewer replicas than the quorum are reachable, the cluster is degraded
+    /// and the update waits for the quorum to be back, until `QUORUM_TIMEOUT`
+    /// passes and the leader steps down. Only with `degraded_quorum` set it
+    /// waits for the reachable ones instead, so the leader keeps processing
+    /// payments even when it is the only node alive.
+    pub fn broadcast_last_log(&self, update: &StateUpdate) -> bool {
+        let (epoch, seq) = self.state.lock().expect("Unable to get lock").version();
+        let mut bytes = vec![LOG_UPDATE];
+        bytes.extend((epoch as u64).to_be_bytes());
+        bytes.extend((seq as u64).to_be_bytes());
+        bytes.extend(update.to_bytes());
+
+        let stored =
+            |log_acks: &LogAcks| log_acks.values().filter(|(acked, _)| *acked >= seq).count();
+
+        // The leader itself already stored the update
+        let needed = self.cluster.quorum() - 1;
+        let start = Instant::now();
+        let mut log_acks = self.log_acks.0.lock().expect("Unable to get lock");
+        loop {
+            for i in self.cluster.ids() {
+                if i != self.id && log_acks.get(&i).map_or(0, |(acked, _)| *acked) < seq {
+                    // Replicas that are down will get a snapshot when they join again
+                    let _ignore = self.socket.send_to(&bytes, self.cluster.data_addr(i));
+                }
+            }
+            log_acks = self
+                .log_acks
+                .1
+                .wait_timeout_while(log_acks, LOG_ACK_TIMEOUT, |log_acks| {
+                    stored(log_acks) < needed
+                })
+                .expect("Unable to wait for condvar")
+                .0;
+            if stored(&log_acks) >= needed {
+                return true;
+            }
+            if !self.is_leader() || self.is_stopped() {
+                return false;
+            }
+            // Every replica alive had the time to store the update, so the
+            // ones that didn't yet aren't reachable
+            let reachable = self.reachable_replicas(&log_acks, seq);
+            if self.cluster.degraded_quorum() && stored(&log_acks) >= needed.min(reachable) {
+                return true;
+            }
+            if !self.cluster.degraded_quorum() && start.elapsed() >= QUORUM_TIMEOUT {
+                self.logger.info(format!(
+                    "Update {} not stored by the quorum in {} s, stepping down",
+                    seq,
+                    QUORUM_TIMEOUT.as_secs()
+                ));
+                self.clear_leader();
+                return false;
+            }
+            self.logger.trace(format!(
+                "Update {} stored by {} of {} replicas, sending it again",
+                seq,
+                stored(&log_acks),
+                needed
+            ));
+        }
+    }
+
+    /// Amount of replicas that acknowledged an update lately while this node
+    /// leads, or the update with the given sequence number, which they only
+    /// acknowledge once. Logs when the cluster becomes degraded, because fewer
+    /// replicas than the quorum are reachable, and when it recovers.
+    fn reachable_replicas(&self, log_acks: &LogAcks, seq: usize) -> usize {
+        let mut was_degraded = self.degraded.lock().expect("Unable to get lock");
+        let reachable = log_acks
+            .values()
+            .filter(|(acked, acked_at)| *acked >= seq || acked_at.elapsed() < TIMEOUT)
+            .count();
+        let degraded = reachable + 1 < self.cluster.quorum();
+        if degraded != *was_degraded {
+            self.logger.info(if degraded && self.cluster.degraded_quorum() {
+                format!(
+                    "WARNING: degraded cluster: only {} of {} nodes reachable, below the quorum of {}, storing updates on them only",
+                    reachable + 1,
+                    self.cluster.size(),
+                    self.cluster.quorum()
+                )
+            } else if degraded {
+                format!(
+                    "Degraded cluster: only {} of {} nodes reachable, waiting for the quorum of {}",
+                    reachable + 1,
+                    self.cluster.size(),
+                    self.cluster.quorum()
+                )
+            } else {
+                "Cluster recovered its quorum".to_string()
+            });
+            *was_degraded = degraded;
+        }
+        reachable
     }
 
     /// Main loop function of the node. If it's the leader it will process the
@@ -529,35 +1385,23 @@ impl AlgloboNode {
     /// is set.
     pub fn loop_node(&mut self) {
         self.logger.info("Start".to_string());
-        let socket = UdpSocket::bind(id_to_dataaddr(self.id)).expect("Unable to bind socket");
 
         while !self.stop.load(Ordering::SeqCst) {
             if self.am_i_leader() {
                 self.logger.info("I am the leader".to_string());
-                let _ignore = socket.set_read_timeout(None);
-                self.process_payments();
-                break;
+                if self.process_payments() {
+                    break;
+                }
+                // A deposed leader doesn't know who took over
+                if self.leader().is_none() && !self.join() {
+                    self.find_new();
+                }
             } else {
                 let leader_id = self.get_leader_id();
                 self.logger
                     .trace(format!("Waiting for message from leader {}", leader_id));
 
-                let mut response: [u8; std::mem::size_of::<usize>() + 1] = Default::default();
-                socket
-                    .set_read_timeout(Some(TIMEOUT))
-                    .expect("Unable to set timeout");
-
-                if let Ok((_size, _from)) = socket.recv_from(&mut response) {
-                    *self.last_status.lock().expect("Unable to get lock") = response[0];
-                    let id_bytes: [u8; std::mem::size_of::<usize>()] =
-                        response[1..].try_into().expect("Incorrect message length");
-                    *self.last_id.lock().expect("Unable to get lock") =
-                        usize::from_be_bytes(id_bytes) as usize;
-                    self.logger.trace(format!(
-                        "Received last log: Last status is {} for node {}",
-                        response[0], response[1]
-                    ));
-                } else {
+                if !self.election.follow(self) {
                     self.logger
                         .info("The leader is dead. Long live the leader.".to_string());
                     self.find_new();
@@ -566,6 +1410,173 @@ impl AlgloboNode {
         }
     }
 
+    /// Waits for a message from the leader on the data socket and applies it.
+    /// Returns false if the failure detector suspects the leader is dead before
+    /// a message arrives.
+    pub fn receive_from_leader(&self) -> bool {
+        let mut response = vec![0; MAX_DATAGRAM];
+        self.data_socket
+            .set_read_timeout(Some(self.cluster.heartbeat_interval()))
+            .expect("Unable to set timeout");
+
+        while !self.is_stopped() {
+            // The leader may have handed over its place to this node
+            if self.is_leader() {
+                return true;
+            }
+            if let Ok((size, from)) = self.data_socket.recv_from(&mut response) {
+                if self.receive_log(&response[..size], from) {
+                    self.detector
+                        .lock()
+                        .expect("Unable to get lock")
+                        .heartbeat();
+                    return true;
+                }
+            }
+            let phi = self.detector.lock().expect("Unable to get lock").phi();
+            if phi > self.cluster.phi_threshold() {
+                self.logger
+                    .info(format!("Suspecting the leader with phi {:.2}", phi));
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sends a heartbeat to every replica if the node is the leader and the
+    /// heartbeat interval passed since the last one
+    pub fn heartbeat(&self) {
+        let mut last_heartbeat = self.last_heartbeat.lock().expect("Unable to get lock");
+        if !self.is_leader() || last_heartbeat.elapsed() < self.cluster.heartbeat_interval() {
+            return;
+        }
+        let mut bytes = vec![LOG_HEARTBEAT];
+        bytes.extend((self.epoch() as u64).to_be_bytes());
+        for i in self.cluster.ids() {
+            if i != self.id {
+                let _ignore = self.socket.send_to(&bytes, self.cluster.data_addr(i));
+            }
+        }
+        *last_heartbeat = Instant::now();
+    }
+
+    /// Applies a data message sent by the leader, either the status of a
+    /// transaction, a part of a snapshot of the whole state or a heartbeat, and
+    /// acknowledges updates and whole snapshots with a MSG_LOG_ACK. Messages
+    /// from an older epoch than the known one are rejected with a MSG_STALE,
+    /// and updates that come after a gap with a MSG_RESEND. Returns true if the message shows
+    /// that the leader is alive. Messages that can't be decoded are logged and
+    /// dropped.
+    fn receive_log(&self, msg: &[u8], from: SocketAddr) -> bool {
+        match self.apply_log(msg, from) {
+            Ok(alive) => alive,
+            Err(err) => {
+                self.logger
+                    .info(format!("Discarded data message from {}: {}", from, err));
+                false
+            }
+        }
+    }
+
+    /// Decodes and applies a data message for `receive_log`
+    fn apply_log(&self, msg: &[u8], from: SocketAddr) -> Result<bool, FrameError> {
+        let mut reader = FrameReader::new(msg);
+        let kind = reader.u8()?;
+        let mut state = self.state.lock().expect("Unable to get lock");
+        match kind {
+            LOG_UPDATE => {
+                let epoch = reader.usize()?;
+                let seq = reader.usize()?;
+                let update = StateUpdate::read(&mut reader)?;
+                reader.finish()?;
+                if epoch < state.epoch {
+                    self.reject_stale(epoch, state.epoch, from);
+                    return Ok(false);
+                }
+                if epoch == state.epoch && seq <= state.seq {
+                    // A retransmission of an update that was already applied,
+                    // which only needs to be acknowledged again
+                } else if seq == state.seq + 1 {
+                    state.epoch = epoch;
+                    state.apply(&update);
+                    state.compact(self.cluster.history());
+                } else {
+                    self.logger.trace(format!(
+                        "Missed updates, got {} after {}, asking for them again",
+                        seq, state.seq
+                    ));
+                    self.send_to(&AlgloboNode::ids_to_msg(MSG_RESEND, &[self.id]), from);
+                    return Ok(true);
+                }
+                self.logger.trace(format!(
+                    "Received last log: Last status is {} for transaction {}",
+                    update.status as char, update.id
+                ));
+            }
+            LOG_SNAPSHOT => {
+                let version = (reader.usize()?, reader.usize()?);
+                let part = reader.u32()? as usize;
+                let count = reader.u32()? as usize;
+                if part >= count || count > MAX_SNAPSHOT_PARTS {
+                    return Err(FrameError::InvalidPart { part, count });
+                }
+                let bytes = reader.bytes(reader.remaining())?;
+                let snapshot = match self
+                    .snapshot_parts
+                    .lock()
+                    .expect("Unable to get lock")
+                    .add(version, part, count, bytes)
+                {
+                    Some(snapshot) => CoordinatorState::from_bytes(&snapshot)?,
+                    // The rest of the parts are still on their way
+                    None => return Ok(true),
+                };
+                if snapshot.epoch < state.epoch {
+                    self.reject_stale(snapshot.epoch, state.epoch, from);
+                    return Ok(false);
+                }
+                // A snapshot that arrives late, after updates applied on top of
+                // it, would roll them back
+                if snapshot.version() > state.version() {
+                    *state = snapshot;
+                    self.logger.trace(format!("Received snapshot: {}", state));
+                } else {
+                    self.logger.trace(format!(
+                        "Ignored snapshot of update {}, already at update {}",
+                        snapshot.seq, state.seq
+                    ));
+                }
+            }
+            LOG_HEARTBEAT => {
+                let epoch = reader.usize()?;
+                reader.finish()?;
+                if epoch < state.epoch {
+                    self.reject_stale(epoch, state.epoch, from);
+                    return Ok(false);
+                }
+                return Ok(true);
+            }
+            _ => {
+                self.logger.info("Got unknown data message".to_string());
+                return Ok(false);
+            }
+        }
+        self.send_to(
+            &AlgloboNode::ids_to_msg(MSG_LOG_ACK, &[state.epoch, state.seq, self.id]),
+            from,
+        );
+        Ok(true)
+    }
+
+    /// Tells the sender of a data message from an older epoch about the known one
+    fn reject_stale(&self, epoch: usize, known_epoch: usize, from: SocketAddr) {
+        self.logger.info(format!(
+            "Rejected data message from stale epoch {} (current is {})",
+            epoch, known_epoch
+        ));
+        self.send_to(&AlgloboNode::ids_to_msg(MSG_STALE, &[known_epoch]), from);
+    }
+
     /// Asks itself... am I the leader?
     /// Do I exist?
     /// Is any of this even real?
@@ -0,0 +1,217 @@
+//! BullyElection struct
+//!
+//! Bully election algorithm: a node sends an ELECTION message with its candidacy
+//! (its id, how up to date its state is and its priority) to every node. The nodes
+//! that rank higher, being more up to date or else having a higher priority or
+//! id, answer it. If none of them answers in time, it becomes the leader and sends
+//! a COORDINATOR message to every node. If one of them answers, that node takes
+//! over the election, and this node waits for its COORDINATOR message.
+//!
+//! Unlike the ring, dead nodes are waited for in parallel, so the election takes
+//! about the same time no matter how many of them are down.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::alglobo_node::{AlgloboNode, MSG_COORDINATOR, MSG_ELECTION};
+use crate::election::{rank, Election, CANDIDACY_FIELDS};
+
+/// Control message answering an ELECTION from a node with a lower id
+pub const MSG_ANSWER: u8 = b'O';
+/// Time to wait for an answer from any node with a higher id
+pub const ANSWER_TIMEOUT: Duration = Duration::from_secs(1);
+/// Field of a COORDINATOR message sent after a planned handover, which nodes
+/// that rank higher accept instead of bullying the sender
+const HANDOVER: usize = 1;
+/// Time to wait for the COORDINATOR message once a higher node answered
+pub const COORDINATOR_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// BullyElection struct
+pub struct BullyElection {
+    /// Flag set when a node with a higher id answers, with a lock and a condvar
+    answered: (Mutex<bool>, Condvar),
+    /// Whether this node is already running an election
+    running: AtomicBool,
+}
+
+impl BullyElection {
+    /// Creates the BullyElection
+    pub fn new() -> BullyElection {
+        BullyElection {
+            answered: (Mutex::new(false), Condvar::new()),
+            running: AtomicBool::new(false),
+        }
+    }
+
+    /// Runs rounds of the election until there is a leader
+    fn run(&self, node: &AlgloboNode) {
+        node.clear_leader();
+        while !node.is_stopped() {
+            *self.answered.0.lock().expect("Unable to get lock") = false;
+
+            let msg = AlgloboNode::ids_to_msg(MSG_ELECTION, &node.candidacy());
+            for id in node.cluster().ids() {
+                if id != node.id() {
+                    node.send_to(&msg, node.cluster().ctrl_addr(id));
+                }
+            }
+
+            let (answered, _timeout) = self
+                .answered
+                .1
+                .wait_timeout_while(
+                    self.answered.0.lock().expect("Unable to get lock"),
+                    ANSWER_TIMEOUT,
+                    |answered| !*answered,
+                )
+                .expect("Unable to wait for condvar");
+            if !*answered {
+                node.logger()
+                    .trace("No higher node answered, I am the coordinator".to_string());
+                let msg = AlgloboNode::ids_to_msg(MSG_COORDINATOR, &node.candidacy());
+                for id in node.cluster().ids() {
+                    if id != node.id() {
+                        node.send_to(&msg, node.cluster().ctrl_addr(id));
+                    }
+                }
+                node.set_leader(node.id());
+                return;
+            }
+            drop(answered);
+
+            if node.wait_leader(COORDINATOR_TIMEOUT).is_some() {
+                return;
+            }
+            node.logger()
+                .trace("No COORDINATOR after an answer, restarting election".to_string());
+        }
+    }
+
+    /// Whether the candidacy of another node ranks lower than the one of this node
+    fn outranks(own: &[usize], candidacy: &[usize]) -> bool {
+        rank(candidacy) < rank(own)
+    }
+
+    /// Starts an election on a new thread, answering the ELECTION of a lower node
+    fn spawn_election(node: &AlgloboNode) {
+        let clone = node.clone();
+        thread::Builder::new()
+            .name(format!("Node{}-Bully", node.id()))
+            .spawn(move || clone.start_election())
+            .expect("node election thread creation failed");
+    }
+}
+
+impl Default for BullyElection {
+    fn default() -> Self {
+        BullyElection::new()
+    }
+}
+
+impl Election for BullyElection {
+    fn name(&self) -> &'static str {
+        "bully"
+    }
+
+    fn start(&self, node: &AlgloboNode) {
+        if self.running.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.run(node);
+        self.running.store(false, Ordering::SeqCst);
+    }
+
+    fn handle(&self, node: &AlgloboNode, msg_type: u8, ids: Vec<usize>, from: SocketAddr) -> bool {
+        match msg_type {
+            MSG_ELECTION => {
+                node.logger()
+                    .trace(format!("Got ELECTION from {} with ids {:?}", from, ids));
+                if BullyElection::outranks(&node.candidacy(), &ids) {
+                    node.send_to(&AlgloboNode::ids_to_msg(MSG_ANSWER, &[node.id()]), from);
+                    BullyElection::spawn_election(node);
+                }
+                true
+            }
+            MSG_ANSWER => {
+                node.logger()
+                    .trace(format!("Got ANSWER from {} with ids {:?}", from, ids));
+                *self.answered.0.lock().expect("Unable to get lock") = true;
+                self.answered.1.notify_all();
+                true
+            }
+            MSG_COORDINATOR => {
+                node.logger()
+                    .trace(format!("Got COORDINATOR from {} with ids {:?}", from, ids));
+                if BullyElection::outranks(&node.candidacy(), &ids) && ids.get(3) != Some(&HANDOVER)
+                {
+                    // A lower node can't lead while this one is alive, so we bully it
+                    BullyElection::spawn_election(node);
+                } else {
+                    node.set_leader(ids[0]);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// An ELECTION has the candidacy of its sender, an ANSWER the id of its
+    /// sender, and a COORDINATOR the candidacy of the leader, marked or not as a
+    /// handover
+    fn valid_fields(&self, msg_type: u8, fields: &[usize]) -> bool {
+        match msg_type {
+            MSG_ELECTION => fields.len() == CANDIDACY_FIELDS,
+            MSG_ANSWER => fields.len() == 1,
+            MSG_COORDINATOR => {
+                fields.len() == CANDIDACY_FIELDS || fields.len() == CANDIDACY_FIELDS + 1
+            }
+            _ => true,
+        }
+    }
+
+    /// Announces the new leader to every node, marked as a handover so that
+    /// nodes with a higher id accept it
+    fn announce(&self, node: &AlgloboNode) {
+        let mut msg = node.candidacy();
+        msg.push(HANDOVER);
+        let msg = AlgloboNode::ids_to_msg(MSG_COORDINATOR, &msg);
+        for id in node.cluster().ids() {
+            if id != node.id() {
+                node.send_to(&msg, node.cluster().ctrl_addr(id));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn more_up_to_date_nodes_outrank_the_rest() {
+        assert!(BullyElection::outranks(&[0, 9, 0], &[4, 8, 5]));
+        assert!(!BullyElection::outranks(&[4, 8, 5], &[0, 9, 0]));
+    }
+
+    #[test]
+    fn ties_break_by_priority_and_then_by_id() {
+        assert!(BullyElection::outranks(&[1, 9, 2], &[4, 9, 1]));
+        assert!(BullyElection::outranks(&[4, 9, 1], &[1, 9, 1]));
+        assert!(!BullyElection::outranks(&[1, 9, 1], &[4, 9, 1]));
+        assert!(!BullyElection::outranks(&[1, 9, 1], &[1, 9, 1]));
+    }
+
+    #[test]
+    fn coordinators_may_be_handovers() {
+        let bully = BullyElection::new();
+        assert!(bully.valid_fields(MSG_ELECTION, &[1, 9, 0]));
+        assert!(!bully.valid_fields(MSG_ELECTION, &[1, 9, 0, 1]));
+        assert!(bully.valid_fields(MSG_ANSWER, &[1]));
+        assert!(bully.valid_fields(MSG_COORDINATOR, &[1, 9, 0]));
+        assert!(bully.valid_fields(MSG_COORDINATOR, &[1, 9, 0, HANDOVER]));
+        assert!(!bully.valid_fields(MSG_COORDINATOR, &[1]));
+    }
+}
@@ -0,0 +1,217 @@
+//! ClusterConfig struct
+//!
+//! Membership of the AlgloboNode cluster: which nodes exist and where each of
+//! them listens. Every node and the alglobo process read the same file.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use crate::node_config::NodeConfig;
+
+/// Cluster config file
+pub const CLUSTER_FILE: &str = "src/cluster.yaml";
+
+/// ClusterConfig struct
+#[derive(Debug, Clone)]
+pub struct ClusterConfig {
+    /// Every node of the cluster, sorted by id
+    nodes: Vec<NodeConfig>,
+    /// Name of the leader election algorithm: ring, bully or raft
+    election: String,
+    /// Amount of nodes, counting the leader, that must store a state update
+    /// before the leader moves on
+    quorum: usize,
+    /// Whether the leader only waits for the reachable nodes when fewer than
+    /// the quorum are, instead of waiting for the quorum
+    degraded_quorum: bool,
+    /// Interval between the heartbeats of the leader
+    heartbeat_interval: Duration,
+    /// Suspicion level of the failure detector above which the leader is dead
+    phi_threshold: f64,
+    /// Whether invalid payments are quarantined instead of rejecting the file
+    quarantine: bool,
+    /// Amount of decided transactions kept in the replicated state, as the
+    /// older ones are only kept in the outcome ledger
+    history: usize,
+}
+
+impl ClusterConfig {
+    /// Parses a cluster yaml config file
+    pub fn from_file(filename: &str) -> ClusterConfig {
+        let file = std::fs::File::open(filename).expect("Couldn't open cluster config file");
+        let config: serde_yaml::Value =
+            serde_yaml::from_reader(file).expect("Couldn't parse cluster config yaml");
+
+        let mut nodes: Vec<NodeConfig> = config["nodes"]
+            .as_sequence()
+            .expect("Cluster config must have a list of nodes")
+            .iter()
+            .map(NodeConfig::from_yaml)
+            .collect();
+        nodes.sort_by_key(|node| node.id);
+
+        if nodes.is_empty() {
+            panic!("Cluster config must have at least one node");
+        }
+        if nodes.windows(2).any(|pair| pair[0].id == pair[1].id) {
+            panic!("Cluster config has repeated node ids");
+        }
+        if let Some(size) = config["size"].as_u64() {
+            if size as usize != nodes.len() {
+                panic!(
+                    "Cluster size is {} but {} nodes are configured",
+                    size,
+                    nodes.len()
+                );
+            }
+        }
+
+        let election = config["election"].as_str().unwrap_or("ring").to_string();
+
+        let quorum = config["quorum"]
+            .as_u64()
+            .map_or(nodes.len() / 2 + 1, |quorum| quorum as usize);
+        if quorum == 0 || quorum > nodes.len() {
+            panic!(
+                "Quorum must be between 1 and the cluster size, but it is {}",
+                quorum
+            );
+        }
+
+        let degraded_quorum = config["degraded_quorum"].as_bool().unwrap_or(false);
+
+        let heartbeat_interval =
+            Duration::from_millis(config["heartbeat_ms"].as_u64().unwrap_or(500));
+        if heartbeat_interval.as_millis() == 0 {
+            panic!("Heartbeat interval must be greater than zero");
+        }
+        let phi_threshold = config["phi_threshold"].as_f64().unwrap_or(8.0);
+        let quarantine = match config["invalid_payments"].as_str().unwrap_or("reject") {
+            "reject" => false,
+            "quarantine" => true,
+            other => panic!(
+                "Invalid payments must be \"reject\" or \"quarantine\", but it is {}",
+                other
+            ),
+        };
+
+        let history = config["history"].as_u64().unwrap_or(500) as usize;
+        if history == 0 {
+            panic!("History must keep at least one transaction");
+        }
+
+        ClusterConfig {
+            nodes,
+            election,
+            quorum,
+            degraded_quorum,
+            heartbeat_interval,
+            phi_threshold,
+            quarantine,
+            history,
+        }
+    }
+
+    /// The amount of nodes in the cluster
+    pub fn size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Every node id, in ascending order
+    pub fn ids(&self) -> Vec<usize> {
+        self.nodes.iter().map(|node| node.id).collect()
+    }
+
+    /// Name of the leader election algorithm
+    pub fn election(&self) -> &str {
+        &self.election
+    }
+
+    /// Amount of nodes, counting the leader, that must store a state update
+    pub fn quorum(&self) -> usize {
+        self.quorum
+    }
+
+    /// Whether the leader only waits for the reachable nodes when fewer than
+    /// the quorum are
+    pub fn degraded_quorum(&self) -> bool {
+        self.degraded_quorum
+    }
+
+    /// Interval between the heartbeats of the leader
+    pub fn heartbeat_interval(&self) -> Duration {
+        self.heartbeat_interval
+    }
+
+    /// Suspicion level of the failure detector above which the leader is dead
+    pub fn phi_threshold(&self) -> f64 {
+        self.phi_threshold
+    }
+
+    /// Whether invalid payments are quarantined instead of rejecting the file
+    pub fn quarantine(&self) -> bool {
+        self.quarantine
+    }
+
+    /// Amount of decided transactions kept in the replicated state
+    pub fn history(&self) -> usize {
+        self.history
+    }
+
+    /// Whether a node with the given id is part of the cluster
+    pub fn contains(&self, id: usize) -> bool {
+        self.nodes.iter().any(|node| node.id == id)
+    }
+
+    /// Socket used for receiving leader election/coordination messages
+    pub fn ctrl_addr(&self, id: usize) -> SocketAddr {
+        self.node(id).ctrl
+    }
+
+    /// Socket used for receiving transaction information from the leader
+    pub fn data_addr(&self, id: usize) -> SocketAddr {
+        self.node(id).data
+    }
+
+    /// Address the node binds its control socket to
+    pub fn ctrl_bind_addr(&self, id: usize) -> SocketAddr {
+        self.node(id).ctrl_bind
+    }
+
+    /// Address the node binds its data socket to
+    pub fn data_bind_addr(&self, id: usize) -> SocketAddr {
+        self.node(id).data_bind
+    }
+
+    /// Priority of the node to become the leader among equally up to date nodes
+    pub fn priority(&self, id: usize) -> usize {
+        self.node(id).priority
+    }
+
+    /// Highest priority of any node of the cluster
+    pub fn max_priority(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.priority)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Get the id of the node that follows the given one in the ring,
+    /// going back to the lowest id after the highest one.
+    pub fn next(&self, id: usize) -> usize {
+        self.nodes
+            .iter()
+            .find(|node| node.id > id)
+            .unwrap_or(&self.nodes[0])
+            .id
+    }
+
+    /// Returns the config of the node with the given id
+    fn node(&self, id: usize) -> &NodeConfig {
+        self.nodes
+            .iter()
+            .find(|node| node.id == id)
+            .unwrap_or_else(|| panic!("Node {} is not part of the cluster", id))
+    }
+}
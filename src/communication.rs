@@ -1,6 +1,7 @@
 //! Several definitions regarding the alglobo<->agents communication protocol
 
 use std::convert::TryInto;
+use std::io::{self, Read};
 
 /// Transaction Message for the first phase: preparing
 pub const PREPARE: u8 = b'P';
@@ -17,41 +18,66 @@ pub const ACK: u8 = 1;
 pub const PAYMENT_ERR: u8 = 0;
 /// Message when a payment was accepted
 pub const PAYMENT_OK: u8 = 1;
+/// Message when the epoch of the sender is older than one the agent already saw
+pub const STALE_EPOCH: u8 = 2;
 
-/// The number of bytes needed in a DataMsg array
-pub type DataMsgBytes = [u8; 9];
+/// Longest payment id a DataMsg can have, so that a corrupted length doesn't
+/// make the agent allocate any amount of memory
+pub const MAX_PAYMENT_ID: usize = 1024;
 
-/// 9 byte message to communicate from alglobo to the agents
+/// The number of bytes of a DataMsg before the id of its payment
+pub type DataMsgBytes = [u8; 17];
+
+/// Message to communicate from alglobo to the agents: 17 bytes followed by the
+/// id of the payment
 pub struct DataMsg {
-    /// 4 bytes id of the transaction to operate on
-    pub transaction_id: u32,
+    /// Id of the payment to operate on, which is the same on every attempt
+    pub payment_id: String,
     /// 4 bytes for the transaction payment
     pub data: u32,
     /// 1 byte for the transaction operation
     pub opcode: u8,
+    /// 8 bytes for the epoch of the leader that sent it
+    pub epoch: u64,
 }
 
 impl DataMsg {
-    /// Translate a 9 byte array into a DataMsg structure
-    pub fn from_bytes(msg: DataMsgBytes) -> DataMsg {
-        let transaction_id: u32 =
-            u32::from_be_bytes(msg[0..4].try_into().expect("Couldn't convert to u32"));
-        let data: u32 = u32::from_be_bytes(msg[4..8].try_into().expect("Couldn't convert to u32"));
-        let opcode: u8 = msg[8];
-
-        DataMsg {
-            transaction_id,
+    /// Reads a DataMsg from a stream: its first 17 bytes, which end with the
+    /// length of the payment id, and then the payment id. Fails if the stream
+    /// ends before the message does or the payment id is too long.
+    pub fn read_from(reader: &mut impl Read) -> io::Result<DataMsg> {
+        let mut msg: DataMsgBytes = Default::default();
+        reader.read_exact(&mut msg)?;
+        let data: u32 = u32::from_be_bytes(msg[0..4].try_into().expect("Couldn't convert to u32"));
+        let opcode: u8 = msg[4];
+        let epoch: u64 =
+            u64::from_be_bytes(msg[5..13].try_into().expect("Couldn't convert to u64"));
+        let length = u32::from_be_bytes(msg[13..17].try_into().expect("Couldn't convert to u32"));
+        if length as usize > MAX_PAYMENT_ID {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("payment id of {} bytes is too long", length),
+            ));
+        }
+        let mut payment_id = vec![0; length as usize];
+        reader.read_exact(&mut payment_id)?;
+
+        Ok(DataMsg {
+            payment_id: String::from_utf8_lossy(&payment_id).to_string(),
             data,
             opcode,
-        }
+            epoch,
+        })
     }
 
-    /// Translate a DataMsg structure into a 9 byte array
-    pub fn to_bytes(data_msg: &DataMsg) -> DataMsgBytes {
+    /// Translate a DataMsg structure into an array of bytes
+    pub fn to_bytes(data_msg: &DataMsg) -> Vec<u8> {
         let mut bytes = Vec::new();
-        bytes.extend(data_msg.transaction_id.to_be_bytes());
         bytes.extend(data_msg.data.to_be_bytes());
-        bytes.push(data_msg.opcode as u8);
-        bytes.try_into().expect("Couldn't form bytearray")
+        bytes.push(data_msg.opcode);
+        bytes.extend(data_msg.epoch.to_be_bytes());
+        bytes.extend((data_msg.payment_id.len() as u32).to_be_bytes());
+        bytes.extend(data_msg.payment_id.as_bytes());
+        bytes
     }
 }
@@ -0,0 +1,178 @@
+//! ControlMsg struct
+//!
+//! Message sent between nodes on the control socket, used for the leader
+//! election, the coordinator announcements, the acks and the kill messages.
+//! Every message is framed as:
+//! - 1 byte with the version of the format
+//! - 1 byte with the message type
+//! - 2 bytes with the amount of fields, big endian
+//! - 8 bytes for each field, big endian
+//!
+//! So the format doesn't depend on the platform, and the receiver checks the
+//! length of the message against its header before reading any field.
+
+use std::convert::TryInto;
+
+use crate::control_msg_error::ControlMsgError;
+
+/// Version of the format, increased on every incompatible change
+pub const CONTROL_VERSION: u8 = 1;
+/// Bytes of the header: version, type and amount of fields
+const HEADER_LEN: usize = 4;
+/// Bytes of each field
+const FIELD_LEN: usize = 8;
+/// Maximum amount of fields of a message, so that it fits in a UDP datagram
+pub const MAX_FIELDS: usize = 8000;
+
+/// ControlMsg struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControlMsg {
+    /// Type of the message
+    pub kind: u8,
+    /// Fields of the message, which depend on its type
+    pub fields: Vec<usize>,
+}
+
+impl ControlMsg {
+    /// Translate the message into an array of bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.fields.len() > MAX_FIELDS {
+            panic!(
+                "Control message has {} fields, the maximum is {}",
+                self.fields.len(),
+                MAX_FIELDS
+            );
+        }
+        let mut bytes = vec![CONTROL_VERSION, self.kind];
+        bytes.extend((self.fields.len() as u16).to_be_bytes());
+        for field in &self.fields {
+            bytes.extend((*field as u64).to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Translate an array of bytes into the message, failing if it isn't
+    /// a well formed message of the current version
+    pub fn from_bytes(bytes: &[u8]) -> Result<ControlMsg, ControlMsgError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(ControlMsgError::Truncated(bytes.len()));
+        }
+        if bytes[0] != CONTROL_VERSION {
+            return Err(ControlMsgError::UnknownVersion(bytes[0]));
+        }
+        let count = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        let expected = HEADER_LEN + count * FIELD_LEN;
+        if bytes.len() != expected {
+            return Err(ControlMsgError::LengthMismatch {
+                expected,
+                actual: bytes.len(),
+            });
+        }
+
+        let fields = bytes[HEADER_LEN..]
+            .chunks(FIELD_LEN)
+            .map(|field| {
+                let field = u64::from_be_bytes(field.try_into().expect("Field of 8 bytes"));
+                field
+                    .try_into()
+                    .map_err(|_| ControlMsgError::FieldOverflow(field))
+            })
+            .collect::<Result<Vec<usize>, ControlMsgError>>()?;
+        Ok(ControlMsg {
+            kind: bytes[1],
+            fields,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let msg = ControlMsg {
+            kind: b'E',
+            fields: vec![0, 7, usize::MAX],
+        };
+        let bytes = msg.to_bytes();
+        assert_eq!(bytes.len(), HEADER_LEN + 3 * FIELD_LEN);
+        assert_eq!(&bytes[..HEADER_LEN], &[CONTROL_VERSION, b'E', 0, 3]);
+        assert_eq!(ControlMsg::from_bytes(&bytes), Ok(msg));
+
+        let empty = ControlMsg {
+            kind: b'H',
+            fields: vec![],
+        };
+        assert_eq!(ControlMsg::from_bytes(&empty.to_bytes()), Ok(empty));
+    }
+
+    #[test]
+    fn truncated_header() {
+        assert_eq!(
+            ControlMsg::from_bytes(&[]),
+            Err(ControlMsgError::Truncated(0))
+        );
+        assert_eq!(
+            ControlMsg::from_bytes(&[CONTROL_VERSION, b'E', 0]),
+            Err(ControlMsgError::Truncated(3))
+        );
+    }
+
+    #[test]
+    fn unknown_version() {
+        let mut bytes = ControlMsg {
+            kind: b'E',
+            fields: vec![1],
+        }
+        .to_bytes();
+        bytes[0] = CONTROL_VERSION + 1;
+        assert_eq!(
+            ControlMsg::from_bytes(&bytes),
+            Err(ControlMsgError::UnknownVersion(CONTROL_VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn length_mismatch() {
+        let bytes = ControlMsg {
+            kind: b'E',
+            fields: vec![1, 2],
+        }
+        .to_bytes();
+        assert_eq!(
+            ControlMsg::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(ControlMsgError::LengthMismatch {
+                expected: 20,
+                actual: 19
+            })
+        );
+        let mut longer = bytes.clone();
+        longer.push(0);
+        assert_eq!(
+            ControlMsg::from_bytes(&longer),
+            Err(ControlMsgError::LengthMismatch {
+                expected: 20,
+                actual: 21
+            })
+        );
+        // A header that claims more fields than the datagram has
+        assert_eq!(
+            ControlMsg::from_bytes(&[CONTROL_VERSION, b'E', 0xff, 0xff]),
+            Err(ControlMsgError::LengthMismatch {
+                expected: HEADER_LEN + 0xffff * FIELD_LEN,
+                actual: 4
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "maximum")]
+    fn too_many_fields() {
+        ControlMsg {
+            kind: b'E',
+            fields: vec![0; MAX_FIELDS + 1],
+        }
+        .to_bytes();
+    }
+}
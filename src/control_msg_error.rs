@@ -0,0 +1,57 @@
+//! ControlMsgError enum
+//!
+//! Reasons why a control message received by a node can't be decoded.
+
+use std::error::Error;
+use std::fmt;
+
+/// ControlMsgError enum
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlMsgError {
+    /// The message is shorter than the header
+    Truncated(usize),
+    /// The message was encoded with another version of the format
+    UnknownVersion(u8),
+    /// The length of the message doesn't match its amount of fields
+    LengthMismatch {
+        /// Length expected from the amount of fields in the header
+        expected: usize,
+        /// Actual length of the message
+        actual: usize,
+    },
+    /// A field doesn't fit in the integers of this platform
+    FieldOverflow(u64),
+    /// The message doesn't have the amount of fields its kind needs
+    FieldCount {
+        /// Kind of the message
+        kind: u8,
+        /// Amount of fields it has
+        count: usize,
+    },
+}
+
+impl fmt::Display for ControlMsgError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ControlMsgError::Truncated(len) => {
+                write!(f, "message of {} bytes is shorter than the header", len)
+            }
+            ControlMsgError::UnknownVersion(version) => {
+                write!(f, "unknown format version {}", version)
+            }
+            ControlMsgError::LengthMismatch { expected, actual } => write!(
+                f,
+                "message has {} bytes but its header says {}",
+                actual, expected
+            ),
+            ControlMsgError::FieldOverflow(field) => {
+                write!(f, "field {} doesn't fit in this platform", field)
+            }
+            ControlMsgError::FieldCount { kind, count } => {
+                write!(f, "message {} can't have {} fields", *kind as char, count)
+            }
+        }
+    }
+}
+
+impl Error for ControlMsgError {}
@@ -0,0 +1,427 @@
+//! CoordinatorState struct
+//!
+//! State of the payments processing that the leader replicates to every node,
+//! so that any of them can take over as coordinator with no loss, and any of
+//! them can report the same status as the leader.
+
+use std::fmt;
+
+use crate::communication::{ABORT, COMMIT, PREPARE};
+use crate::frame_error::FrameError;
+use crate::frame_reader::FrameReader;
+use crate::outcome::Outcome;
+use crate::state_update::{StateUpdate, SOURCE, VOTES};
+
+/// CoordinatorState struct
+#[derive(Debug, Clone, Default)]
+pub struct CoordinatorState {
+    /// Number of the last transaction with a known status
+    pub last_id: usize,
+    /// Id of the payment of the last transaction
+    pub last_payment: String,
+    /// Last known status (PREPARE, VOTES, COMMIT or ABORT) of the last transaction,
+    /// or 0 if no transaction was started yet
+    pub last_status: u8,
+    /// Index of each agent involved in the last transaction
+    pub participants: Vec<usize>,
+    /// Prices of the last transaction, for each agent involved
+    pub prices: Vec<u32>,
+    /// Vote of each agent involved in the last transaction, once all of them answered
+    pub votes: Vec<u8>,
+    /// Milliseconds since the Unix epoch when the last transaction was prepared
+    pub started: u64,
+    /// Outcome of the last finished transactions, which make up the end of the
+    /// outcome ledger, and of the failure ledger written to the retry file
+    /// with the aborted ones
+    pub outcomes: Vec<Outcome>,
+    /// Payment id of the finished transactions compacted out of `outcomes`,
+    /// oldest first, whose rows are only kept in the outcome ledger. Their ids
+    /// are kept so that they aren't decided again by a leader whose ledger
+    /// misses them.
+    pub compacted: Vec<String>,
+    /// Amount of the compacted transactions that were aborted
+    pub compacted_failed: usize,
+    /// Fingerprint of the payments file being processed, or 0 if not known yet
+    pub source: u32,
+    /// Amount of rows of the payments file
+    pub rows: usize,
+    /// Epoch of the latest leader, which is higher for every new leader
+    pub epoch: usize,
+    /// Sequence number of the last applied update
+    pub seq: usize,
+}
+
+impl CoordinatorState {
+    /// Applies an update of the state
+    pub fn apply(&mut self, update: &StateUpdate) {
+        self.seq += 1;
+        match update.status {
+            SOURCE => {
+                self.source = update.data[0];
+                self.rows = update.id;
+                return;
+            }
+            PREPARE => {
+                // The data has the index of each agent involved followed by its price
+                self.participants = update.data.chunks(2).map(|pair| pair[0] as usize).collect();
+                self.prices = update.data.chunks(2).map(|pair| pair[1]).collect();
+                self.votes.clear();
+                self.started = update.time;
+            }
+            VOTES => self.votes = update.data.iter().map(|vote| *vote as u8).collect(),
+            // A payment is only decided once, even if its decision is replicated again
+            COMMIT | ABORT if !self.is_processed(&update.payment) => {
+                self.outcomes.push(Outcome {
+                    payment: update.payment.clone(),
+                    participants: self.participants.clone(),
+                    prices: self.prices.clone(),
+                    votes: self.votes.clone(),
+                    decision: update.status,
+                    started: self.started,
+                    finished: update.time,
+                    node: update.data.first().copied().unwrap_or_default() as usize,
+                });
+            }
+            _ => {}
+        }
+        self.last_id = update.id;
+        self.last_payment = update.payment.clone();
+        self.last_status = update.status;
+    }
+
+    /// Returns the id of the transaction that is still in flight, if the
+    /// last one was prepared but not finished
+    pub fn in_flight(&self) -> Option<usize> {
+        match self.last_status {
+            PREPARE | VOTES => Some(self.last_id),
+            _ => None,
+        }
+    }
+
+    /// Returns the id and the decision of the last transaction, if it was
+    /// decided, since the agents may not have got it yet
+    pub fn decided(&self) -> Option<(usize, u8)> {
+        match self.last_status {
+            COMMIT | ABORT => Some((self.last_id, self.last_status)),
+            _ => None,
+        }
+    }
+
+    /// Epoch and sequence number of the last applied update, which only grow
+    /// as updates are applied, to tell which of two states is newer
+    pub fn version(&self) -> (usize, usize) {
+        (self.epoch, self.seq)
+    }
+
+    /// Returns the number of the next transaction to start
+    pub fn next_id(&self) -> usize {
+        self.compacted.len() + self.outcomes.len()
+    }
+
+    /// Compacts the oldest outcomes so that at most `history` of them are kept
+    pub fn compact(&mut self, history: usize) {
+        if self.outcomes.len() <= history {
+            return;
+        }
+        let count = self.outcomes.len() - history;
+        for outcome in self.outcomes.drain(..count) {
+            self.compacted.push(outcome.payment);
+            if outcome.decision == ABORT {
+                self.compacted_failed += 1;
+            }
+        }
+    }
+
+    /// Whether the payment with the id was decided
+    pub fn is_processed(&self, payment: &str) -> bool {
+        self.outcomes
+            .iter()
+            .any(|outcome| outcome.payment == payment)
+            || self.compacted.iter().any(|compacted| compacted == payment)
+    }
+
+    /// Payment id of every decided transaction, compacted or not
+    pub fn decided_payments(&self) -> impl Iterator<Item = &String> {
+        self.compacted
+            .iter()
+            .chain(self.outcomes.iter().map(|outcome| &outcome.payment))
+    }
+
+    /// Outcomes of the aborted transactions, which make up the failure ledger
+    pub fn failed(&self) -> impl Iterator<Item = &Outcome> {
+        self.outcomes
+            .iter()
+            .filter(|outcome| outcome.decision == ABORT)
+    }
+
+    /// Translate the state into an array of bytes, with fixed-width big endian numbers
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.last_status];
+        for value in &[
+            self.last_id,
+            self.epoch,
+            self.seq,
+            self.rows,
+            self.started as usize,
+            self.compacted_failed,
+        ] {
+            bytes.extend((*value as u64).to_be_bytes());
+        }
+        bytes.extend(self.source.to_be_bytes());
+        write_string(&mut bytes, &self.last_payment);
+
+        write_prices(&mut bytes, &self.participants, &self.prices);
+        bytes.extend((self.votes.len() as u64).to_be_bytes());
+        bytes.extend(&self.votes);
+        bytes.extend((self.outcomes.len() as u64).to_be_bytes());
+        for outcome in &self.outcomes {
+            write_string(&mut bytes, &outcome.payment);
+            write_prices(&mut bytes, &outcome.participants, &outcome.prices);
+            bytes.extend((outcome.votes.len() as u64).to_be_bytes());
+            bytes.extend(&outcome.votes);
+            bytes.push(outcome.decision);
+            for value in &[outcome.started, outcome.finished, outcome.node as u64] {
+                bytes.extend(value.to_be_bytes());
+            }
+        }
+        bytes.extend((self.compacted.len() as u64).to_be_bytes());
+        for payment in &self.compacted {
+            write_string(&mut bytes, payment);
+        }
+        bytes
+    }
+
+    /// Translate an array of bytes into the state, which has to take the
+    /// whole array
+    pub fn from_bytes(bytes: &[u8]) -> Result<CoordinatorState, FrameError> {
+        let mut reader = FrameReader::new(bytes);
+        let mut state = CoordinatorState {
+            last_status: reader.u8()?,
+            last_id: reader.usize()?,
+            epoch: reader.usize()?,
+            seq: reader.usize()?,
+            rows: reader.usize()?,
+            started: reader.u64()?,
+            compacted_failed: reader.usize()?,
+            source: reader.u32()?,
+            last_payment: reader.string()?,
+            ..Default::default()
+        };
+
+        let (participants, prices) = read_prices(&mut reader)?;
+        state.participants = participants;
+        state.prices = prices;
+        let count = reader.count(1)?;
+        state.votes = reader.bytes(count)?.to_vec();
+        // Each outcome has at least the lengths of its payment, prices and
+        // votes, its decision and three numbers
+        let count = reader.count(8 * 3 + 1 + 8 * 3)?;
+        for _ in 0..count {
+            let payment = reader.string()?;
+            let (participants, prices) = read_prices(&mut reader)?;
+            let votes = reader.count(1)?;
+            state.outcomes.push(Outcome {
+                payment,
+                participants,
+                prices,
+                votes: reader.bytes(votes)?.to_vec(),
+                decision: reader.u8()?,
+                started: reader.u64()?,
+                finished: reader.u64()?,
+                node: reader.usize()?,
+            });
+        }
+        // Each compacted id has at least its length
+        let count = reader.count(8)?;
+        for _ in 0..count {
+            state.compacted.push(reader.string()?);
+        }
+        reader.finish()?;
+        Ok(state)
+    }
+}
+
+impl fmt::Display for CoordinatorState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.last_status {
+            0 => '-',
+            status => status as char,
+        };
+        write!(
+            f,
+            "transaction {} {} {} | {} of {} rows processed, {} failed | epoch {}, update {}",
+            self.last_id,
+            self.last_payment,
+            status,
+            self.next_id(),
+            self.rows,
+            self.compacted_failed + self.failed().count(),
+            self.epoch,
+            self.seq
+        )
+    }
+}
+
+/// Writes the amount of agents involved in a transaction, followed by the
+/// index of each one and its price
+fn write_prices(bytes: &mut Vec<u8>, participants: &[usize], prices: &[u32]) {
+    bytes.extend((participants.len() as u64).to_be_bytes());
+    for (agent, price) in participants.iter().zip(prices) {
+        bytes.extend((*agent as u64).to_be_bytes());
+        bytes.extend(price.to_be_bytes());
+    }
+}
+
+/// Reads the agents involved in a transaction and their prices
+fn read_prices(reader: &mut FrameReader) -> Result<(Vec<usize>, Vec<u32>), FrameError> {
+    let count = reader.count(8 + 4)?;
+    (0..count)
+        .map(|_| Ok((reader.usize()?, reader.u32()?)))
+        .collect()
+}
+
+/// Writes the length of a string followed by its bytes
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend((string.len() as u64).to_be_bytes());
+    bytes.extend(string.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state() -> CoordinatorState {
+        let mut state = CoordinatorState {
+            epoch: 2,
+            ..Default::default()
+        };
+        let updates = vec![
+            StateUpdate {
+                data: vec![0xdead_beef],
+                ..StateUpdate::new(SOURCE, 3, "")
+            },
+            StateUpdate {
+                data: vec![0, 121, 1, 507],
+                ..StateUpdate::new(PREPARE, 0, "PAY-001")
+            },
+            StateUpdate {
+                data: vec![1, 0],
+                ..StateUpdate::new(VOTES, 0, "PAY-001")
+            },
+            StateUpdate {
+                data: vec![4],
+                ..StateUpdate::new(ABORT, 0, "PAY-001")
+            },
+            StateUpdate {
+                data: vec![2, 673],
+                ..StateUpdate::new(PREPARE, 1, "PAY-002")
+            },
+            StateUpdate {
+                data: vec![4],
+                ..StateUpdate::new(COMMIT, 1, "PAY-002")
+            },
+            StateUpdate {
+                data: vec![0, 4, 2, 152],
+                ..StateUpdate::new(PREPARE, 2, "PAY-003")
+            },
+        ];
+        for update in &updates {
+            state.apply(update);
+        }
+        state
+    }
+
+    #[test]
+    fn applies_updates() {
+        let mut state = state();
+        assert_eq!(state.source, 0xdead_beef);
+        assert_eq!(state.rows, 3);
+        assert_eq!(state.seq, 7);
+        assert_eq!(state.in_flight(), Some(2));
+        assert_eq!(state.outcomes.len(), 2);
+        assert_eq!(state.outcomes[0].votes, vec![1, 0]);
+        assert_eq!(state.failed().count(), 1);
+
+        state.compact(1);
+        assert_eq!(state.compacted, vec!["PAY-001".to_string()]);
+        assert_eq!(state.compacted_failed, 1);
+        assert_eq!(state.outcomes[0].payment, "PAY-002");
+        assert_eq!(state.next_id(), 2);
+        assert!(state.is_processed("PAY-001") && state.is_processed("PAY-002"));
+        assert!(!state.is_processed("PAY-003"));
+        assert_eq!(state.decided_payments().count(), 2);
+
+        // A compacted payment isn't decided again
+        state.apply(&StateUpdate::new(COMMIT, 2, "PAY-001"));
+        assert_eq!(state.next_id(), 2);
+    }
+
+    #[test]
+    fn newer_states_have_higher_versions() {
+        let old = state();
+        let mut newer = old.clone();
+        newer.apply(&StateUpdate::new(ABORT, 2, "PAY-003"));
+        assert!(newer.version() > old.version());
+
+        // A new leader's state is newer even if it applied fewer updates
+        let new_leader = CoordinatorState {
+            epoch: old.epoch + 1,
+            seq: 1,
+            ..Default::default()
+        };
+        assert!(new_leader.version() > newer.version());
+        assert_eq!(old.clone().version(), old.version());
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut state = state();
+        state.compact(1);
+        let bytes = state.to_bytes();
+        let decoded = CoordinatorState::from_bytes(&bytes).expect("Valid state");
+        assert_eq!(decoded.to_bytes(), bytes);
+        assert_eq!(decoded.outcomes, state.outcomes);
+        assert_eq!(decoded.participants, state.participants);
+        assert_eq!(decoded.prices, state.prices);
+        assert_eq!(decoded.last_payment, "PAY-003");
+        assert_eq!(decoded.compacted, state.compacted);
+        assert_eq!((decoded.compacted_failed, decoded.epoch), (1, 2));
+
+        let empty = CoordinatorState::default().to_bytes();
+        let decoded = CoordinatorState::from_bytes(&empty).expect("Valid state");
+        assert_eq!(decoded.to_bytes(), empty);
+    }
+
+    #[test]
+    fn truncated_bytes() {
+        let bytes = state().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(
+                CoordinatorState::from_bytes(&bytes[..len]).is_err(),
+                "{}",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = state().to_bytes();
+        bytes.extend(&[0, 0]);
+        assert_eq!(
+            CoordinatorState::from_bytes(&bytes).err(),
+            Some(FrameError::TrailingBytes(2))
+        );
+    }
+
+    #[test]
+    fn counts_beyond_the_frame() {
+        // The amount of outcomes is the last field of a state without them
+        let mut bytes = CoordinatorState::default().to_bytes();
+        let len = bytes.len();
+        for count in &[1, u32::MAX as u64, u64::MAX] {
+            bytes[len - 8..].copy_from_slice(&count.to_be_bytes());
+            assert!(CoordinatorState::from_bytes(&bytes).is_err(), "{}", count);
+        }
+    }
+}
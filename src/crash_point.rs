@@ -0,0 +1,53 @@
+//! Crash injection
+//!
+//! Points of the commit of a transaction where the leader can be made to crash,
+//! to check that a new leader finishes it the same way on every agent. The point
+//! is chosen with the `ALGLOBO_CRASH_AT` environment variable, and the first
+//! leader of the process that reaches it crashes. Crashes are only injected
+//! when built with the `crash-points` feature, so the leader never checks for
+//! them otherwise.
+
+#[cfg(feature = "crash-points")]
+use std::env;
+#[cfg(feature = "crash-points")]
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Environment variable with the point where the leader crashes
+pub const CRASH_AT_VAR: &str = "ALGLOBO_CRASH_AT";
+
+/// After the votes are replicated, before the decision is
+pub const BEFORE_DECISION: &str = "before-decision";
+/// After the decision is replicated, before any agent gets it
+pub const BEFORE_PHASE_TWO: &str = "before-phase-two";
+/// After only the first agent got the decision
+pub const DURING_PHASE_TWO: &str = "during-phase-two";
+/// After every agent got the decision, before the next transaction starts
+pub const AFTER_PHASE_TWO: &str = "after-phase-two";
+
+/// Every crash point, in the order the leader goes through them
+pub const CRASH_POINTS: [&str; 4] = [
+    BEFORE_DECISION,
+    BEFORE_PHASE_TWO,
+    DURING_PHASE_TWO,
+    AFTER_PHASE_TWO,
+];
+
+/// Set once a node of the process crashed, so that the new leader doesn't crash
+/// at the same point again and the payments can be processed
+#[cfg(feature = "crash-points")]
+static CRASHED: AtomicBool = AtomicBool::new(false);
+
+/// Returns true if the leader has to crash at this point
+#[cfg(feature = "crash-points")]
+pub fn crash_at(point: &str) -> bool {
+    match env::var(CRASH_AT_VAR) {
+        Ok(crash_point) if crash_point == point => !CRASHED.swap(true, Ordering::SeqCst),
+        _ => false,
+    }
+}
+
+/// The leader never crashes without the `crash-points` feature
+#[cfg(not(feature = "crash-points"))]
+pub fn crash_at(_point: &str) -> bool {
+    false
+}
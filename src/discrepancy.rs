@@ -0,0 +1,120 @@
+//! Discrepancy enum
+//!
+//! Ways in which the outcome ledger of the coordinator and the records of the
+//! agents can disagree about a transaction.
+
+use std::fmt;
+
+/// Discrepancy enum
+#[derive(Debug, Clone, PartialEq)]
+pub enum Discrepancy {
+    /// A line of the outcome ledger can't be read
+    UnreadableLine(usize),
+    /// The outcome ledger has the same payment id twice
+    RepeatedOutcome(String),
+    /// Some agents committed a payment while others aborted it
+    AgentsDisagree {
+        /// Id of the payment
+        payment: String,
+        /// Decision of the outcome ledger, if it has the payment
+        decision: Option<String>,
+        /// Agents that committed it
+        committed: Vec<String>,
+        /// Agents that aborted it
+        aborted: Vec<String>,
+    },
+    /// An agent ended up with a different decision than the outcome ledger
+    ConflictingDecision {
+        /// Id of the payment
+        payment: String,
+        /// Decision of the outcome ledger
+        decision: String,
+        /// Agent with the other decision
+        agent: String,
+        /// Decision of the agent
+        state: String,
+    },
+    /// An agent prepared a payment but never got a decision for it
+    StuckInPrepare {
+        /// Id of the payment
+        payment: String,
+        /// Agent that prepared it
+        agent: String,
+    },
+    /// An agent involved in a committed payment has no record of it
+    MissingAtAgent {
+        /// Id of the payment
+        payment: String,
+        /// Agent without the payment
+        agent: String,
+    },
+    /// An agent committed a payment that isn't in the outcome ledger
+    UnknownCommit {
+        /// Id of the payment
+        payment: String,
+        /// Agent that committed it
+        agent: String,
+    },
+    /// A payment of the outcome ledger involves an agent that isn't in the
+    /// agents config
+    UnknownParticipant {
+        /// Id of the payment
+        payment: String,
+        /// Index of the agent
+        agent: usize,
+    },
+}
+
+impl fmt::Display for Discrepancy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Discrepancy::UnreadableLine(line) => {
+                write!(f, "line {} of the outcome ledger can't be read", line)
+            }
+            Discrepancy::RepeatedOutcome(payment) => {
+                write!(f, "{}: the outcome ledger has it more than once", payment)
+            }
+            Discrepancy::AgentsDisagree {
+                payment,
+                decision,
+                committed,
+                aborted,
+            } => write!(
+                f,
+                "{}: {} committed but {} aborted, the outcome ledger says {}",
+                payment,
+                committed.join(", "),
+                aborted.join(", "),
+                decision.as_deref().unwrap_or("nothing")
+            ),
+            Discrepancy::ConflictingDecision {
+                payment,
+                decision,
+                agent,
+                state,
+            } => write!(
+                f,
+                "{}: the outcome ledger says {} but {} has {}",
+                payment, decision, agent, state
+            ),
+            Discrepancy::StuckInPrepare { payment, agent } => {
+                write!(f, "{}: {} is stuck in PREPARE", payment, agent)
+            }
+            Discrepancy::MissingAtAgent { payment, agent } => write!(
+                f,
+                "{}: committed in the outcome ledger but {} has no record of it",
+                payment, agent
+            ),
+            Discrepancy::UnknownCommit { payment, agent } => write!(
+                f,
+                "{}: {} committed it but the outcome ledger doesn't have it",
+                payment, agent
+            ),
+            Discrepancy::UnknownParticipant { payment, agent } => write!(
+                f,
+                "{}: the outcome ledger involves agent {}, which isn't in the agents config",
+                payment, agent
+            ),
+        }
+    }
+}
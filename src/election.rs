@@ -0,0 +1,138 @@
+//! Election trait
+//!
+//! Common interface of the leader election algorithms an AlgloboNode can use.
+//! The algorithm is picked with the `election` key of the cluster config file.
+//!
+//! The algorithm also decides how the coordinator state reaches the replicas.
+//! By default the leader sends every change on the data socket, but an algorithm
+//! like Raft replaces it with its own replicated log.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::alglobo_node::AlgloboNode;
+use crate::bully_election::BullyElection;
+use crate::logger::Logger;
+use crate::raft_election::RaftElection;
+use crate::ring_election::RingElection;
+use crate::state_update::StateUpdate;
+
+/// Election trait
+pub trait Election: Send + Sync {
+    /// Name of the algorithm, used for logging
+    fn name(&self) -> &'static str;
+
+    /// Starts a new election from the given node. The election ends when the
+    /// node sets its leader, which the caller waits for.
+    fn start(&self, node: &AlgloboNode);
+
+    /// Handles an election message received on the control socket of the node.
+    /// Returns false if the message type isn't part of this algorithm.
+    fn handle(&self, node: &AlgloboNode, msg_type: u8, ids: Vec<usize>, from: SocketAddr) -> bool;
+
+    /// Whether a message of the algorithm has the fields its type needs, so
+    /// that the node drops malformed messages before handling them. Messages of
+    /// types that aren't part of the algorithm are left to `handle`.
+    fn valid_fields(&self, msg_type: u8, fields: &[usize]) -> bool;
+
+    /// Replicates an update of the coordinator state from the leader. Returns
+    /// once the leader can act on it, or false if it is no longer the leader.
+    fn replicate(&self, node: &AlgloboNode, update: &StateUpdate) -> bool {
+        node.apply_state(update);
+        node.broadcast_last_log(update)
+    }
+
+    /// Waits as a replica for news from the leader. Returns false if the
+    /// leader is considered dead.
+    fn follow(&self, node: &AlgloboNode) -> bool {
+        node.receive_from_leader()
+    }
+
+    /// Brings a node that joined the cluster up to date, called on the leader
+    fn catch_up(&self, node: &AlgloboNode, id: usize) {
+        node.send_snapshot(id);
+    }
+
+    /// Returns the epoch of a node that just became the leader, higher than
+    /// the epoch of every previous leader
+    fn new_epoch(&self, node: &AlgloboNode) -> usize {
+        node.next_epoch()
+    }
+
+    /// The replica a leader hands over to when none is chosen, which has to
+    /// be reachable and as up to date as possible
+    fn best_replica(&self, node: &AlgloboNode) -> Option<usize> {
+        node.best_replica()
+    }
+
+    /// Hands over the place of the leader to the target node, as a planned
+    /// handover. Returns true once the node is no longer the leader.
+    fn hand_over(&self, node: &AlgloboNode, target: usize) -> bool {
+        node.hand_over_state(target)
+    }
+
+    /// Lets every node know that this one took over as leader after a
+    /// planned handover
+    fn announce(&self, node: &AlgloboNode);
+
+    /// Periodic work of the algorithm, like the heartbeats of a leader. Called
+    /// from its own thread of the node every tick.
+    fn tick(&self, node: &AlgloboNode) {
+        node.heartbeat();
+    }
+}
+
+/// Amount of fields of the candidacy of a node: its id, the sequence number of
+/// its last state update and its priority
+pub const CANDIDACY_FIELDS: usize = 3;
+
+/// Orders the candidacies of the nodes to pick the leader: the most up to date
+/// node wins, then the one with the highest priority, and then the one with the
+/// highest id. A missing field ranks lowest.
+pub fn rank(candidacy: &[usize]) -> (usize, usize, usize) {
+    let field = |i: usize| candidacy.get(i).copied().unwrap_or(0);
+    (field(1), field(2), field(0))
+}
+
+/// Whether the fields of a message are one or more whole candidacies
+pub fn is_candidacies(fields: &[usize]) -> bool {
+    !fields.is_empty() && fields.len() % CANDIDACY_FIELDS == 0
+}
+
+/// Creates the election algorithm with the given name for the given node
+pub fn new_election(name: &str, id: usize, logger: &Logger) -> Arc<dyn Election> {
+    match name {
+        "ring" => Arc::new(RingElection {}),
+        "bully" => Arc::new(BullyElection::new()),
+        "raft" => Arc::new(RaftElection::new(id, logger)),
+        _ => panic!("Unknown election algorithm {}", name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranks_by_updates_then_priority_then_id() {
+        let mut candidacies = [[4, 10, 0], [1, 12, 0], [2, 12, 1], [3, 12, 1]];
+        candidacies.sort_by_key(|candidacy| rank(candidacy));
+        assert_eq!(candidacies[3], [3, 12, 1]);
+        assert_eq!(candidacies[0], [4, 10, 0]);
+    }
+
+    #[test]
+    fn missing_fields_rank_lowest() {
+        assert_eq!(rank(&[]), (0, 0, 0));
+        assert_eq!(rank(&[5]), (0, 0, 5));
+        assert!(rank(&[5, 1]) > rank(&[6]));
+    }
+
+    #[test]
+    fn candidacies_must_be_whole() {
+        assert!(is_candidacies(&[1, 2, 3]));
+        assert!(is_candidacies(&[1, 2, 3, 4, 5, 6]));
+        assert!(!is_candidacies(&[]));
+        assert!(!is_candidacies(&[1, 2, 3, 4]));
+    }
+}
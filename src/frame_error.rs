@@ -0,0 +1,53 @@
+//! FrameError enum
+//!
+//! Reasons why a binary frame received from another process, like a state
+//! update or a snapshot, can't be decoded.
+
+use std::error::Error;
+use std::fmt;
+
+/// FrameError enum
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    /// The frame ends before a field it announces
+    Truncated {
+        /// Length the frame needs to hold the field
+        needed: usize,
+        /// Actual length of the frame
+        actual: usize,
+    },
+    /// A length or count doesn't fit in the integers of this platform
+    FieldOverflow(u64),
+    /// The frame has bytes left after its last field
+    TrailingBytes(usize),
+    /// The frame is a part of a message split in an invalid amount of parts
+    InvalidPart {
+        /// Index of the part
+        part: usize,
+        /// Amount of parts of the message
+        count: usize,
+    },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FrameError::Truncated { needed, actual } => write!(
+                f,
+                "frame has {} bytes but needs at least {}",
+                actual, needed
+            ),
+            FrameError::FieldOverflow(field) => {
+                write!(f, "field {} doesn't fit in this platform", field)
+            }
+            FrameError::TrailingBytes(count) => {
+                write!(f, "frame has {} bytes after its last field", count)
+            }
+            FrameError::InvalidPart { part, count } => {
+                write!(f, "frame is part {} of {}", part, count)
+            }
+        }
+    }
+}
+
+impl Error for FrameError {}
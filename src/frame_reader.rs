@@ -0,0 +1,152 @@
+//! FrameReader struct
+//!
+//! Reads the fields of a binary frame in order, checking that each one is
+//! within the frame, so that a short or corrupted frame is an error instead of
+//! a panic. Numbers are fixed-width big endian, and strings and lists are
+//! preceded by their length as a u64.
+
+use std::convert::{TryFrom, TryInto};
+
+use crate::frame_error::FrameError;
+
+/// FrameReader struct
+pub struct FrameReader<'a> {
+    /// Bytes of the frame
+    bytes: &'a [u8],
+    /// Position of the next field
+    pos: usize,
+}
+
+impl<'a> FrameReader<'a> {
+    /// Creates a reader at the start of the frame
+    pub fn new(bytes: &'a [u8]) -> FrameReader<'a> {
+        FrameReader { bytes, pos: 0 }
+    }
+
+    /// Reads the next `length` bytes
+    pub fn bytes(&mut self, length: usize) -> Result<&'a [u8], FrameError> {
+        let end = self
+            .pos
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(FrameError::Truncated {
+                needed: self.pos.saturating_add(length),
+                actual: self.bytes.len(),
+            })?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    /// Reads a byte
+    pub fn u8(&mut self) -> Result<u8, FrameError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Reads a big endian u32
+    pub fn u32(&mut self) -> Result<u32, FrameError> {
+        let bytes = self.bytes(4)?.try_into().expect("Slice of 4 bytes");
+        Ok(u32::from_be_bytes(bytes))
+    }
+
+    /// Reads a big endian u64
+    pub fn u64(&mut self) -> Result<u64, FrameError> {
+        let bytes = self.bytes(8)?.try_into().expect("Slice of 8 bytes");
+        Ok(u64::from_be_bytes(bytes))
+    }
+
+    /// Reads a big endian u64 that has to fit in a usize
+    pub fn usize(&mut self) -> Result<usize, FrameError> {
+        let value = self.u64()?;
+        usize::try_from(value).map_err(|_| FrameError::FieldOverflow(value))
+    }
+
+    /// Reads the amount of items of a list, each one at least `item_len` bytes
+    /// long, checking that the rest of the frame can hold them before anything
+    /// is allocated for them
+    pub fn count(&mut self, item_len: usize) -> Result<usize, FrameError> {
+        let count = self.usize()?;
+        let needed = count
+            .checked_mul(item_len)
+            .and_then(|length| length.checked_add(self.pos))
+            .ok_or(FrameError::FieldOverflow(count as u64))?;
+        if needed > self.bytes.len() {
+            return Err(FrameError::Truncated {
+                needed,
+                actual: self.bytes.len(),
+            });
+        }
+        Ok(count)
+    }
+
+    /// Reads a string preceded by its length
+    pub fn string(&mut self) -> Result<String, FrameError> {
+        let length = self.count(1)?;
+        Ok(String::from_utf8_lossy(self.bytes(length)?).to_string())
+    }
+
+    /// Amount of bytes left to read
+    pub fn remaining(&self) -> usize {
+        self.bytes.len() - self.pos
+    }
+
+    /// Checks that every byte of the frame was read
+    pub fn finish(&self) -> Result<(), FrameError> {
+        match self.remaining() {
+            0 => Ok(()),
+            count => Err(FrameError::TrailingBytes(count)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_fields_in_order() {
+        let mut bytes = vec![7];
+        bytes.extend(9u32.to_be_bytes());
+        bytes.extend(3u64.to_be_bytes());
+        bytes.extend(b"abc");
+        let mut reader = FrameReader::new(&bytes);
+        assert_eq!(reader.u8(), Ok(7));
+        assert_eq!(reader.u32(), Ok(9));
+        assert_eq!(reader.string(), Ok("abc".to_string()));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn fields_beyond_the_frame() {
+        let mut reader = FrameReader::new(&[1, 2, 3]);
+        assert_eq!(
+            reader.u32(),
+            Err(FrameError::Truncated {
+                needed: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(reader.u8(), Ok(1));
+        assert_eq!(reader.finish(), Err(FrameError::TrailingBytes(2)));
+        assert!(reader.bytes(usize::MAX).is_err());
+    }
+
+    #[test]
+    fn counts_beyond_the_frame() {
+        let bytes = [u64::MAX.to_be_bytes(), 2u64.to_be_bytes()].concat();
+        assert_eq!(
+            FrameReader::new(&bytes).count(2),
+            Err(FrameError::FieldOverflow(u64::MAX))
+        );
+        assert_eq!(FrameReader::new(&bytes).count(0), Ok(usize::MAX));
+        let mut reader = FrameReader::new(&bytes[8..]);
+        assert_eq!(
+            reader.count(1),
+            Err(FrameError::Truncated {
+                needed: 10,
+                actual: 8
+            })
+        );
+        assert_eq!(FrameReader::new(&bytes[8..]).string().ok(), None);
+    }
+}
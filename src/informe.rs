@@ -16,8 +16,12 @@
 //!
 //! Por un lado se debe levantar el sistema de alglobo que será el encargado de resolver todo el procesamiento de pagos y enviárselo a cada uno de los agentes en cuestión. De forma opcional, se puede pasar por parámetro un archivo txt con los diferentes precios a cobrar, de no agregar este parámetro, se utilizará el archivo default `src/prices.csv`. Para levantarlo: `cargo run --bin alglobo <archivo>`
 //!
+//! De esta forma todas las réplicas corren como threads de un mismo proceso, lo cual resulta cómodo para demostraciones. Para que cada réplica sea un proceso independiente (y pueda caerse por su cuenta, por ejemplo con una señal), se levanta un proceso por réplica: `cargo run --bin alglobo -- --node-id <id> [--cluster <archivo>] <archivo>`. Todos los procesos deben compartir el mismo archivo de configuración del cluster.
+//!
 //! Por otro lado, se debe levantar el sistema de agentes (Banco, Aerolínea y Hotel) que se encargaran de recibir y procesar el pago. Para levantarlo: `cargo run --bin agents`
 //!
+//! Una vez terminada la corrida, `cargo run --bin informe` imprime un reporte de la misma a partir de los logs (ver [Reporte de la corrida](#reporte-de-la-corrida)).
+//!
 //! ### Supuestos
 //!
 //! - Al tratar las transacciones con el método de commit de dos fases, se asume que si un nodo de alglobo falla:
@@ -26,7 +30,7 @@
 //!     - Durante la segunda fase (no logra imprimir COMMIT/ABORT), el siguiente nodo de alglobo ABORTA esa transacción.
 //!     - Tras finalizar la segunda fase, significa que se completó la transacción y el siguiente nodo podrá seguir con la siguiente transacción.
 //! - Una vez que finalizan las líneas del archivo, se cierran ambos sistemas.
-//! - Las transacciones resultarán en ABORT si alguno de los agentes se encuentra caído.
+//! - Las transacciones resultarán en ABORT si alguno de los agentes se encuentra caído y todavía ninguno de sus backups tomó su lugar.
 //!
 //! ### Implementación
 //!
@@ -39,7 +43,7 @@
 //! Una vez que se enciende el sistema, la terminal se queda a la espera de que el usuario ingrese un número, el identificador de la réplica, para poder simular la salida de servicio de la misma, mostrando que el sistema en su conjunto sigue funcionando.
 //! Esto se resuelve con el **algoritmo Ring**. Para resolver con este algoritmo, fue importante que cada réplica conozca el identificador de la siguiente réplica y el identificador de la réplica líder (la réplica que se mantiene activa y resuelve el procesamiento de pagos).
 //!
-//! Al conocer el identificador, el mismo va a poder conocer la dirección a la cual enviar los mensajes, ya que las mismas dependen de este. Cada réplica cuenta con dos sockets para recibir información, una para control y todo los relacionado al algoritmo de elección por los que va a enviar y recibir mensajes del tipo ELECTION, COORDINATOR, ACK (por tratarse de UDP) y otro de data por el cuál el lider enviará información de los pagos procesados a las réplicas para que estén actualizadas.
+//! Al conocer el identificador, el mismo va a poder conocer la dirección a la cual enviar los mensajes, ya que las direcciones de cada réplica se definen en el archivo de configuración del cluster (`src/cluster.yaml`), junto con el tamaño del mismo. El orden del anillo lo dan los identificadores de menor a mayor. Cada réplica cuenta con dos sockets para recibir información, una para control y todo los relacionado al algoritmo de elección por los que va a enviar y recibir mensajes del tipo ELECTION, COORDINATOR, ACK (por tratarse de UDP) y otro de data por el cuál el lider enviará información de los pagos procesados a las réplicas para que estén actualizadas.
 //!
 //! Todas estas direcciones se van a utilizar para enviar y recibir mensajes vía Socket UDP en torno a resolver el problema de disponibilidad del servicio:
 //!
@@ -51,11 +55,37 @@
 //!
 //! 4. Cuando el mensaje COORDINATOR finaliza la circulación, todas las réplicas estarán al tanto del nuevo líder.
 //!
+//! Como alternativa al anillo se puede usar el **algoritmo Bully** (`election: "bully"` en el archivo del cluster): la réplica que detecta la caída del líder envía ELECTION a las réplicas con mejor candidatura y, si ninguna responde en un segundo, se proclama líder y envía COORDINATOR a todas. Cada réplica loguea cuánto tardó cada elección que inició.
+//!
+//! En todos los algoritmos gana la réplica con mejor **candidatura**: la más actualizada, luego la de mayor prioridad (`priority` en `cluster.yaml`) y luego la de mayor identificador.
+//!
+//! Una tercera opción es **Raft** (`election: "raft"`), que además de elegir al líder replica el log del coordinador: el líder solo actúa sobre cada estado de una transacción una vez que lo guardó una mayoría de las réplicas. Cada réplica guarda su término, su voto y su log en el directorio `raft/`. El cluster necesita una mayoría de réplicas vivas para avanzar.
+//!
+//! Cada líder toma una **época** mayor a las anteriores (con Raft, el término), que viaja en cada mensaje a los agentes y a las réplicas. Un líder al que se le rechaza un mensaje por tener una época vieja deja de serlo y vuelve a unirse al cluster.
+//!
+//! Las actualizaciones del estado llevan un **número de secuencia**, y el líder las reenvía hasta que las guardó el `quorum` configurado (una mayoría si no se indica). Una réplica que detecta una actualización salteada pide el estado completo con RESEND.
+//!
+//! El líder envía un **heartbeat** cada `heartbeat_ms` milisegundos, y cada réplica lo considera caído cuando el detector **phi accrual** supera el `phi_threshold` configurado.
+//!
+//! Si sobrevive **una sola réplica**, esta se proclama líder y loguea que el cluster está degradado. Si el quorum no guarda una actualización en 10 segundos, el líder deja de serlo; con `degraded_quorum: true` sigue procesando pagos con las réplicas que responden (no disponible con Raft).
+//!
+//! Las direcciones de las réplicas y de los agentes pueden tener cualquier host, y cada proceso puede escuchar en una dirección distinta a la que usan los demás, por lo que pueden correr en distintas máquinas. El programa de agentes puede levantar solo algunos agentes o réplicas con `--agent` y `--replica`.
+//!
+//! Los mensajes de control usan un formato binario versionado que no depende de la plataforma; los mensajes mal formados se descartan y se loguean.
+//!
+//! Para cambiar de líder a propósito se escribe `d <líder> [<destino>]` en la terminal: el líder termina la transacción en curso y le traspasa el liderazgo a la réplica indicada o a la más actualizada, sin abortar ningún pago.
+//!
+//! Una réplica que se inicia (o se reinicia luego de haber sido dada de baja) envía JOIN a las demás; si existe un líder, este le responde y le envía el estado del coordinador, sin necesidad de una nueva elección.
+//!
 //! ##### Procesamiento de pagos
 //!
 //! El sistema de alglobo debe encargarse de resolver todo el procesamiento de pagos y enviárselo a cada uno de los agentes en cuestión. Para ello se abre una conexión UDP para cada uno de los procesos.
 //!
-//! En el caso de ser líder, el proceso se encargará de leer una línea a la vez del archivo pasado por parámetro o el default `src/prices.csv`. Cada línea del archivo va a contener 3 números, siendo el primero el precio a cobrar al Banco, el segundo el de la Aerolínea y el tercero del Hotel.
+//! En el caso de ser líder, el proceso se encargará de leer una línea a la vez del archivo pasado por parámetro o el default `src/prices.csv`. La primera línea es un encabezado con una columna `id` con el identificador de cada pago y una columna por agente, con su nombre en `agents.yaml`, con el precio a cobrarle, por ejemplo `id,bank,airline,hotel`. Los agentes con la columna vacía no participan del pago, o bien se pueden enumerar en una columna opcional `agents`.
+//!
+//! Antes de procesar los pagos se valida el archivo completo y se informa el número de línea y el motivo de cada línea inválida. Según `invalid_payments` en `cluster.yaml`, se rechaza el archivo (`reject`, el valor por defecto) o se ponen esas líneas en cuarentena en `src/prices-quarantine.csv` (`quarantine`).
+//!
+//! En lugar de un archivo se puede pasar un **directorio de spool**: sus archivos `.csv` y `.jsonl` se leen en orden de nombre y el cluster sigue esperando nuevos pagos en lugar de terminar. Con `--listen <dirección>` (o `unix:<ruta>`) alglobo además recibe pagos por un socket, un encabezado y luego un pago por línea, y responde `OK <id>` una vez guardado en el spool o `ERR <motivo>` si es inválido.
 //! De manera concurrente y vía TCP se les envía a los tres agentes el precio a cobrar. Este envío se va a resolver con **commit en dos fases**:
 //!
 //! - Fase 1: El coordinador escribe el mensaje de PREPARE y lo envía a los tres agentes (con TCP). Luego emite al resto de las réplicas que se encuentra en la fase PREPARE para la transacción corresponde (con UDP por la dirección de data mencionada anteriormente).
@@ -64,9 +94,11 @@
 //!
 //! De esta forma garantizamos que las transacciones sean serializables, por lo que si se cae el coordinador, la réplica que tome su lugar va a tener la información necesaria para terminar su trabajo y continuarlo sin notar cambios en el funcionamiento del sistema.
 //!
+//! Para eso cada réplica guarda el **estado completo del coordinador**: el archivo de pagos, la transacción en curso con sus precios y votos, y las transacciones decididas. Un nuevo líder vuelve a enviar a los agentes la decisión de la última transacción, y escribiendo `s <id>` en la terminal cualquier réplica informa ese estado. El test `tests/crash_recovery.rs` mata al líder en cada punto del commit y se corre con `cargo test --features crash-points`.
+//!
 //! #### Agentes
 //!
-//! Al igual que del lado de alglobo, tras levantar el servicio de agentes la terminal se queda a la espera de que el usuario ingrese un número, el identificador del agente, para poder simular la salida de su servicio, mostrando nuevamente que el sistema en su conjunto sigue funcionando. Sin embargo, a diferencia de alglobo, los agentes no cuentan con réplicas, por lo tanto una vez que se cae uno de ellos, siempre se va a devolver ABORT.
+//! Al igual que del lado de alglobo, tras levantar el servicio de agentes la terminal se queda a la espera de que el usuario ingrese un número, el identificador del agente, para poder simular la salida de su servicio, mostrando nuevamente que el sistema en su conjunto sigue funcionando. A diferencia de alglobo, los agentes se replican con un esquema **primary-backup**: en `agents.yaml` cada agente tiene una lista de puertos, el primero del primario y el resto de sus backups, y si el primario se cae un backup toma su lugar. Mientras ningún backup lo haga, las transacciones que involucren a ese agente se abortan.
 //!
 //! La estructura **Agent** maneja la lógica básica de las transacciones, realizando COMMIT o ABORT de forma acorde, mientras que en `agents.rs` se levantan los servicios correspondientes donde cada uno tendrá una estructura **Agent** asociada.
 //!
@@ -76,5 +108,84 @@
 //!
 //! - Luego, tras recibir el mensaje de la segunda fase de alglobo, loguea COMMIT o ABORT según corresponda.
 //!
-//!
-fn main() {}
+//! #### Identificadores de pago estables
+//!
+//! Cada transacción se identifica por el `id` de su pago, que viaja a los agentes, se replica y se conserva en el archivo de reintentos. Cada id se decide una sola vez, y cada agente recuerda su voto por id, por lo que no cobra dos veces un pago que recibe de nuevo.
+//!
+//! #### Pagos en JSON Lines
+//!
+//! alglobo también lee pagos en JSON Lines: un objeto por línea con el `id`, los montos por agente en `amounts` y opcionalmente `currency` y `metadata`. El formato se elige por la extensión `.jsonl` o con `--format <csv|jsonl>`, y el archivo de reintentos se escribe en el mismo formato que la entrada.
+//!
+//! #### Ledger de resultados
+//!
+//! El líder escribe un ledger de resultados (`src/prices-outcomes.csv`, o `.jsonl` en JSON Lines) con una fila por transacción decidida: el id del pago, la decisión, el monto y el voto de cada agente, los momentos en que se preparó y se decidió, y el nodo que la decidió. Como los resultados se replican, un nuevo líder sigue escribiendo el ledger del anterior.
+//!
+//! #### Reporte de la corrida
+//!
+//! El binario `informe`, además de contener este documento, genera un reporte de una corrida a partir de los archivos `logs/*.log` que escriben los nodos y los agentes, y del archivo de reintentos: `cargo run --bin informe -- [--logs <directorio>] [--retry <archivo>] [--export <archivo.md|archivo.html>]`. El reporte se imprime como texto, y cada `--export` lo exporta en Markdown o HTML según su extensión, para sumarlo a las revisiones de incidentes. Incluye:
+//!
+//! - La cantidad de transacciones commiteadas y abortadas.
+//! - Por agente, el monto commiteado y la cantidad de pagos commiteados, abortados y votados con ERR.
+//! - La causa de cada aborto.
+//! - Cada cambio de líder con su epoch, y cada elección con su algoritmo y su duración.
+//! - Las ventanas en que un agente quedó sin primario.
+//! - Los pagos del archivo de reintentos, con la causa de su aborto.
+//!
+//! #### Conciliación
+//!
+//! El binario `reconcile` concilia el ledger de resultados con los logs de cada agente, cruzándolos por id de pago: `cargo run --bin reconcile -- [--logs <directorio>] [--ledger <archivo>]`. Reporta cada discrepancia, por ejemplo un pago que un agente commiteó y otro abortó o un agente trabado en PREPARE, y termina con código 1 si encontró alguna.
+//!
+//!
+#![forbid(unsafe_code)]
+#![allow(dead_code)]
+mod communication;
+mod coordinator_state;
+mod frame_error;
+mod frame_reader;
+mod informe_args;
+mod invalid_line;
+mod ledgers;
+mod log_line;
+pub mod logger;
+mod outcome;
+mod payment;
+mod payment_columns;
+mod payment_error;
+mod payments_format;
+mod report_format;
+mod report_table;
+mod run_report;
+mod spool_reader;
+mod state_update;
+mod utils;
+mod validation_report;
+
+use std::{env, fs};
+
+use informe_args::InformeArgs;
+use log_line::LogLine;
+use payment::Payment;
+use payments_format::PaymentsFormat;
+use report_format::ReportFormat;
+use run_report::RunReport;
+use utils::get_agents_names;
+
+/// Main function. Prints the report of the run of the logs, and exports it to
+/// the given files
+fn main() {
+    let args = InformeArgs::parse(env::args().skip(1));
+    let agents = get_agents_names();
+    let lines = LogLine::read_dir(&args.logs_dir);
+    let retry = args
+        .retry_file()
+        .map(|path| Payment::read(&path, PaymentsFormat::from_path(&path), &agents).payments)
+        .unwrap_or_default();
+
+    let report = RunReport::new(&lines, &agents, retry);
+    print!("{}", report.render(ReportFormat::Text));
+    for path in &args.exports {
+        fs::write(path, report.render(ReportFormat::from_path(path)))
+            .expect("Couldn't write the report");
+        println!("\nReport exported to {}", path);
+    }
+}
@@ -0,0 +1,53 @@
+//! InformeArgs struct
+//!
+//! Command line arguments of the informe program.
+
+use crate::ledgers::{existing_ledger, RETRY_FILE};
+use crate::logger::PREFIX_PATH;
+
+/// InformeArgs struct
+#[derive(Debug, Clone)]
+pub struct InformeArgs {
+    /// Directory with the logs of the run
+    pub logs_dir: String,
+    /// Failure ledger of the run, or None to use the default one if it exists
+    pub retry_file: Option<String>,
+    /// Files to export the report to, in the format of their extension
+    pub exports: Vec<String>,
+}
+
+impl InformeArgs {
+    /// Parses the arguments of the program, where `args` doesn't include the
+    /// program name. Accepts `--logs <dir>`, `--retry <file>` and any amount of
+    /// `--export <file.md|file.html>`.
+    pub fn parse(args: impl Iterator<Item = String>) -> InformeArgs {
+        let mut parsed = InformeArgs {
+            logs_dir: PREFIX_PATH.to_string(),
+            retry_file: None,
+            exports: vec![],
+        };
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--logs" => parsed.logs_dir = args.next().expect("--logs needs a value"),
+                "--retry" => {
+                    parsed.retry_file = Some(args.next().expect("--retry needs a value"));
+                }
+                "--export" => parsed
+                    .exports
+                    .push(args.next().expect("--export needs a value")),
+                _ => panic!("Unknown argument {}", arg),
+            }
+        }
+        parsed
+    }
+
+    /// Failure ledger of the run: the one given with `--retry`, or else the
+    /// default one in CSV or JSON Lines, if there is one
+    pub fn retry_file(&self) -> Option<String> {
+        self.retry_file
+            .clone()
+            .or_else(|| existing_ledger(RETRY_FILE))
+    }
+}
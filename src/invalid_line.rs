@@ -0,0 +1,62 @@
+//! InvalidLine struct
+//!
+//! A line of a payments file that can't be processed, and why.
+
+use crate::payment_error::PaymentError;
+
+/// InvalidLine struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidLine {
+    /// Payments file of the line
+    pub file: String,
+    /// Number of the line, counting from 1
+    pub number: usize,
+    /// Contents of the line
+    pub content: String,
+    /// Reason why the line is invalid
+    pub error: PaymentError,
+}
+
+impl InvalidLine {
+    /// Header of the quarantine file
+    pub fn csv_header() -> String {
+        "file,line,error,content".to_string()
+    }
+
+    /// Line of the quarantine file for the invalid line, quoting its fields
+    pub fn to_csv_line(&self) -> String {
+        [
+            self.file.clone(),
+            self.number.to_string(),
+            self.error.to_string(),
+            self.content.clone(),
+        ]
+        .iter()
+        .map(|field| format!("\"{}\"", field.replace('"', "\"\"")))
+        .collect::<Vec<String>>()
+        .join(",")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_every_field() {
+        let line = InvalidLine {
+            file: "src/prices.csv".to_string(),
+            number: 3,
+            content: "PAY-3,\"121\",x".to_string(),
+            error: PaymentError::MissingId,
+        };
+        assert_eq!(
+            line.to_csv_line(),
+            "\"src/prices.csv\",\"3\",\"missing id\",\"PAY-3,\"\"121\"\",x\""
+        );
+        assert_eq!(
+            InvalidLine::csv_header().split(',').count(),
+            line.to_csv_line().split("\",\"").count()
+        );
+    }
+}
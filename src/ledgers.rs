@@ -0,0 +1,429 @@
+//! Ledgers struct
+//!
+//! Files the leader writes while processing payments, in the format of the
+//! payments: the outcome ledger, with a row per decided transaction, and the
+//! failure ledger (the retry file), with the aborted payments so that they can
+//! be processed again. A node that starts leading appends to the ledgers it
+//! has the replicated outcomes they miss, so a new leader keeps the rows of
+//! the previous ones. The rows of the outcomes compacted out of the replicated
+//! state can't be added, so a node whose ledgers miss them keeps them missing.
+//! The first leader of a run, or one whose ledgers have payments the cluster
+//! didn't decide, as they are from another run, starts them again instead.
+//!
+//! An outcome is recorded as soon as its decision is replicated, before the
+//! agents get it in phase two: a new leader sends them the same decision if
+//! this one dies before they do.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::path::Path;
+
+use crate::communication::ABORT;
+use crate::coordinator_state::CoordinatorState;
+use crate::outcome::Outcome;
+use crate::payment::Payment;
+use crate::payments_format::PaymentsFormat;
+use crate::utils::{create_empty_csv, open_csv_to_append, write_csv_line};
+
+/// Outcome ledger, without the extension of its format
+pub const OUTCOME_FILE: &str = "src/prices-outcomes";
+/// Failure ledger with the aborted payments, without the extension of its format
+pub const RETRY_FILE: &str = "src/prices-retry";
+
+/// Ledgers struct
+pub struct Ledgers {
+    /// Format of both ledgers
+    format: PaymentsFormat,
+    /// Name of every agent of the agents config file
+    agents: Vec<String>,
+    /// Outcome ledger
+    outcomes: File,
+    /// Failure ledger
+    retry: File,
+    /// Id of every payment in the outcome ledger
+    recorded: HashSet<String>,
+    /// Id of every payment decided by the cluster, even if it's not in the
+    /// outcome ledger
+    decided: HashSet<String>,
+}
+
+impl Ledgers {
+    /// Opens both ledgers and appends the replicated outcomes they miss,
+    /// taking the currency and metadata of each one from the payment with its id
+    pub fn new(
+        format: PaymentsFormat,
+        agents: &[String],
+        state: &CoordinatorState,
+        payments: &[Payment],
+    ) -> Ledgers {
+        Ledgers::open(
+            &format!("{}.{}", OUTCOME_FILE, format.extension()),
+            &format!("{}.{}", RETRY_FILE, format.extension()),
+            format,
+            agents,
+            state,
+            payments,
+        )
+    }
+
+    /// Opens the ledgers at the given paths, see `new`
+    fn open(
+        outcome_path: &str,
+        retry_path: &str,
+        format: PaymentsFormat,
+        agents: &[String],
+        state: &CoordinatorState,
+        payments: &[Payment],
+    ) -> Ledgers {
+        let decided: HashSet<String> = state.decided_payments().cloned().collect();
+        let recorded = match Path::new(outcome_path).exists() && state.source != 0 {
+            true => run_outcomes(outcome_path, format, agents, &decided),
+            false => None,
+        };
+
+        let mut ledgers = match recorded {
+            Some(recorded) => Ledgers {
+                format,
+                agents: agents.to_vec(),
+                outcomes: open_ledger(outcome_path, format.outcome_header(agents)),
+                retry: open_ledger(retry_path, format.header(agents)),
+                recorded,
+                decided,
+            },
+            None => {
+                let ledgers = Ledgers {
+                    format,
+                    agents: agents.to_vec(),
+                    outcomes: create_empty_csv(outcome_path),
+                    retry: create_empty_csv(retry_path),
+                    recorded: HashSet::new(),
+                    decided,
+                };
+                if let Some(header) = format.outcome_header(agents) {
+                    write_csv_line(&ledgers.outcomes, &header);
+                }
+                if let Some(header) = format.header(agents) {
+                    write_csv_line(&ledgers.retry, &header);
+                }
+                ledgers
+            }
+        };
+        for outcome in &state.outcomes {
+            if ledgers.recorded.contains(&outcome.payment) {
+                continue;
+            }
+            let payment = ledger_payment(
+                payments,
+                &outcome.payment,
+                &outcome.participants,
+                &outcome.prices,
+            );
+            ledgers.record(outcome, &payment);
+        }
+        ledgers
+    }
+
+    /// Appends the outcome of a transaction to the outcome ledger, and its
+    /// payment to the failure ledger if it was aborted
+    pub fn record(&mut self, outcome: &Outcome, payment: &Payment) {
+        self.recorded.insert(outcome.payment.clone());
+        self.decided.insert(outcome.payment.clone());
+        write_csv_line(
+            &self.outcomes,
+            &self.format.outcome_line(outcome, payment, &self.agents),
+        );
+        if outcome.decision == ABORT {
+            write_csv_line(&self.retry, &self.format.line(payment, &self.agents));
+        }
+    }
+
+    /// Whether the payment with the id was already decided, even if its
+    /// outcome was compacted and isn't in the outcome ledger
+    pub fn is_decided(&self, payment: &str) -> bool {
+        self.decided.contains(payment)
+    }
+
+    /// Amount of payments in the outcome ledger
+    pub fn recorded(&self) -> usize {
+        self.recorded.len()
+    }
+}
+
+/// Id of every payment of an existing outcome ledger, or None if the ledger is
+/// from another run: it has another header, or a payment the cluster didn't
+/// decide. Lines that can't be read are kept, as the last one may have been
+/// cut short by a crash.
+fn run_outcomes(
+    path: &str,
+    format: PaymentsFormat,
+    agents: &[String],
+    decided: &HashSet<String>,
+) -> Option<HashSet<String>> {
+    let mut recorded = HashSet::new();
+    for line in read_outcomes(path, agents) {
+        match line {
+            Ok(outcome) if decided.contains(&outcome.payment) => {
+                recorded.insert(outcome.payment);
+            }
+            Ok(_) => return None,
+            // The header is the first line
+            Err(1) if format.outcome_header(agents).is_some() => return None,
+            Err(_) => {}
+        }
+    }
+    Some(recorded)
+}
+
+/// Opens a ledger to append to it, starting it with the header if it's empty,
+/// or else ending its last line if it was cut short so that the next one
+/// starts on its own line
+fn open_ledger(path: &str, header: Option<String>) -> File {
+    let contents = fs::read(path).unwrap_or_default();
+    let file = open_csv_to_append(path);
+    match (contents.last(), header) {
+        (None, Some(header)) => write_csv_line(&file, &header),
+        (Some(byte), _) if *byte != b'\n' => write_csv_line(&file, ""),
+        _ => {}
+    }
+    file
+}
+
+/// Payment of a transaction of the replicated state, with the currency and
+/// metadata of the payment with its id if it's still among the payments read,
+/// as only the prices are replicated
+pub fn ledger_payment(
+    payments: &[Payment],
+    id: &str,
+    participants: &[usize],
+    prices: &[u32],
+) -> Payment {
+    let read = payments.iter().find(|payment| payment.id == id);
+    Payment {
+        id: id.to_string(),
+        participants: participants.to_vec(),
+        prices: prices.to_vec(),
+        currency: read.and_then(|payment| payment.currency.clone()),
+        metadata: read.and_then(|payment| payment.metadata.clone()),
+    }
+}
+
+/// Path of a ledger given its path without the extension, in CSV or else in
+/// JSON Lines, if one of them exists
+pub fn existing_ledger(ledger: &str) -> Option<String> {
+    [PaymentsFormat::Csv, PaymentsFormat::JsonLines]
+        .iter()
+        .map(|format| format!("{}.{}", ledger, format.extension()))
+        .find(|path| Path::new(path).exists())
+}
+
+/// Reads an outcome ledger in the format of its extension, with the outcome of
+/// each line, or its number counting from 1 if it can't be read
+pub fn read_outcomes(path: &str, agents: &[String]) -> Vec<Result<Outcome, usize>> {
+    let contents = fs::read_to_string(path).expect("Couldn't read outcome ledger");
+    let format = PaymentsFormat::from_path(path);
+    let mut lines = contents
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line))
+        .filter(|(_, line)| !line.trim().is_empty());
+    if let Some(header) = format.outcome_header(agents) {
+        match lines.next() {
+            Some((_, line)) if line == header => {}
+            Some((number, _)) => return vec![Err(number)],
+            None => return vec![],
+        }
+    }
+    lines
+        .map(|(number, line)| format.parse_outcome(line, agents).ok_or(number))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::{COMMIT, PAYMENT_ERR, PAYMENT_OK};
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    fn outcome(payment: &str, decision: u8) -> Outcome {
+        Outcome {
+            payment: payment.to_string(),
+            participants: vec![0, 2],
+            prices: vec![121, 433],
+            votes: vec![
+                PAYMENT_OK,
+                if decision == COMMIT {
+                    PAYMENT_OK
+                } else {
+                    PAYMENT_ERR
+                },
+            ],
+            decision,
+            started: 1_600_000_000_000,
+            finished: 1_600_000_000_250,
+            node: 4,
+        }
+    }
+
+    /// State of a run whose outcomes are the given ones, after compacting the
+    /// ones with the `compacted` ids
+    fn state(compacted: &[&str], outcomes: Vec<Outcome>) -> CoordinatorState {
+        CoordinatorState {
+            source: 0xdead_beef,
+            compacted: compacted.iter().map(|id| id.to_string()).collect(),
+            outcomes,
+            ..Default::default()
+        }
+    }
+
+    /// Paths of both ledgers of a test in the temporary directory, with the
+    /// outcome ledger holding the given outcomes
+    fn ledger_paths(test: &str, existing: &[Outcome]) -> (String, String) {
+        let dir = std::env::temp_dir();
+        let path = |ledger: &str| {
+            dir.join(format!("{}-{}-{}.csv", test, ledger, std::process::id()))
+                .to_string_lossy()
+                .into_owned()
+        };
+        let (outcome_path, retry_path) = (path("outcomes"), path("retry"));
+        let mut lines = vec![Outcome::csv_header(&agents())];
+        lines.extend(
+            existing
+                .iter()
+                .map(|outcome| outcome.to_csv_line(&agents())),
+        );
+        fs::write(&outcome_path, lines.join("\n") + "\n").expect("Ledger written");
+        let _ignore = fs::remove_file(&retry_path);
+        (outcome_path, retry_path)
+    }
+
+    /// Payment ids of the outcome ledger, with None for the lines that can't be read
+    fn ledger_ids(path: &str) -> Vec<Option<String>> {
+        read_outcomes(path, &agents())
+            .into_iter()
+            .map(|line| line.ok().map(|outcome| outcome.payment))
+            .collect()
+    }
+
+    fn ids(ids: &[&str]) -> Vec<Option<String>> {
+        ids.iter().map(|id| Some(id.to_string())).collect()
+    }
+
+    fn open(paths: &(String, String), state: &CoordinatorState) -> Ledgers {
+        Ledgers::open(
+            &paths.0,
+            &paths.1,
+            PaymentsFormat::Csv,
+            &agents(),
+            state,
+            &[],
+        )
+    }
+
+    #[test]
+    fn replicated_outcomes_are_appended() {
+        let paths = ledger_paths("appended", &[outcome("PAY-001", COMMIT)]);
+        let state = state(
+            &[],
+            vec![
+                outcome("PAY-001", COMMIT),
+                outcome("PAY-002", ABORT),
+                outcome("PAY-003", COMMIT),
+            ],
+        );
+        let mut ledgers = open(&paths, &state);
+        assert_eq!(ledgers.recorded(), 3);
+        assert!(ledgers.is_decided("PAY-002"));
+        assert!(!ledgers.is_decided("PAY-004"));
+
+        ledgers.record(
+            &outcome("PAY-004", ABORT),
+            &Payment {
+                id: "PAY-004".to_string(),
+                participants: vec![0],
+                prices: vec![121],
+                currency: None,
+                metadata: None,
+            },
+        );
+        assert!(ledgers.is_decided("PAY-004"));
+        assert_eq!(
+            ledger_ids(&paths.0),
+            ids(&["PAY-001", "PAY-002", "PAY-003", "PAY-004"])
+        );
+        let retry = fs::read_to_string(&paths.1).expect("Retry ledger written");
+        assert_eq!(
+            retry.lines().collect::<Vec<&str>>(),
+            vec!["id,bank,airline,hotel", "PAY-002,121,,433", "PAY-004,121,,"]
+        );
+    }
+
+    #[test]
+    fn compacted_outcomes_are_kept() {
+        let paths = ledger_paths("compacted", &[outcome("PAY-001", ABORT)]);
+        let state = state(&["PAY-001"], vec![outcome("PAY-002", COMMIT)]);
+        let ledgers = open(&paths, &state);
+        assert_eq!(ledger_ids(&paths.0), ids(&["PAY-001", "PAY-002"]));
+        assert_eq!(ledgers.recorded(), 2);
+
+        // A node without the ledger can't add the compacted outcome, but
+        // doesn't decide its payment again
+        fs::remove_file(&paths.0).expect("Ledger removed");
+        let ledgers = open(&paths, &state);
+        assert_eq!(ledger_ids(&paths.0), ids(&["PAY-002"]));
+        assert_eq!(ledgers.recorded(), 1);
+        assert!(ledgers.is_decided("PAY-001"));
+    }
+
+    #[test]
+    fn ledgers_of_another_run_start_again() {
+        let paths = ledger_paths("another-run", &[outcome("PAY-009", COMMIT)]);
+        let state = state(&[], vec![outcome("PAY-001", COMMIT)]);
+        open(&paths, &state);
+        assert_eq!(ledger_ids(&paths.0), ids(&["PAY-001"]));
+
+        // The first leader of a run doesn't know its source yet
+        let paths = ledger_paths("first-leader", &[outcome("PAY-001", COMMIT)]);
+        let state = CoordinatorState::default();
+        let ledgers = open(&paths, &state);
+        assert_eq!(ledger_ids(&paths.0), vec![]);
+        assert_eq!(ledgers.recorded(), 0);
+    }
+
+    #[test]
+    fn lines_cut_short_are_ended() {
+        let paths = ledger_paths("cut-short", &[outcome("PAY-001", COMMIT)]);
+        let line = outcome("PAY-002", COMMIT).to_csv_line(&agents());
+        let contents = fs::read_to_string(&paths.0).expect("Ledger written");
+        fs::write(&paths.0, contents + &line[..line.len() / 2]).expect("Ledger written");
+
+        let state = state(
+            &[],
+            vec![outcome("PAY-001", COMMIT), outcome("PAY-002", COMMIT)],
+        );
+        open(&paths, &state);
+        assert_eq!(
+            ledger_ids(&paths.0),
+            vec![
+                Some("PAY-001".to_string()),
+                None,
+                Some("PAY-002".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn existing_ledgers_in_either_format() {
+        let (path, _) = ledger_paths("existing", &[]);
+        let ledger = path.trim_end_matches(".csv");
+        assert_eq!(existing_ledger(ledger), Some(path.clone()));
+        fs::remove_file(&path).expect("Ledger removed");
+        assert_eq!(existing_ledger(ledger), None);
+    }
+}
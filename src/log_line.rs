@@ -0,0 +1,164 @@
+//! LogLine struct
+//!
+//! A line of a log file written by `Logger`, read back with the name of the log
+//! it belongs to, so that the logs of a run can be examined after it ended.
+
+use std::fs;
+
+use chrono::{DateTime, FixedOffset};
+
+use crate::logger::{SEPARATOR, TIME_FORMAT};
+
+/// Extension of the log files
+const LOG_EXTENSION: &str = "log";
+/// Prefix of the messages about a transaction, followed by its payment id
+const TRANSACTION_PREFIX: &str = "Transaction ";
+
+/// LogLine struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogLine {
+    /// Name of the log, like `node-3` or `bank-1`
+    pub log: String,
+    /// When the line was logged
+    pub time: DateTime<FixedOffset>,
+    /// Whether the line was logged with the INFO level
+    pub info: bool,
+    /// Message of the line
+    pub msg: String,
+}
+
+impl LogLine {
+    /// Parses a line of the given log, or returns None if it wasn't written by
+    /// `Logger`
+    pub fn parse(log: &str, line: &str) -> Option<LogLine> {
+        let mut fields = line.splitn(3, SEPARATOR);
+        let time = DateTime::parse_from_str(fields.next()?, TIME_FORMAT).ok()?;
+        let level = fields.next()?.trim();
+        let msg = fields.next()?.trim_end();
+        Some(LogLine {
+            log: log.to_string(),
+            time,
+            info: level == "INFO",
+            msg: msg.to_string(),
+        })
+    }
+
+    /// Reads every line of the log files of a directory, sorted by time.
+    /// Lines logged at the same time keep the order of their log.
+    pub fn read_dir(dir: &str) -> Vec<LogLine> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .expect("Couldn't read log directory")
+            .map(|entry| entry.expect("Couldn't read log directory").path())
+            .filter(|file| file.extension().is_some_and(|ext| ext == LOG_EXTENSION))
+            .collect();
+        files.sort();
+
+        let mut lines = vec![];
+        for file in files {
+            let log = file
+                .file_stem()
+                .expect("Log file without a name")
+                .to_string_lossy()
+                .to_string();
+            let contents = fs::read_to_string(&file).expect("Couldn't read log file");
+            lines.extend(
+                contents
+                    .lines()
+                    .filter_map(|line| LogLine::parse(&log, line)),
+            );
+        }
+        lines.sort_by_key(|line| line.time);
+        lines
+    }
+
+    /// Payment id and message of a line about a transaction, like
+    /// `Transaction PAY-001 | COMMIT`
+    pub fn transaction(&self) -> Option<(&str, &str)> {
+        self.msg
+            .strip_prefix(TRANSACTION_PREFIX)?
+            .split_once(SEPARATOR)
+    }
+
+    /// Index of the agent of the log among the given agent names, if it's the
+    /// log of a replica of one of them: `bank` for the primary and `bank-1` for
+    /// its first backup
+    pub fn agent(&self, agents: &[String]) -> Option<usize> {
+        agents.iter().position(|agent| {
+            self.log == *agent
+                || self
+                    .log
+                    .strip_prefix(agent.as_str())
+                    .and_then(|replica| replica.strip_prefix('-'))
+                    .is_some_and(|replica| replica.parse::<usize>().is_ok())
+        })
+    }
+
+    /// Id of the node of the log, if it's the log of an alglobo node
+    pub fn node(&self) -> Option<usize> {
+        self.log.strip_prefix("node-")?.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec!["bank".to_string(), "hotel".to_string()]
+    }
+
+    fn line(log: &str, msg: &str) -> LogLine {
+        LogLine::parse(
+            log,
+            &format!("2026-01-01 00:00:01.5 +00:00 | TRACE  | {} ", msg),
+        )
+        .expect("Line written by the logger")
+    }
+
+    #[test]
+    fn parses_the_lines_of_the_logger() {
+        let parsed = LogLine::parse(
+            "node-2",
+            "2026-01-01 00:00:01.5 +00:00 | INFO   | I am the leader ",
+        )
+        .expect("Line written by the logger");
+        assert_eq!(parsed.log, "node-2");
+        assert_eq!(parsed.time.timestamp_millis() % 1000, 500);
+        assert!(parsed.info);
+        assert_eq!(parsed.msg, "I am the leader");
+        assert!(!line("node-2", "I am the leader").info);
+        assert_eq!(LogLine::parse("node-2", "I am the leader"), None);
+        assert_eq!(LogLine::parse("node-2", "yesterday | INFO | Hi"), None);
+    }
+
+    #[test]
+    fn splits_the_lines_of_a_transaction() {
+        assert_eq!(
+            line("bank", "Transaction PAY-1 | COMMIT").transaction(),
+            Some(("PAY-1", "COMMIT"))
+        );
+        assert_eq!(
+            line("node-0", "Transaction PAY-1 | Could not connect to agent").transaction(),
+            Some(("PAY-1", "Could not connect to agent"))
+        );
+        assert_eq!(line("bank", "Transaction PAY-1").transaction(), None);
+        assert_eq!(line("bank", "Payment PAY-1 of $1 | OK").transaction(), None);
+    }
+
+    #[test]
+    fn finds_the_agent_of_every_replica() {
+        assert_eq!(line("bank", "").agent(&agents()), Some(0));
+        assert_eq!(line("hotel-2", "").agent(&agents()), Some(1));
+        assert_eq!(line("hotel-", "").agent(&agents()), None);
+        assert_eq!(line("hotel-b", "").agent(&agents()), None);
+        assert_eq!(line("banking", "").agent(&agents()), None);
+        assert_eq!(line("node-1", "").agent(&agents()), None);
+    }
+
+    #[test]
+    fn finds_the_node_of_a_log() {
+        assert_eq!(line("node-3", "").node(), Some(3));
+        assert_eq!(line("node-x", "").node(), None);
+        assert_eq!(line("bank", "").node(), None);
+    }
+}
@@ -3,7 +3,11 @@ use std::fs::OpenOptions;
 use std::io::Write;
 
 /// Log directory
-const PREFIX_PATH: &str = "logs/";
+pub const PREFIX_PATH: &str = "logs/";
+/// Format of the time at the start of every log line
+pub const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f %:z";
+/// Separator between the fields of a log line
+pub const SEPARATOR: &str = " | ";
 
 /// Simple logging levels for our logger
 #[derive(Debug, Clone, Copy)]
@@ -79,7 +83,14 @@ impl Logger {
         // So we first do some format! shenanigans to convert the debug string to a string
         let loglevelstr = format!("{:?}", loglevel);
 
-        let msg = format!("{} | {:<6} | {} \n", chrono::Local::now(), loglevelstr, msg);
+        let msg = format!(
+            "{}{}{:<6}{}{} \n",
+            chrono::Local::now().format(TIME_FORMAT),
+            SEPARATOR,
+            loglevelstr,
+            SEPARATOR,
+            msg
+        );
         file.write_all(msg.as_bytes())
             .expect("Unable to write data");
     }
@@ -0,0 +1,51 @@
+//! NodeConfig struct
+//!
+//! Membership information of a single AlgloboNode, as read from the cluster config file.
+
+use std::net::SocketAddr;
+
+use crate::utils::resolve_addr;
+
+/// NodeConfig struct
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// The id of the node
+    pub id: usize,
+    /// Address of the socket used for receiving leader election/coordination messages
+    pub ctrl: SocketAddr,
+    /// Address of the socket used for receiving transaction information from the leader
+    pub data: SocketAddr,
+    /// Address the control socket binds to, which can differ from the one the
+    /// other nodes use to reach it
+    pub ctrl_bind: SocketAddr,
+    /// Address the data socket binds to
+    pub data_bind: SocketAddr,
+    /// Priority of the node to become the leader among equally up to date nodes
+    pub priority: usize,
+}
+
+impl NodeConfig {
+    /// Parses a yaml node entry into a NodeConfig
+    pub fn from_yaml(node: &serde_yaml::Value) -> NodeConfig {
+        let ctrl = yaml_to_addr(&node["ctrl"]);
+        let data = yaml_to_addr(&node["data"]);
+        NodeConfig {
+            id: node["id"]
+                .as_u64()
+                .expect("Node id must be an unsigned integer") as usize,
+            ctrl,
+            data,
+            ctrl_bind: node.get("ctrl_bind").map_or(ctrl, yaml_to_addr),
+            data_bind: node.get("data_bind").map_or(data, yaml_to_addr),
+            priority: node["priority"].as_u64().unwrap_or(0) as usize,
+        }
+    }
+}
+
+/// Parses a yaml host:port string, where the host can be a hostname, into a socket address
+fn yaml_to_addr(addr: &serde_yaml::Value) -> SocketAddr {
+    resolve_addr(
+        addr.as_str()
+            .expect("Node address must be a host:port string"),
+    )
+}
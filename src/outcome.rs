@@ -0,0 +1,382 @@
+//! Outcome struct
+//!
+//! Record of a decided transaction, which the leader replicates along with the
+//! rest of the coordinator state and writes to the outcome ledger, with a row
+//! per transaction so that it can be reconciled against the agents.
+
+use std::convert::TryFrom;
+
+use chrono::{DateTime, Local, SecondsFormat, TimeZone};
+use serde_json::{Map, Value};
+
+use crate::communication::{ABORT, COMMIT, PAYMENT_ERR, PAYMENT_OK};
+use crate::payment::{Payment, AMOUNTS_FIELD, CURRENCY_FIELD, ID_FIELD, METADATA_FIELD};
+
+/// Column and field of the outcome ledger with the decision
+pub const DECISION_FIELD: &str = "decision";
+/// Field of a JSON outcome with the vote of each agent involved, by name
+pub const VOTES_FIELD: &str = "votes";
+/// Suffix of the column of the outcome ledger with the vote of an agent
+pub const VOTE_SUFFIX: &str = "_vote";
+/// Column and field of the outcome ledger with the start of the transaction
+pub const STARTED_FIELD: &str = "started";
+/// Column and field of the outcome ledger with the end of the transaction
+pub const FINISHED_FIELD: &str = "finished";
+/// Column and field of the outcome ledger with the node that decided
+pub const NODE_FIELD: &str = "node";
+/// Vote of an agent involved whose vote isn't known, as the leader died
+/// before replicating the votes
+pub const UNKNOWN: &str = "UNKNOWN";
+
+/// Outcome struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    /// Id of the payment of the transaction
+    pub payment: String,
+    /// Index of each agent involved
+    pub participants: Vec<usize>,
+    /// Price to charge each agent involved
+    pub prices: Vec<u32>,
+    /// Vote of each agent involved, or none if they weren't replicated
+    pub votes: Vec<u8>,
+    /// Decision of the transaction, COMMIT or ABORT
+    pub decision: u8,
+    /// Milliseconds since the Unix epoch when the transaction was prepared
+    pub started: u64,
+    /// Milliseconds since the Unix epoch when the transaction was decided
+    pub finished: u64,
+    /// Id of the node that decided the transaction
+    pub node: usize,
+}
+
+impl Outcome {
+    /// Header of the outcome ledger in CSV for the given agent names
+    pub fn csv_header(agents: &[String]) -> String {
+        let mut columns = vec![ID_FIELD.to_string(), DECISION_FIELD.to_string()];
+        columns.extend(agents.iter().cloned());
+        columns.extend(
+            agents
+                .iter()
+                .map(|agent| format!("{}{}", agent, VOTE_SUFFIX)),
+        );
+        columns.push(STARTED_FIELD.to_string());
+        columns.push(FINISHED_FIELD.to_string());
+        columns.push(NODE_FIELD.to_string());
+        columns.join(",")
+    }
+
+    /// Line of the outcome ledger in CSV, with the columns of `csv_header`.
+    /// The agents not involved have an empty price and vote.
+    pub fn to_csv_line(&self, agents: &[String]) -> String {
+        let mut fields = vec![self.payment.clone(), self.decision_name().to_string()];
+        for agent in 0..agents.len() {
+            fields.push(match self.position(agent) {
+                Some(i) => self.prices[i].to_string(),
+                None => String::new(),
+            });
+        }
+        for agent in 0..agents.len() {
+            fields.push(match self.position(agent) {
+                Some(i) => self.vote_name(i).to_string(),
+                None => String::new(),
+            });
+        }
+        fields.push(format_time(self.started));
+        fields.push(format_time(self.finished));
+        fields.push(self.node.to_string());
+        fields.join(",")
+    }
+
+    /// Line of the outcome ledger in JSON Lines, with the amounts, currency and
+    /// metadata of the payment as in the payments file, and the vote of each
+    /// agent involved by name
+    pub fn to_json_line(&self, payment: &Payment, agents: &[String]) -> String {
+        let by_agent = |value: &dyn Fn(usize) -> Value| -> Value {
+            Value::Object(
+                self.participants
+                    .iter()
+                    .enumerate()
+                    .map(|(i, agent)| (agents[*agent].clone(), value(i)))
+                    .collect(),
+            )
+        };
+        let mut fields = Map::new();
+        fields.insert(ID_FIELD.to_string(), Value::from(self.payment.clone()));
+        fields.insert(
+            DECISION_FIELD.to_string(),
+            Value::from(self.decision_name()),
+        );
+        fields.insert(
+            AMOUNTS_FIELD.to_string(),
+            by_agent(&|i| Value::from(self.prices[i])),
+        );
+        if let Some(currency) = &payment.currency {
+            fields.insert(CURRENCY_FIELD.to_string(), Value::from(currency.clone()));
+        }
+        fields.insert(
+            VOTES_FIELD.to_string(),
+            by_agent(&|i| Value::from(self.vote_name(i))),
+        );
+        fields.insert(
+            STARTED_FIELD.to_string(),
+            Value::from(format_time(self.started)),
+        );
+        fields.insert(
+            FINISHED_FIELD.to_string(),
+            Value::from(format_time(self.finished)),
+        );
+        fields.insert(NODE_FIELD.to_string(), Value::from(self.node));
+        if let Some(metadata) = &payment.metadata {
+            fields.insert(METADATA_FIELD.to_string(), metadata.clone());
+        }
+        Value::Object(fields).to_string()
+    }
+
+    /// Parses a line of an outcome ledger in CSV, with the columns of
+    /// `csv_header`, or returns None if it isn't one
+    pub fn from_csv_line(line: &str, agents: &[String]) -> Option<Outcome> {
+        let fields: Vec<&str> = line.split(',').collect();
+        if fields.len() != 2 * agents.len() + 5 {
+            return None;
+        }
+        let prices = &fields[2..2 + agents.len()];
+        let votes = &fields[2 + agents.len()..2 + 2 * agents.len()];
+        let mut outcome = Outcome {
+            payment: fields[0].to_string(),
+            participants: vec![],
+            prices: vec![],
+            votes: vec![],
+            decision: parse_decision(fields[1])?,
+            started: parse_time(fields[2 + 2 * agents.len()])?,
+            finished: parse_time(fields[3 + 2 * agents.len()])?,
+            node: fields[4 + 2 * agents.len()].parse().ok()?,
+        };
+        let mut vote_names = vec![];
+        for (agent, price) in prices.iter().enumerate() {
+            if price.is_empty() {
+                continue;
+            }
+            outcome.participants.push(agent);
+            outcome.prices.push(price.parse().ok()?);
+            vote_names.push(votes[agent]);
+        }
+        outcome.votes = parse_votes(&vote_names);
+        Some(outcome)
+    }
+
+    /// Parses a line of an outcome ledger in JSON Lines, matching the agents
+    /// of its amounts and votes to the given agent names, or returns None if it
+    /// isn't one
+    pub fn from_json(line: &str, agents: &[String]) -> Option<Outcome> {
+        let fields: Map<String, Value> = serde_json::from_str(line).ok()?;
+        let amounts = fields.get(AMOUNTS_FIELD)?.as_object()?;
+        let votes = fields.get(VOTES_FIELD)?.as_object()?;
+        let text = |field: &str| fields.get(field).and_then(Value::as_str);
+        let mut outcome = Outcome {
+            payment: text(ID_FIELD)?.to_string(),
+            participants: vec![],
+            prices: vec![],
+            votes: vec![],
+            decision: parse_decision(text(DECISION_FIELD)?)?,
+            started: parse_time(text(STARTED_FIELD)?)?,
+            finished: parse_time(text(FINISHED_FIELD)?)?,
+            node: fields.get(NODE_FIELD)?.as_u64()? as usize,
+        };
+        let mut vote_names = vec![];
+        for (agent, name) in agents.iter().enumerate() {
+            if let Some(price) = amounts.get(name) {
+                outcome.participants.push(agent);
+                outcome.prices.push(u32::try_from(price.as_u64()?).ok()?);
+                vote_names.push(votes.get(name).and_then(Value::as_str).unwrap_or(UNKNOWN));
+            }
+        }
+        outcome.votes = parse_votes(&vote_names);
+        Some(outcome)
+    }
+
+    /// Position of an agent among the ones involved, if it's involved
+    fn position(&self, agent: usize) -> Option<usize> {
+        self.participants
+            .iter()
+            .position(|participant| *participant == agent)
+    }
+
+    /// Name of the decision of the transaction
+    fn decision_name(&self) -> &'static str {
+        decision_name(self.decision)
+    }
+
+    /// Name of the vote of the agent at a position among the ones involved
+    fn vote_name(&self, i: usize) -> &'static str {
+        match self.votes.get(i) {
+            Some(&PAYMENT_OK) => "OK",
+            Some(&PAYMENT_ERR) => "ERR",
+            _ => UNKNOWN,
+        }
+    }
+}
+
+/// Name of a decision, COMMIT or ABORT
+pub fn decision_name(decision: u8) -> &'static str {
+    match decision {
+        COMMIT => "COMMIT",
+        ABORT => "ABORT",
+        _ => UNKNOWN,
+    }
+}
+
+/// Parses the name of a decision of the outcome ledger
+fn parse_decision(name: &str) -> Option<u8> {
+    match name {
+        "COMMIT" => Some(COMMIT),
+        "ABORT" => Some(ABORT),
+        _ => None,
+    }
+}
+
+/// Parses the names of the votes of the agents involved. As only a prefix of
+/// them can be replicated, they end at the first unknown one.
+fn parse_votes(names: &[&str]) -> Vec<u8> {
+    names
+        .iter()
+        .map_while(|name| match *name {
+            "OK" => Some(PAYMENT_OK),
+            "ERR" => Some(PAYMENT_ERR),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Parses an RFC 3339 timestamp as milliseconds since the Unix epoch
+fn parse_time(time: &str) -> Option<u64> {
+    let millis = DateTime::parse_from_rfc3339(time).ok()?.timestamp_millis();
+    u64::try_from(millis).ok()
+}
+
+/// Formats milliseconds since the Unix epoch as a local RFC 3339 timestamp, or
+/// as UNKNOWN if they are out of the range of a timestamp
+fn format_time(millis: u64) -> String {
+    i64::try_from(millis)
+        .ok()
+        .and_then(|millis| Local.timestamp_millis_opt(millis).single())
+        .map_or_else(
+            || UNKNOWN.to_string(),
+            |time| time.to_rfc3339_opts(SecondsFormat::Millis, false),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    fn outcome() -> Outcome {
+        Outcome {
+            payment: "PAY-001".to_string(),
+            participants: vec![0, 2],
+            prices: vec![121, 433],
+            votes: vec![PAYMENT_OK, PAYMENT_ERR],
+            decision: ABORT,
+            started: 1_600_000_000_000,
+            finished: 1_600_000_000_250,
+            node: 4,
+        }
+    }
+
+    fn payment() -> Payment {
+        Payment {
+            id: "PAY-001".to_string(),
+            participants: vec![0, 2],
+            prices: vec![121, 433],
+            currency: Some("ARS".to_string()),
+            metadata: Some(serde_json::json!({ "seat": "12A" })),
+        }
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let line = outcome().to_csv_line(&agents());
+        assert_eq!(
+            line.split(',').count(),
+            Outcome::csv_header(&agents()).split(',').count()
+        );
+        assert_eq!(Outcome::from_csv_line(&line, &agents()), Some(outcome()));
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let line = outcome().to_json_line(&payment(), &agents());
+        let fields: Value = serde_json::from_str(&line).expect("Valid JSON");
+        assert_eq!(fields[CURRENCY_FIELD], "ARS");
+        assert_eq!(fields[METADATA_FIELD]["seat"], "12A");
+        assert_eq!(fields[VOTES_FIELD]["hotel"], "ERR");
+        assert_eq!(Outcome::from_json(&line, &agents()), Some(outcome()));
+    }
+
+    #[test]
+    fn votes_end_at_the_first_unknown_one() {
+        let mut outcome = Outcome {
+            votes: vec![PAYMENT_OK],
+            ..outcome()
+        };
+        let line = outcome.to_csv_line(&agents());
+        assert!(line.contains(UNKNOWN));
+        assert_eq!(
+            Outcome::from_csv_line(&line, &agents()),
+            Some(outcome.clone())
+        );
+
+        outcome.votes.clear();
+        let line = outcome.to_json_line(&payment(), &agents());
+        assert_eq!(Outcome::from_json(&line, &agents()), Some(outcome));
+    }
+
+    #[test]
+    fn malformed_csv_lines() {
+        let line = outcome().to_csv_line(&agents());
+        let parse = |line: &str| Outcome::from_csv_line(line, &agents());
+        assert_eq!(parse(""), None);
+        assert_eq!(parse(&Outcome::csv_header(&agents())), None);
+        assert_eq!(parse(&format!("{},", line)), None);
+        assert_eq!(parse(&line.replace("ABORT", "MAYBE")), None);
+        assert_eq!(parse(&line.replace("121", "x")), None);
+        assert_eq!(parse(&line.replace(",4", ",-4")), None);
+        let fields: Vec<&str> = line.split(',').collect();
+        let mut bad_time = fields.clone();
+        bad_time[8] = "yesterday";
+        assert_eq!(parse(&bad_time.join(",")), None);
+    }
+
+    #[test]
+    fn malformed_json_lines() {
+        let parse = |line: &str| Outcome::from_json(line, &agents());
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("[]"), None);
+        assert_eq!(parse("{\"id\": \"PAY-001\"}"), None);
+
+        let line = outcome().to_json_line(&payment(), &agents());
+        let mut fields: Value = serde_json::from_str(&line).expect("Valid JSON");
+        fields[AMOUNTS_FIELD]["bank"] = Value::from(-1);
+        assert_eq!(parse(&fields.to_string()), None);
+        fields[AMOUNTS_FIELD]["bank"] = Value::from(u64::MAX);
+        assert_eq!(parse(&fields.to_string()), None);
+        fields[AMOUNTS_FIELD]["bank"] = Value::from(121);
+        fields[STARTED_FIELD] = Value::from(12);
+        assert_eq!(parse(&fields.to_string()), None);
+    }
+
+    #[test]
+    fn times_out_of_range() {
+        assert_eq!(format_time(u64::MAX), UNKNOWN);
+        assert_eq!(parse_time(UNKNOWN), None);
+        assert_eq!(parse_time("1969-12-31T23:59:59Z"), None);
+        assert_eq!(parse_time(&format_time(1234)), Some(1234));
+    }
+}
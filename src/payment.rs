@@ -0,0 +1,401 @@
+//! Payment struct
+//!
+//! A row of the payments file. The file starts with a header naming its columns:
+//! an `id` column with the id of each payment, and a column for each agent with
+//! the price to charge it, matched to the agents by name:
+//! ```csv
+//! id,bank,airline,hotel
+//! PAY-001,121,507,433
+//! PAY-002,,152,
+//! ```
+//! A payment only involves the agents with a price, so the second one is only
+//! sent to the airline. An optional `agents` column lists them explicitly,
+//! separated by spaces, in which case the other agents must have no price.
+//! The id names the transaction of the payment for the cluster and the agents,
+//! so it stays the same whatever line the payment is on.
+//!
+//! A payments file can also be in JSON Lines, with no header and an object per
+//! line with the id, the price to charge each agent involved, and optionally
+//! the currency and any metadata, which are kept in the ledgers:
+//! ```json
+//! {"id": "PAY-001", "amounts": {"bank": 121, "hotel": 433}, "currency": "USD"}
+//! ```
+//!
+//! A spool directory holds payments files that keep growing: its `.csv` and
+//! `.jsonl` files are read in name order as a single sequence of payments,
+//! skipping hidden files and the last line of a file if it's not complete yet.
+//! Spool files are only appended to, so each one is read from where the
+//! previous read stopped (see `SpoolReader`).
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+use crate::communication::MAX_PAYMENT_ID;
+use crate::payment_columns::PaymentColumns;
+use crate::payment_error::PaymentError;
+use crate::payments_format::PaymentsFormat;
+use crate::spool_reader::SpoolReader;
+use crate::validation_report::ValidationReport;
+
+/// Name of the column with the id of each payment
+pub const ID_COLUMN: &str = "id";
+/// Name of the optional column with the agents involved in each payment
+pub const AGENTS_COLUMN: &str = "agents";
+/// Field of a JSON payment with its id
+pub const ID_FIELD: &str = "id";
+/// Field of a JSON payment with the price to charge each agent involved, by name
+pub const AMOUNTS_FIELD: &str = "amounts";
+/// Optional field of a JSON payment with its currency
+pub const CURRENCY_FIELD: &str = "currency";
+/// Optional field of a JSON payment with any metadata of the upstream service
+pub const METADATA_FIELD: &str = "metadata";
+/// Characters an id can't have, as the ledgers and the raft log have a payment
+/// per line with comma separated fields
+const ID_SEPARATORS: [char; 3] = [',', '\n', '\r'];
+
+/// Payment struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payment {
+    /// Id of the payment, as given in the payments file
+    pub id: String,
+    /// Index of each agent involved in the payment, in the order of the agents
+    /// config file
+    pub participants: Vec<usize>,
+    /// Price to charge each agent involved in the payment
+    pub prices: Vec<u32>,
+    /// Currency of the prices, if the payments file has it
+    pub currency: Option<String>,
+    /// Metadata of the payment, if the payments file has it
+    pub metadata: Option<Value>,
+}
+
+impl Payment {
+    /// Reads and validates every line of a payments file in the given format,
+    /// or of every file of a spool directory in the format of its extension,
+    /// reporting the invalid ones
+    pub fn read(path: &str, format: PaymentsFormat, agents: &[String]) -> ValidationReport {
+        let mut report = ValidationReport::default();
+        if !Path::new(path).is_dir() {
+            let contents = fs::read_to_string(path).expect("Couldn't read payments file");
+            Payment::validate(path, format, &contents, agents, &mut report);
+            return report;
+        }
+        let mut spool = SpoolReader::new(path, agents);
+        spool.read();
+        spool.into_report()
+    }
+
+    /// Validates the contents of a payments file, matching its columns or
+    /// fields to the given agent names, and adds its lines to the report
+    fn validate(
+        filename: &str,
+        format: PaymentsFormat,
+        contents: &str,
+        agents: &[String],
+        report: &mut ValidationReport,
+    ) {
+        let mut columns = None;
+        Payment::validate_lines(filename, format, contents, 1, &mut columns, agents, report);
+        if format == PaymentsFormat::Csv && columns.is_none() {
+            report.add_invalid(filename, 1, "", PaymentError::MissingHeader);
+        }
+    }
+
+    /// Validates lines of a payments file, numbered from `first`, and adds
+    /// them to the report. `columns` has the result of parsing the header of a
+    /// CSV file, or None if the lines start with it.
+    pub fn validate_lines(
+        filename: &str,
+        format: PaymentsFormat,
+        contents: &str,
+        first: usize,
+        columns: &mut Option<Result<PaymentColumns, PaymentError>>,
+        agents: &[String],
+        report: &mut ValidationReport,
+    ) {
+        // Lines are numbered from 1, as in an editor
+        let lines = contents
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (first + i, line))
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        for (number, line) in lines {
+            if format == PaymentsFormat::JsonLines {
+                report.add(filename, number, line, Payment::from_json(line, agents));
+                continue;
+            }
+            match columns {
+                Some(Ok(columns)) => report.add(filename, number, line, columns.parse(line)),
+                // None of the lines of the file can be read without its header
+                Some(Err(_)) => {
+                    report.add_invalid(filename, number, line, PaymentError::InvalidHeader)
+                }
+                None => {
+                    let header = PaymentColumns::from_header(line, agents);
+                    if let Err(err) = &header {
+                        report.add_invalid(filename, number, line, err.clone());
+                    }
+                    *columns = Some(header);
+                }
+            }
+        }
+    }
+
+    /// Header of a payments file for the given agent names
+    pub fn csv_header(agents: &[String]) -> String {
+        let mut columns = vec![ID_COLUMN.to_string()];
+        columns.extend(agents.iter().cloned());
+        columns.join(",")
+    }
+
+    /// Line of a payments file for the payment, with the columns of `csv_header`.
+    /// The agents not involved in the payment have an empty column.
+    pub fn to_csv_line(&self, agents: usize) -> String {
+        let mut fields = vec![self.id.clone()];
+        for agent in 0..agents {
+            fields.push(match self.price_of(agent) {
+                Some(price) => price.to_string(),
+                None => String::new(),
+            });
+        }
+        fields.join(",")
+    }
+
+    /// Parses a line of a JSON Lines payments file, matching the agents of its
+    /// amounts to the given agent names
+    pub fn from_json(line: &str, agents: &[String]) -> Result<Payment, PaymentError> {
+        let fields: Map<String, Value> = match serde_json::from_str(line) {
+            Ok(Value::Object(fields)) => fields,
+            Ok(_) => return Err(PaymentError::InvalidJson("not an object".to_string())),
+            Err(err) => return Err(PaymentError::InvalidJson(err.to_string())),
+        };
+        for field in fields.keys() {
+            if ![ID_FIELD, AMOUNTS_FIELD, CURRENCY_FIELD, METADATA_FIELD].contains(&field.as_str())
+            {
+                return Err(PaymentError::UnknownField(field.clone()));
+            }
+        }
+
+        let id = match fields.get(ID_FIELD) {
+            Some(Value::String(id)) if !id.trim().is_empty() => id.trim().to_string(),
+            None | Some(Value::String(_)) => return Err(PaymentError::MissingId),
+            Some(_) => return Err(PaymentError::InvalidField(ID_FIELD.to_string())),
+        };
+        Payment::check_id(&id)?;
+        let amounts = match fields.get(AMOUNTS_FIELD) {
+            Some(Value::Object(amounts)) => amounts,
+            None => return Err(PaymentError::NoAgents),
+            Some(_) => return Err(PaymentError::InvalidField(AMOUNTS_FIELD.to_string())),
+        };
+        let currency = match fields.get(CURRENCY_FIELD) {
+            Some(Value::String(currency)) => Some(currency.clone()),
+            None | Some(Value::Null) => None,
+            Some(_) => return Err(PaymentError::InvalidField(CURRENCY_FIELD.to_string())),
+        };
+        for name in amounts.keys() {
+            if !agents.contains(name) {
+                return Err(PaymentError::UnknownAgent(name.clone()));
+            }
+        }
+
+        let mut payment = Payment {
+            id,
+            participants: vec![],
+            prices: vec![],
+            currency,
+            metadata: fields.get(METADATA_FIELD).cloned(),
+        };
+        // The agents are kept in the order of the agents config file, as in CSV
+        for (agent, name) in agents.iter().enumerate() {
+            let price = match amounts.get(name) {
+                Some(price) => price,
+                None => continue,
+            };
+            let price = match price.as_u64() {
+                Some(price) => {
+                    u32::try_from(price).map_err(|_| PaymentError::InvalidPrice(name.clone()))?
+                }
+                None if price.as_i64().is_some_and(|price| price < 0) => {
+                    return Err(PaymentError::NegativePrice(name.clone()))
+                }
+                None => return Err(PaymentError::InvalidPrice(name.clone())),
+            };
+            payment.participants.push(agent);
+            payment.prices.push(price);
+        }
+        if payment.participants.is_empty() {
+            return Err(PaymentError::NoAgents);
+        }
+        Ok(payment)
+    }
+
+    /// Checks that an id can name a transaction: the agents refuse the ones
+    /// longer than `MAX_PAYMENT_ID`, and the ledgers can't hold a separator
+    pub fn check_id(id: &str) -> Result<(), PaymentError> {
+        if id.len() > MAX_PAYMENT_ID {
+            return Err(PaymentError::IdTooLong(id.len()));
+        }
+        match id
+            .chars()
+            .find(|character| ID_SEPARATORS.contains(character))
+        {
+            Some(character) => Err(PaymentError::InvalidIdCharacter(character)),
+            None => Ok(()),
+        }
+    }
+
+    /// Line of a JSON Lines payments file for the payment, with the amount of
+    /// each agent involved by name
+    pub fn to_json_line(&self, agents: &[String]) -> String {
+        let mut fields = Map::new();
+        fields.insert(ID_FIELD.to_string(), Value::from(self.id.clone()));
+        fields.insert(
+            AMOUNTS_FIELD.to_string(),
+            Value::Object(
+                self.participants
+                    .iter()
+                    .zip(&self.prices)
+                    .map(|(agent, price)| (agents[*agent].clone(), Value::from(*price)))
+                    .collect(),
+            ),
+        );
+        if let Some(currency) = &self.currency {
+            fields.insert(CURRENCY_FIELD.to_string(), Value::from(currency.clone()));
+        }
+        if let Some(metadata) = &self.metadata {
+            fields.insert(METADATA_FIELD.to_string(), metadata.clone());
+        }
+        Value::Object(fields).to_string()
+    }
+
+    /// Price to charge an agent, if it's involved in the payment
+    pub fn price_of(&self, agent: usize) -> Option<u32> {
+        self.participants
+            .iter()
+            .position(|participant| *participant == agent)
+            .map(|i| self.prices[i])
+    }
+
+    /// Selects the item of each agent involved in the payment, from a list
+    /// with an item for every agent
+    pub fn select<T: Clone>(&self, items: &[T]) -> Vec<T> {
+        self.participants
+            .iter()
+            .map(|agent| items[*agent].clone())
+            .collect()
+    }
+
+    /// Describes the price to charge each agent involved, with the given agent
+    /// names, followed by the currency if there is one
+    pub fn describe(&self, agents: &[String]) -> String {
+        let prices = self
+            .participants
+            .iter()
+            .zip(&self.prices)
+            .map(|(agent, price)| format!("{} {}", agents[*agent], price))
+            .collect::<Vec<String>>()
+            .join(", ");
+        match &self.currency {
+            Some(currency) => format!("{} {}", prices, currency),
+            None => prices,
+        }
+    }
+
+    /// Data of the PREPARE update of the payment: the index of each agent
+    /// involved followed by its price
+    pub fn to_prepare_data(&self) -> Vec<u32> {
+        self.participants
+            .iter()
+            .zip(&self.prices)
+            .flat_map(|(agent, price)| vec![*agent as u32, *price])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    fn json_with_id(id: &str) -> String {
+        serde_json::json!({ "id": id, "amounts": { "bank": 121 } }).to_string()
+    }
+
+    #[test]
+    fn parses_json_payments() {
+        let line =
+            r#"{"id": " PAY-001 ", "amounts": {"hotel": 433, "bank": 121}, "currency": "USD"}"#;
+        let payment = Payment::from_json(line, &agents()).expect("Valid line");
+        assert_eq!(payment.id, "PAY-001");
+        assert_eq!(payment.participants, vec![0, 2]);
+        assert_eq!(payment.prices, vec![121, 433]);
+        assert_eq!(payment.currency, Some("USD".to_string()));
+        assert_eq!(
+            Payment::from_json(&payment.to_json_line(&agents()), &agents()),
+            Ok(payment)
+        );
+    }
+
+    #[test]
+    fn ids_the_agents_accept() {
+        let id = "P".repeat(MAX_PAYMENT_ID);
+        let payment = Payment::from_json(&json_with_id(&id), &agents()).expect("Valid line");
+        assert_eq!(payment.id, id);
+        assert_eq!(
+            Payment::from_json(&json_with_id(&format!("{}X", id)), &agents()),
+            Err(PaymentError::IdTooLong(MAX_PAYMENT_ID + 1))
+        );
+    }
+
+    #[test]
+    fn ids_without_separators() {
+        for separator in &ID_SEPARATORS {
+            let id = format!("PAY{}001", separator);
+            assert_eq!(
+                Payment::from_json(&json_with_id(&id), &agents()),
+                Err(PaymentError::InvalidIdCharacter(*separator))
+            );
+        }
+        assert_eq!(
+            Payment::check_id("PAY 001;ARS|x"),
+            Ok(()),
+            "only separators are rejected"
+        );
+    }
+
+    #[test]
+    fn malformed_json_payments() {
+        let parse = |line: &str| Payment::from_json(line, &agents());
+        assert_eq!(
+            parse(r#"{"amounts": {"bank": 1}}"#),
+            Err(PaymentError::MissingId)
+        );
+        assert_eq!(
+            parse(r#"{"id": 1, "amounts": {"bank": 1}}"#),
+            Err(PaymentError::InvalidField(ID_FIELD.to_string()))
+        );
+        assert_eq!(
+            parse(r#"{"id": "P", "amounts": {"car": 1}}"#),
+            Err(PaymentError::UnknownAgent("car".to_string()))
+        );
+        assert_eq!(
+            parse(r#"{"id": "P", "amounts": {"bank": -1}}"#),
+            Err(PaymentError::NegativePrice("bank".to_string()))
+        );
+        assert_eq!(
+            parse(r#"{"id": "P", "amounts": {}}"#),
+            Err(PaymentError::NoAgents)
+        );
+    }
+}
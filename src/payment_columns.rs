@@ -0,0 +1,261 @@
+//! PaymentColumns struct
+//!
+//! Columns of a payments file, as named by its header, used to parse each of
+//! its lines into a payment.
+
+use crate::payment::{Payment, AGENTS_COLUMN, ID_COLUMN};
+use crate::payment_error::PaymentError;
+
+/// PaymentColumns struct
+#[derive(Debug, Clone)]
+pub struct PaymentColumns {
+    /// Name of every agent, in the order of the agents config file
+    agents: Vec<String>,
+    /// Amount of columns of the header
+    width: usize,
+    /// Column with the id of each payment
+    id: usize,
+    /// Column listing the agents involved in each payment, if any
+    listed: Option<usize>,
+    /// Column of each agent, in the order of the agents config file
+    prices: Vec<usize>,
+}
+
+impl PaymentColumns {
+    /// Parses a header, matching its columns to the given agent names
+    pub fn from_header(header: &str, agents: &[String]) -> Result<PaymentColumns, PaymentError> {
+        let header: Vec<&str> = header.split(',').map(str::trim).collect();
+        let id = header
+            .iter()
+            .position(|column| *column == ID_COLUMN)
+            .ok_or(PaymentError::MissingIdColumn)?;
+        let listed = header.iter().position(|column| *column == AGENTS_COLUMN);
+        for (i, column) in header.iter().enumerate() {
+            if i != id && Some(i) != listed && !agents.iter().any(|agent| agent == column) {
+                return Err(PaymentError::UnknownColumn(column.to_string()));
+            }
+            if header[..i].contains(column) {
+                return Err(PaymentError::RepeatedColumn(column.to_string()));
+            }
+        }
+        let prices = agents
+            .iter()
+            .map(|agent| {
+                header
+                    .iter()
+                    .position(|column| column == agent)
+                    .ok_or_else(|| PaymentError::MissingAgentColumn(agent.clone()))
+            })
+            .collect::<Result<Vec<usize>, PaymentError>>()?;
+
+        Ok(PaymentColumns {
+            agents: agents.to_vec(),
+            width: header.len(),
+            id,
+            listed,
+            prices,
+        })
+    }
+
+    /// Parses a line into a payment. A payment involves the agents listed in
+    /// the agents column if there is one, or else the agents with a price.
+    pub fn parse(&self, line: &str) -> Result<Payment, PaymentError> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() != self.width {
+            return Err(PaymentError::FieldCount {
+                expected: self.width,
+                actual: fields.len(),
+            });
+        }
+        if fields[self.id].is_empty() {
+            return Err(PaymentError::MissingId);
+        }
+        Payment::check_id(fields[self.id])?;
+
+        let listed: Option<Vec<&str>> = self
+            .listed
+            .map(|column| fields[column].split_whitespace().collect());
+        if let Some(listed) = &listed {
+            for (i, name) in listed.iter().enumerate() {
+                if !self.agents.iter().any(|agent| agent == name) {
+                    return Err(PaymentError::UnknownAgent(name.to_string()));
+                }
+                if listed[..i].contains(name) {
+                    return Err(PaymentError::RepeatedAgent(name.to_string()));
+                }
+            }
+        }
+
+        let mut payment = Payment {
+            id: fields[self.id].to_string(),
+            participants: vec![],
+            prices: vec![],
+            currency: None,
+            metadata: None,
+        };
+        for (agent, column) in self.prices.iter().enumerate() {
+            let name = &self.agents[agent];
+            let price = fields[*column];
+            let involved = match &listed {
+                Some(listed) => listed.contains(&name.as_str()),
+                None => !price.is_empty(),
+            };
+            if involved && price.is_empty() {
+                return Err(PaymentError::MissingPrice(name.clone()));
+            }
+            if !involved {
+                if !price.is_empty() {
+                    return Err(PaymentError::UnexpectedPrice(name.clone()));
+                }
+                continue;
+            }
+            let price = match price.parse() {
+                Ok(price) => price,
+                Err(_) if price.parse::<i64>().is_ok_and(|price| price < 0) => {
+                    return Err(PaymentError::NegativePrice(name.clone()))
+                }
+                Err(_) => return Err(PaymentError::InvalidPrice(name.clone())),
+            };
+            payment.participants.push(agent);
+            payment.prices.push(price);
+        }
+        if payment.participants.is_empty() {
+            return Err(PaymentError::NoAgents);
+        }
+        Ok(payment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::MAX_PAYMENT_ID;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    fn columns(header: &str) -> PaymentColumns {
+        PaymentColumns::from_header(header, &agents()).expect("Valid header")
+    }
+
+    #[test]
+    fn parses_prices_in_header_order() {
+        let columns = columns("hotel, id ,bank,airline");
+        let payment = columns.parse("433,PAY-001,121, 507").expect("Valid line");
+        assert_eq!(payment.id, "PAY-001");
+        assert_eq!(payment.participants, vec![0, 1, 2]);
+        assert_eq!(payment.prices, vec![121, 507, 433]);
+    }
+
+    #[test]
+    fn agents_with_a_price_are_involved() {
+        let payment = columns("id,bank,airline,hotel")
+            .parse("PAY-002,,152,")
+            .expect("Valid line");
+        assert_eq!(payment.participants, vec![1]);
+        assert_eq!(payment.prices, vec![152]);
+    }
+
+    #[test]
+    fn agents_column_lists_the_involved() {
+        let columns = columns("id,agents,bank,airline,hotel");
+        let payment = columns
+            .parse("PAY-003,hotel bank,0,,9")
+            .expect("Valid line");
+        assert_eq!(payment.participants, vec![0, 2]);
+        assert_eq!(payment.prices, vec![0, 9]);
+
+        assert_eq!(
+            columns.parse("PAY-003,bank,,,"),
+            Err(PaymentError::MissingPrice("bank".to_string()))
+        );
+        assert_eq!(
+            columns.parse("PAY-003,bank,1,2,"),
+            Err(PaymentError::UnexpectedPrice("airline".to_string()))
+        );
+        assert_eq!(
+            columns.parse("PAY-003,bank car,1,,"),
+            Err(PaymentError::UnknownAgent("car".to_string()))
+        );
+        assert_eq!(
+            columns.parse("PAY-003,bank bank,1,,"),
+            Err(PaymentError::RepeatedAgent("bank".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_headers() {
+        let from_header = |header| PaymentColumns::from_header(header, &agents()).err();
+        assert_eq!(
+            from_header("bank,airline,hotel"),
+            Some(PaymentError::MissingIdColumn)
+        );
+        assert_eq!(from_header(""), Some(PaymentError::MissingIdColumn));
+        assert_eq!(
+            from_header("id,bank,airline,hotel,car"),
+            Some(PaymentError::UnknownColumn("car".to_string()))
+        );
+        assert_eq!(
+            from_header("id,bank,airline,hotel,bank"),
+            Some(PaymentError::RepeatedColumn("bank".to_string()))
+        );
+        assert_eq!(
+            from_header("id,bank,hotel"),
+            Some(PaymentError::MissingAgentColumn("airline".to_string()))
+        );
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let columns = columns("id,bank,airline,hotel");
+        assert_eq!(
+            columns.parse("PAY-001,1,2"),
+            Err(PaymentError::FieldCount {
+                expected: 4,
+                actual: 3
+            })
+        );
+        assert_eq!(
+            columns.parse(""),
+            Err(PaymentError::FieldCount {
+                expected: 4,
+                actual: 1
+            })
+        );
+        assert_eq!(columns.parse(" ,1,2,3"), Err(PaymentError::MissingId));
+        assert_eq!(
+            columns.parse("PAY-001,1,x,3"),
+            Err(PaymentError::InvalidPrice("airline".to_string()))
+        );
+        assert_eq!(
+            columns.parse("PAY-001,1,2,4294967296"),
+            Err(PaymentError::InvalidPrice("hotel".to_string()))
+        );
+        assert_eq!(
+            columns.parse("PAY-001,-1,2,3"),
+            Err(PaymentError::NegativePrice("bank".to_string()))
+        );
+        assert_eq!(columns.parse("PAY-001,,,"), Err(PaymentError::NoAgents));
+    }
+
+    #[test]
+    fn ids_the_ledgers_can_hold() {
+        let columns = columns("id,bank,airline,hotel");
+        let id = "P".repeat(MAX_PAYMENT_ID);
+        let payment = columns.parse(&format!("{},1,,", id)).expect("Valid line");
+        assert_eq!(payment.id, id);
+        assert_eq!(
+            columns.parse(&format!("{}X,1,,", id)),
+            Err(PaymentError::IdTooLong(MAX_PAYMENT_ID + 1))
+        );
+        assert_eq!(
+            columns.parse("PAY\r001,1,,"),
+            Err(PaymentError::InvalidIdCharacter('\r'))
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! PaymentError enum
+//!
+//! Reasons why a payments file, or one of its lines, is invalid.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::communication::MAX_PAYMENT_ID;
+
+/// PaymentError enum
+#[derive(Debug, Clone, PartialEq)]
+pub enum PaymentError {
+    /// The file has no header
+    MissingHeader,
+    /// The header has no column with the id of each payment
+    MissingIdColumn,
+    /// The header has a column that isn't the id nor a known agent
+    UnknownColumn(String),
+    /// The header has the same column twice
+    RepeatedColumn(String),
+    /// The header has no column for an agent
+    MissingAgentColumn(String),
+    /// The line has a different amount of fields than the header
+    FieldCount {
+        /// Amount of columns of the header
+        expected: usize,
+        /// Amount of fields of the line
+        actual: usize,
+    },
+    /// The line has an empty id
+    MissingId,
+    /// The id is longer than the agents accept, with its length in bytes
+    IdTooLong(usize),
+    /// The id has a character that separates the fields or lines of the ledgers
+    InvalidIdCharacter(char),
+    /// The agents column of the line lists an unknown agent
+    UnknownAgent(String),
+    /// The agents column of the line lists an agent twice
+    RepeatedAgent(String),
+    /// An agent involved in the payment has no price
+    MissingPrice(String),
+    /// An agent not involved in the payment has a price
+    UnexpectedPrice(String),
+    /// The price of an agent isn't an unsigned integer
+    InvalidPrice(String),
+    /// The price of an agent is negative
+    NegativePrice(String),
+    /// The payment involves no agent
+    NoAgents,
+    /// Another payment has the same id
+    DuplicateId {
+        /// File of the payment with the id
+        file: String,
+        /// Line of the payment with the id
+        line: usize,
+    },
+    /// The header of the file of the line is invalid
+    InvalidHeader,
+    /// The line isn't a JSON object
+    InvalidJson(String),
+    /// The JSON object has a field that isn't one of a payment
+    UnknownField(String),
+    /// A field of the JSON object has the wrong type
+    InvalidField(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PaymentError::MissingHeader => write!(f, "missing header"),
+            PaymentError::MissingIdColumn => write!(f, "missing id column"),
+            PaymentError::UnknownColumn(column) => {
+                write!(f, "column for unknown agent {}", column)
+            }
+            PaymentError::RepeatedColumn(column) => write!(f, "repeated column {}", column),
+            PaymentError::MissingAgentColumn(agent) => {
+                write!(f, "missing the column of agent {}", agent)
+            }
+            PaymentError::FieldCount { expected, actual } => {
+                write!(f, "{} fields but the header has {}", actual, expected)
+            }
+            PaymentError::MissingId => write!(f, "missing id"),
+            PaymentError::IdTooLong(length) => {
+                write!(f, "id of {} bytes, longer than {}", length, MAX_PAYMENT_ID)
+            }
+            PaymentError::InvalidIdCharacter(character) => {
+                write!(f, "id with the character {:?}", character)
+            }
+            PaymentError::UnknownAgent(agent) => write!(f, "lists unknown agent {}", agent),
+            PaymentError::RepeatedAgent(agent) => write!(f, "lists agent {} twice", agent),
+            PaymentError::MissingPrice(agent) => write!(f, "missing the price for {}", agent),
+            PaymentError::UnexpectedPrice(agent) => {
+                write!(f, "price for {}, which isn't involved", agent)
+            }
+            PaymentError::InvalidPrice(agent) => write!(f, "invalid price for {}", agent),
+            PaymentError::NegativePrice(agent) => write!(f, "negative price for {}", agent),
+            PaymentError::NoAgents => write!(f, "involves no agent"),
+            PaymentError::DuplicateId { file, line } => {
+                write!(f, "id already used in {}, line {}", file, line)
+            }
+            PaymentError::InvalidHeader => write!(f, "the header of the file is invalid"),
+            PaymentError::InvalidJson(err) => write!(f, "invalid JSON: {}", err),
+            PaymentError::UnknownField(field) => write!(f, "unknown field {}", field),
+            PaymentError::InvalidField(field) => write!(f, "field {} has the wrong type", field),
+        }
+    }
+}
+
+impl Error for PaymentError {}
@@ -0,0 +1,271 @@
+//! PaymentIntake struct
+//!
+//! Receives payments over a TCP or Unix socket and appends them to a new file
+//! of a spool directory, where the leader picks them up. Each connection sends
+//! a header followed by a payment per line, with the format of a payments file,
+//! and gets an answer per payment: `OK <id>` once it's stored in the spool, or
+//! `ERR <reason>` if it's invalid or its id was already received for a
+//! different payment.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::logger::Logger;
+use crate::payment::Payment;
+use crate::payment_columns::PaymentColumns;
+use crate::payments_format::PaymentsFormat;
+use crate::utils::{create_empty_csv, write_csv_line};
+use crate::validation_report::ValidationReport;
+
+/// Prefix of the address of a Unix socket
+pub const UNIX_PREFIX: &str = "unix:";
+
+/// PaymentIntake struct
+#[derive(Clone)]
+pub struct PaymentIntake {
+    /// Name of every agent of the agents config file
+    agents: Vec<String>,
+    /// Name of the spool file where the received payments are appended
+    filename: String,
+    /// Spool file where the received payments are appended, and the number of
+    /// its last line
+    spool_file: Arc<Mutex<(File, usize)>>,
+    /// Payments of the spool, to reject the ones with a repeated id
+    received: Arc<Mutex<ValidationReport>>,
+    logger: Logger,
+}
+
+impl PaymentIntake {
+    /// Creates the intake, with a new file in the spool directory named after
+    /// the current time
+    pub fn new(spool: &str, agents: Vec<String>) -> PaymentIntake {
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Clock is before the epoch")
+            .as_millis();
+        let filename = Path::new(spool).join(format!("{:020}.csv", millis));
+        let received = Payment::read(spool, PaymentsFormat::Csv, &agents);
+        let filename = filename.to_string_lossy().to_string();
+        let spool_file = create_empty_csv(&filename);
+        write_csv_line(&spool_file, &Payment::csv_header(&agents));
+
+        PaymentIntake {
+            agents,
+            filename,
+            spool_file: Arc::new(Mutex::new((spool_file, 1))),
+            received: Arc::new(Mutex::new(received)),
+            logger: Logger::new("intake".to_string()),
+        }
+    }
+
+    /// Starts listening for payments on a thread, on a TCP address or on a Unix
+    /// socket if the address starts with `unix:`
+    pub fn listen(self, addr: &str) {
+        let addr = addr.to_string();
+        thread::Builder::new()
+            .name("Payment intake".to_string())
+            .spawn(move || {
+                self.logger
+                    .info(format!("Listening for payments on {}", addr));
+                if let Some(path) = addr.strip_prefix(UNIX_PREFIX) {
+                    // The socket file of a previous run would make the bind fail
+                    let _ignore = std::fs::remove_file(path);
+                    let listener = UnixListener::bind(path).expect("Couldn't bind intake socket");
+                    for stream in listener.incoming().flatten() {
+                        let reader = stream.try_clone().expect("Couldn't clone stream");
+                        self.serve(reader, stream);
+                    }
+                } else {
+                    let listener = TcpListener::bind(&addr).expect("Couldn't bind intake socket");
+                    for stream in listener.incoming().flatten() {
+                        let reader = stream.try_clone().expect("Couldn't clone stream");
+                        self.serve(reader, stream);
+                    }
+                }
+            })
+            .expect("Couldn't create payment intake thread");
+    }
+
+    /// Receives the payments of a connection on its own thread
+    fn serve(
+        &self,
+        reader: impl std::io::Read + Send + 'static,
+        writer: impl Write + Send + 'static,
+    ) {
+        let intake = self.clone();
+        thread::spawn(move || intake.receive(BufReader::new(reader), writer));
+    }
+
+    /// Receives the header and then the payments of a connection, answering each one
+    fn receive(&self, reader: impl BufRead, mut writer: impl Write) {
+        let mut lines = reader
+            .lines()
+            .map_while(Result::ok)
+            .filter(|line| !line.trim().is_empty());
+        let columns = match lines.next() {
+            Some(header) => PaymentColumns::from_header(&header, &self.agents),
+            None => return,
+        };
+        let columns = match columns {
+            Ok(columns) => columns,
+            Err(err) => {
+                let _ignore = writeln!(writer, "ERR {}", err);
+                return;
+            }
+        };
+
+        for line in lines {
+            let mut received = self.received.lock().expect("Unable to get lock");
+            let payment = columns
+                .parse(&line)
+                .and_then(|payment| received.check_id(&payment).map(|new| (payment, new)));
+            let answer = match payment {
+                Ok((payment, true)) => {
+                    let mut spool_file = self.spool_file.lock().expect("Unable to get lock");
+                    let (file, number) = &mut *spool_file;
+                    write_csv_line(file, &payment.to_csv_line(self.agents.len()));
+                    file.sync_data().expect("Couldn't sync the spool file");
+                    *number += 1;
+                    received.add(&self.filename, *number, &line, Ok(payment.clone()));
+                    self.logger.info(format!(
+                        "Payment {} of {} received",
+                        payment.id,
+                        payment.describe(&self.agents)
+                    ));
+                    format!("OK {}", payment.id)
+                }
+                // A sender can send a payment again, for example if it didn't
+                // get the answer, and it's only stored once
+                Ok((payment, false)) => format!("OK {}", payment.id),
+                Err(err) => format!("ERR {}", err),
+            };
+            if writeln!(writer, "{}", answer).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spool_reader::SpoolReader;
+    use std::io::Cursor;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    /// Empty spool directory of a test in the temporary directory
+    fn spool(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("intake-{}-{}", test, std::process::id()));
+        let _ignore = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("Spool created");
+        dir.to_string_lossy().into_owned()
+    }
+
+    /// Sends the lines to the intake, returning its answers
+    fn send(intake: &PaymentIntake, lines: &[&str]) -> Vec<String> {
+        let mut answers = Vec::new();
+        intake.receive(Cursor::new(lines.join("\n")), &mut answers);
+        String::from_utf8(answers)
+            .expect("Answers in UTF-8")
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn spool_ids(spool: &str) -> Vec<String> {
+        let mut reader = SpoolReader::new(spool, &agents());
+        reader.read();
+        let report = reader.into_report();
+        assert!(report.is_valid());
+        report
+            .payments
+            .into_iter()
+            .map(|payment| payment.id)
+            .collect()
+    }
+
+    #[test]
+    fn stores_each_valid_payment() {
+        let dir = spool("valid");
+        let intake = PaymentIntake::new(&dir, agents());
+        let answers = send(
+            &intake,
+            &[
+                "id,hotel,airline,bank",
+                "PAY-1,433,,121",
+                "PAY-2,x,,1",
+                "",
+                "PAY-3,,,7",
+            ],
+        );
+        assert_eq!(
+            answers,
+            vec!["OK PAY-1", "ERR invalid price for hotel", "OK PAY-3"]
+        );
+        assert_eq!(spool_ids(&dir), vec!["PAY-1", "PAY-3"]);
+    }
+
+    #[test]
+    fn stores_a_payment_sent_again_once() {
+        let dir = spool("again");
+        let intake = PaymentIntake::new(&dir, agents());
+        let answers = send(
+            &intake,
+            &[
+                "id,bank,airline,hotel",
+                "PAY-1,121,,",
+                "PAY-1,121,,",
+                "PAY-1,5,,",
+            ],
+        );
+        assert_eq!(answers[..2], ["OK PAY-1", "OK PAY-1"]);
+        assert!(answers[2].starts_with("ERR "));
+        assert_eq!(spool_ids(&dir), vec!["PAY-1"]);
+    }
+
+    #[test]
+    fn knows_the_payments_already_in_the_spool() {
+        let dir = spool("known");
+        let intake = PaymentIntake::new(&dir, agents());
+        send(&intake, &["id,bank,airline,hotel", "PAY-1,121,,"]);
+
+        // A later intake writes to its own file of the spool
+        thread::sleep(std::time::Duration::from_millis(2));
+        let intake = PaymentIntake::new(&dir, agents());
+        let answers = send(
+            &intake,
+            &[
+                "id,bank,airline,hotel",
+                "PAY-1,121,,",
+                "PAY-1,5,,",
+                "PAY-2,7,,",
+            ],
+        );
+        assert_eq!(answers[0], "OK PAY-1");
+        assert!(answers[1].starts_with("ERR "));
+        assert_eq!(answers[2], "OK PAY-2");
+        assert_eq!(spool_ids(&dir), vec!["PAY-1", "PAY-2"]);
+    }
+
+    #[test]
+    fn rejects_an_invalid_header() {
+        let dir = spool("header");
+        let intake = PaymentIntake::new(&dir, agents());
+        let answers = send(&intake, &["id,bank,airline,hotel,train", "PAY-1,121,,,5"]);
+        assert_eq!(answers, vec!["ERR column for unknown agent train"]);
+        assert_eq!(spool_ids(&dir), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,187 @@
+//! PaymentsFormat enum
+//!
+//! Format of a payments file and of the ledgers written while processing it:
+//! CSV with a header, or JSON Lines with an object per payment.
+
+use std::path::Path;
+
+use crate::outcome::Outcome;
+use crate::payment::Payment;
+
+/// Extension of the JSON Lines files
+pub const JSONL_EXTENSION: &str = "jsonl";
+/// Extension of the CSV files
+pub const CSV_EXTENSION: &str = "csv";
+
+/// PaymentsFormat enum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PaymentsFormat {
+    /// A header naming the columns, and then a payment per line
+    Csv,
+    /// A JSON object per line, without a header
+    JsonLines,
+}
+
+impl PaymentsFormat {
+    /// Parses the name of a format, as given to `--format`
+    pub fn from_name(name: &str) -> PaymentsFormat {
+        match name {
+            CSV_EXTENSION => PaymentsFormat::Csv,
+            JSONL_EXTENSION => PaymentsFormat::JsonLines,
+            _ => panic!("Unknown format {}, use csv or jsonl", name),
+        }
+    }
+
+    /// Format of a file given its extension, or CSV if it's not `.jsonl`
+    pub fn from_path(path: &str) -> PaymentsFormat {
+        match Path::new(path).extension() {
+            Some(extension) if extension == JSONL_EXTENSION => PaymentsFormat::JsonLines,
+            _ => PaymentsFormat::Csv,
+        }
+    }
+
+    /// Extension of the files of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PaymentsFormat::Csv => CSV_EXTENSION,
+            PaymentsFormat::JsonLines => JSONL_EXTENSION,
+        }
+    }
+
+    /// First line of a file of the format with the given agent names, if it has one
+    pub fn header(&self, agents: &[String]) -> Option<String> {
+        match self {
+            PaymentsFormat::Csv => Some(Payment::csv_header(agents)),
+            PaymentsFormat::JsonLines => None,
+        }
+    }
+
+    /// Line of a file of the format for the payment
+    pub fn line(&self, payment: &Payment, agents: &[String]) -> String {
+        match self {
+            PaymentsFormat::Csv => payment.to_csv_line(agents.len()),
+            PaymentsFormat::JsonLines => payment.to_json_line(agents),
+        }
+    }
+
+    /// First line of an outcome ledger of the format with the given agent
+    /// names, if it has one
+    pub fn outcome_header(&self, agents: &[String]) -> Option<String> {
+        match self {
+            PaymentsFormat::Csv => Some(Outcome::csv_header(agents)),
+            PaymentsFormat::JsonLines => None,
+        }
+    }
+
+    /// Line of an outcome ledger of the format for the outcome of a payment
+    pub fn outcome_line(&self, outcome: &Outcome, payment: &Payment, agents: &[String]) -> String {
+        match self {
+            PaymentsFormat::Csv => outcome.to_csv_line(agents),
+            PaymentsFormat::JsonLines => outcome.to_json_line(payment, agents),
+        }
+    }
+
+    /// Parses a line of an outcome ledger of the format, or returns None if it
+    /// isn't one
+    pub fn parse_outcome(&self, line: &str, agents: &[String]) -> Option<Outcome> {
+        match self {
+            PaymentsFormat::Csv => Outcome::from_csv_line(line, agents),
+            PaymentsFormat::JsonLines => Outcome::from_json(line, agents),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::communication::COMMIT;
+    use crate::payment_columns::PaymentColumns;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    fn payment() -> Payment {
+        Payment {
+            id: "PAY-001".to_string(),
+            participants: vec![0, 2],
+            prices: vec![121, 433],
+            currency: None,
+            metadata: None,
+        }
+    }
+
+    #[test]
+    fn formats_by_name_and_extension() {
+        assert_eq!(PaymentsFormat::from_name("csv"), PaymentsFormat::Csv);
+        assert_eq!(
+            PaymentsFormat::from_name("jsonl"),
+            PaymentsFormat::JsonLines
+        );
+        assert_eq!(
+            PaymentsFormat::from_path("src/prices.jsonl"),
+            PaymentsFormat::JsonLines
+        );
+        assert_eq!(
+            PaymentsFormat::from_path("src/prices.csv"),
+            PaymentsFormat::Csv
+        );
+        assert_eq!(PaymentsFormat::from_path("src/spool"), PaymentsFormat::Csv);
+        for format in &[PaymentsFormat::Csv, PaymentsFormat::JsonLines] {
+            assert_eq!(PaymentsFormat::from_name(format.extension()), *format);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Unknown format")]
+    fn unknown_format_name() {
+        PaymentsFormat::from_name("xml");
+    }
+
+    #[test]
+    fn payment_lines_round_trip() {
+        let csv = PaymentsFormat::Csv;
+        let header = csv.header(&agents()).expect("CSV has a header");
+        let columns = PaymentColumns::from_header(&header, &agents()).expect("Valid header");
+        assert_eq!(
+            columns.parse(&csv.line(&payment(), &agents())),
+            Ok(payment())
+        );
+
+        let jsonl = PaymentsFormat::JsonLines;
+        assert_eq!(jsonl.header(&agents()), None);
+        let line = jsonl.line(&payment(), &agents());
+        assert_eq!(Payment::from_json(&line, &agents()), Ok(payment()));
+    }
+
+    #[test]
+    fn outcome_lines_round_trip() {
+        let outcome = Outcome {
+            payment: "PAY-001".to_string(),
+            participants: vec![0, 2],
+            prices: vec![121, 433],
+            votes: vec![],
+            decision: COMMIT,
+            started: 1_600_000_000_000,
+            finished: 1_600_000_000_250,
+            node: 1,
+        };
+        for format in &[PaymentsFormat::Csv, PaymentsFormat::JsonLines] {
+            let line = format.outcome_line(&outcome, &payment(), &agents());
+            assert_eq!(
+                format.parse_outcome(&line, &agents()),
+                Some(outcome.clone())
+            );
+        }
+        assert!(PaymentsFormat::Csv.outcome_header(&agents()).is_some());
+        assert_eq!(PaymentsFormat::JsonLines.outcome_header(&agents()), None);
+        let header = PaymentsFormat::Csv
+            .outcome_header(&agents())
+            .expect("CSV has a header");
+        assert_eq!(PaymentsFormat::Csv.parse_outcome(&header, &agents()), None);
+    }
+}
@@ -0,0 +1,145 @@
+//! PhiAccrualDetector struct
+//!
+//! Failure detector used by the replicas to decide when the leader is dead.
+//! Instead of a fixed timeout, it keeps the intervals between the messages of
+//! the leader and computes phi, the suspicion level that the leader is down:
+//! phi = -log10(P(next message arrives even later)), assuming the intervals
+//! follow a normal distribution. A phi of 1 means a 10% chance of being wrong
+//! when suspecting the leader, a phi of 2 a 1% chance, and so on.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Amount of intervals kept to estimate their distribution
+const WINDOW: usize = 100;
+
+/// PhiAccrualDetector struct
+#[derive(Debug, Clone)]
+pub struct PhiAccrualDetector {
+    /// Last intervals between messages, in milliseconds
+    intervals: VecDeque<f64>,
+    /// Arrival time of the last message
+    last: Instant,
+    /// Expected interval between messages, used before there are samples
+    expected: Duration,
+}
+
+impl PhiAccrualDetector {
+    /// Creates the detector for messages expected every `expected` interval
+    pub fn new(expected: Duration) -> PhiAccrualDetector {
+        PhiAccrualDetector {
+            intervals: VecDeque::new(),
+            last: Instant::now(),
+            expected,
+        }
+    }
+
+    /// Forgets the previous intervals, as when following a new leader
+    pub fn reset(&mut self) {
+        self.intervals.clear();
+        self.last = Instant::now();
+    }
+
+    /// Records the arrival of a message
+    pub fn heartbeat(&mut self) {
+        if self.intervals.len() == WINDOW {
+            self.intervals.pop_front();
+        }
+        self.intervals
+            .push_back(self.last.elapsed().as_secs_f64() * 1000.0);
+        self.last = Instant::now();
+    }
+
+    /// Suspicion level that the sender is down, given the time since its last message
+    pub fn phi(&self) -> f64 {
+        let expected = self.expected.as_secs_f64() * 1000.0;
+        let (mean, std_dev) = if self.intervals.is_empty() {
+            (expected, 0.0)
+        } else {
+            let count = self.intervals.len() as f64;
+            let mean = self.intervals.iter().sum::<f64>() / count;
+            let variance = self
+                .intervals
+                .iter()
+                .map(|interval| (interval - mean).powi(2))
+                .sum::<f64>()
+                / count;
+            (mean, variance.sqrt())
+        };
+        // Messages sent in a steady rhythm have almost no deviation, so a
+        // minimum deviation and an acceptable pause avoid suspecting the sender
+        // on the first late message
+        let mean = mean + 2.0 * expected;
+        let std_dev = std_dev.max(expected / 4.0);
+
+        let elapsed = self.last.elapsed().as_secs_f64() * 1000.0;
+        let y = (elapsed - mean) / std_dev;
+        // Logistic approximation of the normal cumulative distribution
+        let e = (-y * (1.5976 + 0.070566 * y * y)).exp();
+        if elapsed > mean {
+            -(e / (1.0 + e)).log10()
+        } else {
+            -(1.0 - 1.0 / (1.0 + e)).log10()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPECTED: Duration = Duration::from_millis(500);
+
+    /// Detector whose last message arrived the given time ago, after messages
+    /// that arrived every `interval` milliseconds
+    fn detector(intervals: &[f64], elapsed: Duration) -> PhiAccrualDetector {
+        let mut detector = PhiAccrualDetector::new(EXPECTED);
+        detector.intervals = intervals.iter().copied().collect();
+        detector.last = Instant::now()
+            .checked_sub(elapsed)
+            .expect("Instant in the past");
+        detector
+    }
+
+    #[test]
+    fn suspicion_grows_with_silence() {
+        let intervals = [500.0; 20];
+        let phis: Vec<f64> = [0, 500, 1500, 2500, 5000]
+            .iter()
+            .map(|millis| detector(&intervals, Duration::from_millis(*millis)).phi())
+            .collect();
+        assert!(phis.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", phis);
+        // A message that is only late isn't enough to suspect the sender
+        assert!(phis[1] < 1.0, "{:?}", phis);
+        assert!(phis[4] > 8.0, "{:?}", phis);
+    }
+
+    #[test]
+    fn irregular_senders_are_suspected_later() {
+        let elapsed = Duration::from_millis(3000);
+        let steady = detector(&[500.0; 20], elapsed).phi();
+        let irregular = detector(&[100.0, 900.0].repeat(10), elapsed).phi();
+        assert!(irregular < steady, "{} < {}", irregular, steady);
+    }
+
+    #[test]
+    fn starts_from_the_expected_interval() {
+        let fresh = detector(&[], Duration::from_millis(500)).phi();
+        let late = detector(&[], Duration::from_millis(5000)).phi();
+        assert!(fresh < 1.0 && late > 8.0, "{} {}", fresh, late);
+        // A long silence saturates phi, which still compares with the threshold
+        let silent = detector(&[], Duration::from_secs(60)).phi();
+        assert!(!silent.is_nan() && silent > 8.0, "{}", silent);
+    }
+
+    #[test]
+    fn keeps_a_window_of_intervals() {
+        let mut detector = PhiAccrualDetector::new(EXPECTED);
+        for _ in 0..WINDOW + 10 {
+            detector.heartbeat();
+        }
+        assert_eq!(detector.intervals.len(), WINDOW);
+        detector.reset();
+        assert!(detector.intervals.is_empty());
+    }
+}
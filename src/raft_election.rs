@@ -0,0 +1,690 @@
+//! RaftElection struct
+//!
+//! Raft consensus algorithm, used both to elect the leader and to replicate the
+//! coordinator log. Every update of the coordinator state is an entry of the log, and the leader only acts on it once a majority of the nodes stored
+//! it. Leadership follows Raft terms: a node becomes candidate when it stops
+//! hearing from the leader, and wins the term with the votes of a majority.
+//! Nodes only vote for candidates with a log as up to date as theirs, and nodes
+//! with a lower priority wait longer before becoming candidates.
+//!
+//! Every message is sent on the control socket as a list of numbers:
+//! - REQUEST_VOTE: term, candidate id, last log index, last log term
+//! - VOTE: term, 1 if granted or 0 if not, voter id
+//! - APPEND: term, leader id, previous log index, previous log term, leader commit
+//!   index, and then the term and the update fields of each entry
+//! - APPEND_REPLY: term, 1 on success or 0 on failure, follower id, last log index
+//! - TIMEOUT_NOW: term of the leader handing over its place
+//! - INSTALL_SNAPSHOT: term, leader id, index and term of the last entry of the
+//!   snapshot, part, amount of parts, length in bytes of the part, and then the
+//!   bytes of the part packed 8 in each field
+//!
+//! Each node compacts the entries it applied into a snapshot of the coordinator
+//! state, and the leader sends that snapshot to the followers that miss the
+//! entries it compacted.
+
+use std::net::SocketAddr;
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::alglobo_node::{AlgloboNode, MAX_SNAPSHOT_PARTS, TIMEOUT};
+use crate::coordinator_state::CoordinatorState;
+use crate::election::Election;
+use crate::logger::Logger;
+use crate::raft_entry::{RaftEntry, NOOP};
+use crate::raft_state::{RaftRole, RaftState, COMPACT_AFTER};
+use crate::snapshot_parts::SnapshotParts;
+use crate::state_update::StateUpdate;
+
+/// Control message asking for a vote
+pub const MSG_REQUEST_VOTE: u8 = b'V';
+/// Control message with which the leader asks an up to date follower to start
+/// an election right away, to hand over its place
+pub const MSG_TIMEOUT_NOW: u8 = b'X';
+/// Control message answering a vote request
+pub const MSG_VOTE: u8 = b'W';
+/// Control message with log entries (or none, as a heartbeat) from the leader
+pub const MSG_APPEND: u8 = b'N';
+/// Control message answering an append from the leader
+pub const MSG_APPEND_REPLY: u8 = b'R';
+/// Control message with a part of the snapshot of the leader
+pub const MSG_INSTALL_SNAPSHOT: u8 = b'I';
+/// Interval between appends sent by the leader, even if there are no new entries
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_millis(300);
+/// Maximum amount of entries sent in a single append message
+const MAX_ENTRIES: usize = 32;
+/// Fields before the bytes of an INSTALL_SNAPSHOT
+const SNAPSHOT_HEADER: usize = 7;
+/// Biggest part of a snapshot sent in an INSTALL_SNAPSHOT, in bytes
+const SNAPSHOT_PART: usize = 7000 * 8;
+
+/// Extra election timeout for each level of priority below the highest one
+const PRIORITY_DELAY: Duration = Duration::from_millis(500);
+
+/// Returns a randomized election timeout, so that nodes don't become candidates
+/// at the same time. Nodes with a lower priority wait longer, so among the nodes
+/// with an up to date log the one with the highest priority usually wins.
+fn election_timeout(node: &AlgloboNode) -> Duration {
+    let levels = node.cluster().max_priority() - node.cluster().priority(node.id());
+    Duration::from_millis(rand::thread_rng().gen_range(1500, 3000)) + PRIORITY_DELAY * levels as u32
+}
+
+/// RaftElection struct
+pub struct RaftElection {
+    /// Raft state of the node, with a lock and a condvar notified on every commit
+    /// and every change of role
+    state: (Mutex<RaftState>, Condvar),
+    /// Last time the leader sent appends to every follower
+    last_heartbeat: Mutex<Instant>,
+    /// Parts of the snapshot of the leader received so far
+    snapshot_parts: Mutex<SnapshotParts>,
+}
+
+impl RaftElection {
+    /// Creates the RaftElection for the given node, loading its saved state
+    pub fn new(id: usize, logger: &Logger) -> RaftElection {
+        RaftElection {
+            state: (Mutex::new(RaftState::load(id, logger)), Condvar::new()),
+            last_heartbeat: Mutex::new(Instant::now()),
+            snapshot_parts: Mutex::new(SnapshotParts::default()),
+        }
+    }
+
+    /// Locks the Raft state
+    fn lock(&self) -> MutexGuard<'_, RaftState> {
+        self.state.0.lock().expect("Unable to get raft lock")
+    }
+
+    /// Amount of nodes needed for a majority
+    fn majority(node: &AlgloboNode) -> usize {
+        node.cluster().size() / 2 + 1
+    }
+
+    /// Moves to a newer term as a follower. If the node was leading, it stops
+    /// being the leader until it hears from the new one.
+    fn step_down(&self, node: &AlgloboNode, state: &mut RaftState, term: usize) {
+        if state.role == RaftRole::Leader {
+            node.logger()
+                .info(format!("Stepping down, saw term {}", term));
+            node.clear_leader();
+        }
+        state.step_down(term);
+        self.state.1.notify_all();
+    }
+
+    /// Becomes the leader of the current term, appending a NOOP entry to commit
+    /// the entries of previous terms. The node announces itself as leader once
+    /// that entry is committed, so its coordinator state is up to date.
+    fn become_leader(&self, node: &AlgloboNode, state: &mut RaftState) {
+        node.logger()
+            .info(format!("Won the election for term {}", state.current_term));
+        state.role = RaftRole::Leader;
+        let next = state.last_index() + 1;
+        for id in node.cluster().ids() {
+            state.next_index.insert(id, next);
+            state.match_index.insert(id, 0);
+        }
+        let entry = RaftEntry {
+            term: state.current_term,
+            update: StateUpdate::new(NOOP, 0, ""),
+        };
+        state.store(next, vec![entry]);
+        self.advance_commit(node, state);
+        self.send_appends(node, state);
+    }
+
+    /// Sends the entries each follower is missing (or a heartbeat if none)
+    fn send_appends(&self, node: &AlgloboNode, state: &RaftState) {
+        for id in node.cluster().ids() {
+            if id != node.id() {
+                self.send_append(node, state, id);
+            }
+        }
+        *self.last_heartbeat.lock().expect("Unable to get lock") = Instant::now();
+    }
+
+    /// Sends the entries a follower is missing, starting from its next index,
+    /// or the snapshot if some of them were compacted
+    fn send_append(&self, node: &AlgloboNode, state: &RaftState, id: usize) {
+        let prev_index = state.next_index.get(&id).copied().unwrap_or(1) - 1;
+        if prev_index < state.snapshot_index {
+            self.send_snapshot(node, state, id);
+            return;
+        }
+        let mut fields = vec![
+            state.current_term,
+            node.id(),
+            prev_index,
+            state.term_at(prev_index).unwrap_or(0),
+            state.commit_index,
+        ];
+        for entry in state.entries_after(prev_index, MAX_ENTRIES) {
+            fields.push(entry.term);
+            fields.extend(entry.update.to_fields());
+        }
+        node.send_to(
+            &AlgloboNode::ids_to_msg(MSG_APPEND, &fields),
+            node.cluster().ctrl_addr(id),
+        );
+    }
+
+    /// Sends the snapshot to a follower, split in parts that fit in a datagram
+    fn send_snapshot(&self, node: &AlgloboNode, state: &RaftState, id: usize) {
+        let parts: Vec<&[u8]> = state.snapshot.chunks(SNAPSHOT_PART).collect();
+        if parts.len() > MAX_SNAPSHOT_PARTS {
+            node.logger().info(format!(
+                "Snapshot of {} bytes is too big to send to node {}",
+                state.snapshot.len(),
+                id
+            ));
+            return;
+        }
+        node.logger().trace(format!(
+            "Sending snapshot up to entry {} to node {} in {} parts",
+            state.snapshot_index,
+            id,
+            parts.len()
+        ));
+        for (i, part) in parts.iter().enumerate() {
+            let mut fields = vec![
+                state.current_term,
+                node.id(),
+                state.snapshot_index,
+                state.snapshot_term,
+                i,
+                parts.len(),
+                part.len(),
+            ];
+            for word in part.chunks(8) {
+                let mut bytes = [0; 8];
+                bytes[..word.len()].copy_from_slice(word);
+                fields.push(u64::from_be_bytes(bytes) as usize);
+            }
+            node.send_to(
+                &AlgloboNode::ids_to_msg(MSG_INSTALL_SNAPSHOT, &fields),
+                node.cluster().ctrl_addr(id),
+            );
+        }
+    }
+
+    /// Commits the last entry of the current term stored by a majority, and
+    /// applies every newly committed entry
+    fn advance_commit(&self, node: &AlgloboNode, state: &mut RaftState) {
+        for index in (state.commit_index + 1..=state.last_index()).rev() {
+            if state.term_at(index) != Some(state.current_term) {
+                break;
+            }
+            let replicas = node
+                .cluster()
+                .ids()
+                .iter()
+                .filter(|id| {
+                    **id == node.id() || state.match_index.get(id).copied().unwrap_or(0) >= index
+                })
+                .count();
+            if replicas >= RaftElection::majority(node) {
+                state.commit_index = index;
+                self.apply_committed(node, state);
+                node.set_leader(node.id());
+                break;
+            }
+        }
+    }
+
+    /// Applies the committed entries to the coordinator state of the node,
+    /// starting from the snapshot if it's ahead, and compacts them once there
+    /// are enough
+    fn apply_committed(&self, node: &AlgloboNode, state: &mut RaftState) {
+        if state.last_applied < state.snapshot_index {
+            self.restore_snapshot(node, state);
+        }
+        while state.last_applied < state.commit_index {
+            let update = state.entry(state.last_applied + 1).update.clone();
+            state.last_applied += 1;
+            if update.status != NOOP {
+                node.apply_state(&update);
+            }
+        }
+        if state.last_applied >= state.snapshot_index + COMPACT_AFTER {
+            node.logger().trace(format!(
+                "Compacting the raft log up to entry {}",
+                state.last_applied
+            ));
+            state.compact(node.state_bytes());
+        }
+        self.state.1.notify_all();
+    }
+
+    /// Replaces the coordinator state of the node with the snapshot
+    fn restore_snapshot(&self, node: &AlgloboNode, state: &mut RaftState) {
+        match CoordinatorState::from_bytes(&state.snapshot) {
+            Ok(snapshot) => node.restore_state(snapshot),
+            Err(err) => node
+                .logger()
+                .info(format!("Couldn't restore raft snapshot: {}", err)),
+        }
+        state.last_applied = state.snapshot_index;
+    }
+
+    /// Handles a REQUEST_VOTE, granting it if the candidate log is at least as
+    /// up to date as ours and we didn't vote for someone else in this term
+    fn handle_request_vote(&self, node: &AlgloboNode, fields: &[usize], from: SocketAddr) {
+        let (term, candidate, last_index, last_term) = (fields[0], fields[1], fields[2], fields[3]);
+        let mut state = self.lock();
+        if term > state.current_term {
+            self.step_down(node, &mut state, term);
+        }
+
+        let up_to_date = last_term > state.last_term()
+            || (last_term == state.last_term() && last_index >= state.last_index());
+        let granted = term == state.current_term
+            && state.voted_for.is_none_or(|id| id == candidate)
+            && up_to_date;
+        if granted {
+            state.voted_for = Some(candidate);
+            state.last_heard = Instant::now();
+            state.save();
+        }
+        node.logger().trace(format!(
+            "Vote for {} in term {}: {}",
+            candidate, term, granted
+        ));
+        node.send_to(
+            &AlgloboNode::ids_to_msg(MSG_VOTE, &[state.current_term, granted as usize, node.id()]),
+            from,
+        );
+    }
+
+    /// Handles a VOTE, becoming the leader with the votes of a majority
+    fn handle_vote(&self, node: &AlgloboNode, fields: &[usize]) {
+        let (term, granted, voter) = (fields[0], fields[1] == 1, fields[2]);
+        let mut state = self.lock();
+        if term > state.current_term {
+            self.step_down(node, &mut state, term);
+            return;
+        }
+        if state.role != RaftRole::Candidate || term != state.current_term || !granted {
+            return;
+        }
+        if !state.votes.contains(&voter) {
+            state.votes.push(voter);
+        }
+        if state.votes.len() >= RaftElection::majority(node) {
+            self.become_leader(node, &mut state);
+        }
+    }
+
+    /// Handles an APPEND from the leader, storing its entries if the log matches
+    /// up to the previous index and committing up to the leader commit index
+    fn handle_append(&self, node: &AlgloboNode, fields: &[usize], from: SocketAddr) {
+        let (term, leader, prev_index, prev_term, leader_commit) =
+            (fields[0], fields[1], fields[2], fields[3], fields[4]);
+        let entries = match entries_from_fields(&fields[5..]) {
+            Some(entries) => entries,
+            None => return,
+        };
+        let mut state = self.lock();
+        if term < state.current_term {
+            node.send_to(
+                &AlgloboNode::ids_to_msg(
+                    MSG_APPEND_REPLY,
+                    &[state.current_term, 0, node.id(), state.last_index()],
+                ),
+                from,
+            );
+            return;
+        }
+        if term > state.current_term || state.role != RaftRole::Follower {
+            self.step_down(node, &mut state, term);
+        }
+        state.last_heard = Instant::now();
+        node.set_leader(leader);
+
+        // Entries up to the snapshot are committed, so they match the leader's
+        if prev_index > state.last_index()
+            || (prev_index >= state.snapshot_index && state.term_at(prev_index) != Some(prev_term))
+        {
+            let hint = state.last_index().min(prev_index.saturating_sub(1));
+            node.send_to(
+                &AlgloboNode::ids_to_msg(
+                    MSG_APPEND_REPLY,
+                    &[state.current_term, 0, node.id(), hint],
+                ),
+                from,
+            );
+            return;
+        }
+
+        let mut index = prev_index;
+        let mut new = vec![];
+        for entry in entries {
+            index += 1;
+            // Entries the node has are skipped, up to the first one that isn't
+            if new.is_empty()
+                && (index <= state.snapshot_index || state.term_at(index) == Some(entry.term))
+            {
+                continue;
+            }
+            new.push(entry);
+        }
+        if !new.is_empty() {
+            state.store(index + 1 - new.len(), new);
+        }
+
+        if leader_commit > state.commit_index {
+            state.commit_index = leader_commit.min(index);
+            self.apply_committed(node, &mut state);
+        }
+        node.send_to(
+            &AlgloboNode::ids_to_msg(MSG_APPEND_REPLY, &[state.current_term, 1, node.id(), index]),
+            from,
+        );
+    }
+
+    /// Handles a part of an INSTALL_SNAPSHOT, and once every part arrived
+    /// replaces the log up to the snapshot with it, unless the node already
+    /// applied those entries
+    fn handle_install_snapshot(&self, node: &AlgloboNode, fields: &[usize], from: SocketAddr) {
+        let (term, leader, index, snapshot_term) = (fields[0], fields[1], fields[2], fields[3]);
+        let (part, count, len) = (fields[4], fields[5], fields[6]);
+        let mut state = self.lock();
+        if term < state.current_term {
+            node.send_to(
+                &AlgloboNode::ids_to_msg(
+                    MSG_APPEND_REPLY,
+                    &[state.current_term, 0, node.id(), state.last_index()],
+                ),
+                from,
+            );
+            return;
+        }
+        if term > state.current_term || state.role != RaftRole::Follower {
+            self.step_down(node, &mut state, term);
+        }
+        state.last_heard = Instant::now();
+        node.set_leader(leader);
+
+        let mut bytes: Vec<u8> = fields[SNAPSHOT_HEADER..]
+            .iter()
+            .flat_map(|word| (*word as u64).to_be_bytes())
+            .collect();
+        bytes.truncate(len);
+        let snapshot = match self.snapshot_parts.lock().expect("Unable to get lock").add(
+            (index, snapshot_term),
+            part,
+            count,
+            &bytes,
+        ) {
+            Some(snapshot) => snapshot,
+            // The rest of the parts are still on their way
+            None => return,
+        };
+
+        if index > state.last_applied {
+            if let Err(err) = CoordinatorState::from_bytes(&snapshot) {
+                node.logger()
+                    .info(format!("Discarded raft snapshot from {}: {}", from, err));
+                return;
+            }
+            node.logger()
+                .trace(format!("Installing snapshot up to entry {}", index));
+            state.install(index, snapshot_term, snapshot);
+            self.restore_snapshot(node, &mut state);
+            self.apply_committed(node, &mut state);
+        }
+        node.send_to(
+            &AlgloboNode::ids_to_msg(MSG_APPEND_REPLY, &[state.current_term, 1, node.id(), index]),
+            from,
+        );
+    }
+
+    /// Handles an APPEND_REPLY, moving the follower indexes forward on success
+    /// or backwards on failure, where it gets the missing entries right away
+    fn handle_append_reply(&self, node: &AlgloboNode, fields: &[usize]) {
+        let (term, success, follower, index) = (fields[0], fields[1] == 1, fields[2], fields[3]);
+        let mut state = self.lock();
+        if term > state.current_term {
+            self.step_down(node, &mut state, term);
+            return;
+        }
+        if state.role != RaftRole::Leader || term != state.current_term {
+            return;
+        }
+        if success {
+            let matched = state.match_index.get(&follower).copied().unwrap_or(0);
+            state.match_index.insert(follower, matched.max(index));
+            state.next_index.insert(follower, index + 1);
+            self.advance_commit(node, &mut state);
+        } else {
+            let next = state.next_index.get(&follower).copied().unwrap_or(1);
+            state
+                .next_index
+                .insert(follower, (index + 1).min(next - 1).max(1));
+            self.send_append(node, &state, follower);
+        }
+    }
+}
+
+impl Election for RaftElection {
+    fn name(&self) -> &'static str {
+        "raft"
+    }
+
+    /// Becomes a candidate for a new term and asks every node for its vote,
+    /// repeating with a new term after a randomized timeout until there is a leader.
+    fn start(&self, node: &AlgloboNode) {
+        while !node.is_stopped() {
+            {
+                let mut state = self.lock();
+                state.current_term += 1;
+                state.role = RaftRole::Candidate;
+                state.voted_for = Some(node.id());
+                state.votes = vec![node.id()];
+                state.last_heard = Instant::now();
+                state.save();
+                node.logger()
+                    .trace(format!("Candidate for term {}", state.current_term));
+
+                let msg = AlgloboNode::ids_to_msg(
+                    MSG_REQUEST_VOTE,
+                    &[
+                        state.current_term,
+                        node.id(),
+                        state.last_index(),
+                        state.last_term(),
+                    ],
+                );
+                for id in node.cluster().ids() {
+                    if id != node.id() {
+                        node.send_to(&msg, node.cluster().ctrl_addr(id));
+                    }
+                }
+                if state.votes.len() >= RaftElection::majority(node) {
+                    self.become_leader(node, &mut state);
+                }
+            }
+            if node.wait_leader(election_timeout(node)).is_some() {
+                return;
+            }
+        }
+    }
+
+    /// Checks the amount of fields of each message, listed in the module docs,
+    /// and that the entries of an APPEND are whole
+    fn valid_fields(&self, msg_type: u8, fields: &[usize]) -> bool {
+        match msg_type {
+            MSG_REQUEST_VOTE | MSG_APPEND_REPLY => fields.len() == 4,
+            MSG_VOTE => fields.len() == 3,
+            MSG_APPEND => fields.len() >= 5 && entries_from_fields(&fields[5..]).is_some(),
+            MSG_TIMEOUT_NOW => fields.len() == 1,
+            MSG_INSTALL_SNAPSHOT => {
+                fields.len() >= SNAPSHOT_HEADER
+                    && fields[4] < fields[5]
+                    && fields[5] <= MAX_SNAPSHOT_PARTS
+                    && fields[6] <= SNAPSHOT_PART
+                    && fields.len() - SNAPSHOT_HEADER == fields[6].div_ceil(8)
+            }
+            _ => true,
+        }
+    }
+
+    fn handle(&self, node: &AlgloboNode, msg_type: u8, ids: Vec<usize>, from: SocketAddr) -> bool {
+        match msg_type {
+            MSG_REQUEST_VOTE => self.handle_request_vote(node, &ids, from),
+            MSG_VOTE => self.handle_vote(node, &ids),
+            MSG_APPEND => self.handle_append(node, &ids, from),
+            MSG_APPEND_REPLY => self.handle_append_reply(node, &ids),
+            MSG_INSTALL_SNAPSHOT => self.handle_install_snapshot(node, &ids, from),
+            MSG_TIMEOUT_NOW => {
+                let state = self.lock();
+                if ids[0] == state.current_term && state.role == RaftRole::Follower {
+                    node.logger()
+                        .info(format!("Taking over as leader after term {}", ids[0]));
+                    let clone = node.clone();
+                    thread::Builder::new()
+                        .name(format!("Node{}-Candidate", node.id()))
+                        .spawn(move || clone.start_election())
+                        .expect("node candidate thread creation failed");
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Appends the update to the log and waits until a majority stored it.
+    fn replicate(&self, node: &AlgloboNode, update: &StateUpdate) -> bool {
+        let mut state = self.lock();
+        if state.role != RaftRole::Leader {
+            return false;
+        }
+        let term = state.current_term;
+        let index = state.last_index() + 1;
+        let entry = RaftEntry {
+            term,
+            update: update.clone(),
+        };
+        state.store(index, vec![entry]);
+        self.advance_commit(node, &mut state);
+        self.send_appends(node, &state);
+
+        // Waits with a timeout to notice if the node gets killed meanwhile
+        while state.commit_index < index
+            && state.role == RaftRole::Leader
+            && state.current_term == term
+            && !node.is_stopped()
+        {
+            state = self
+                .state
+                .1
+                .wait_timeout(state, HEARTBEAT_INTERVAL)
+                .expect("Unable to wait for condvar")
+                .0;
+        }
+        // A compacted entry is the one of this term as long as no later leader
+        // came along, as it would have changed the term of the node
+        state.commit_index >= index
+            && match state.term_at(index) {
+                Some(entry_term) => entry_term == term,
+                None => state.current_term == term,
+            }
+    }
+
+    /// The follower that stored the most entries, preferring the highest id on a tie
+    fn best_replica(&self, node: &AlgloboNode) -> Option<usize> {
+        self.lock()
+            .match_index
+            .iter()
+            .filter(|(id, _)| **id != node.id())
+            .max_by_key(|(id, matched)| (**matched, **id))
+            .map(|(id, _)| *id)
+    }
+
+    /// Sends the target the entries it is missing, and once its log matches
+    /// asks it to start an election, which it wins for having the whole log.
+    /// This node steps down meanwhile, so it doesn't append new entries.
+    fn hand_over(&self, node: &AlgloboNode, target: usize) -> bool {
+        let mut state = self.lock();
+        let start = Instant::now();
+        while state.match_index.get(&target).copied().unwrap_or(0) < state.last_index() {
+            if state.role != RaftRole::Leader || start.elapsed() > TIMEOUT {
+                return false;
+            }
+            self.send_append(node, &state, target);
+            state = self
+                .state
+                .1
+                .wait_timeout(state, HEARTBEAT_INTERVAL / 3)
+                .expect("Unable to wait for condvar")
+                .0;
+        }
+        let term = state.current_term;
+        self.step_down(node, &mut state, term);
+        node.send_to(
+            &AlgloboNode::ids_to_msg(MSG_TIMEOUT_NOW, &[term]),
+            node.cluster().ctrl_addr(target),
+        );
+        true
+    }
+
+    /// The new leader lets the others know through its appends
+    fn announce(&self, _node: &AlgloboNode) {}
+
+    /// Terms already increase with every leader, so they are used as epochs.
+    fn new_epoch(&self, _node: &AlgloboNode) -> usize {
+        self.lock().current_term
+    }
+
+    /// Followers hear from the leader through its appends, so this only checks
+    /// that they keep arriving before a randomized election timeout.
+    fn follow(&self, node: &AlgloboNode) -> bool {
+        let timeout = election_timeout(node);
+        while !node.is_stopped() {
+            sleep(HEARTBEAT_INTERVAL / 3);
+            let state = self.lock();
+            if state.role == RaftRole::Leader {
+                return true;
+            }
+            if state.last_heard.elapsed() > timeout {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Nodes catch up from the appends of the leader, which go back in the log
+    /// until they find the last entry the node has.
+    fn catch_up(&self, _node: &AlgloboNode, _id: usize) {}
+
+    /// Sends appends to every follower if the leader was quiet for a heartbeat interval.
+    fn tick(&self, node: &AlgloboNode) {
+        let state = self.lock();
+        let quiet = self
+            .last_heartbeat
+            .lock()
+            .expect("Unable to get lock")
+            .elapsed()
+            > HEARTBEAT_INTERVAL;
+        if state.role == RaftRole::Leader && quiet {
+            self.send_appends(node, &state);
+        }
+    }
+}
+
+/// Translates the fields of the entries of an APPEND, the term and the update
+/// fields of each one, or returns None if an entry isn't whole
+fn entries_from_fields(fields: &[usize]) -> Option<Vec<RaftEntry>> {
+    let mut entries = vec![];
+    let mut pos = 0;
+    while pos < fields.len() {
+        let (update, len) = StateUpdate::from_fields(&fields[pos + 1..])?;
+        entries.push(RaftEntry {
+            term: fields[pos],
+            update,
+        });
+        pos += 1 + len;
+    }
+    Some(entries)
+}
@@ -0,0 +1,18 @@
+//! RaftEntry struct
+//!
+//! A single entry of the replicated coordinator log used by the Raft algorithm.
+
+use crate::state_update::StateUpdate;
+
+/// Kind of update of the entry a new Raft leader appends to commit the entries
+/// of previous terms. It doesn't change the coordinator state.
+pub const NOOP: u8 = b'N';
+
+/// RaftEntry struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct RaftEntry {
+    /// Term of the leader that appended the entry
+    pub term: usize,
+    /// Update of the coordinator state, or a NOOP
+    pub update: StateUpdate,
+}
@@ -0,0 +1,443 @@
+//! RaftState struct
+//!
+//! State each node keeps for the Raft algorithm. The term and the vote are
+//! saved to a file on every change, and the log to another one where each new
+//! entry is appended and synced, so a node that restarts keeps them. Once enough
+//! entries were applied, they are replaced by a snapshot of the coordinator
+//! state, saved to a third file.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::logger::Logger;
+use crate::raft_entry::RaftEntry;
+use crate::state_update::StateUpdate;
+
+/// Directory where every node saves its Raft state
+pub const RAFT_DIR: &str = "raft/";
+/// Amount of applied entries after which the log is compacted into a snapshot
+pub const COMPACT_AFTER: usize = 1000;
+
+/// Role of a node in the Raft algorithm
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RaftRole {
+    /// Receives the log from the leader
+    Follower,
+    /// Asks the other nodes for votes to become the leader
+    Candidate,
+    /// Replicates its log to the followers
+    Leader,
+}
+
+/// RaftState struct
+#[derive(Debug)]
+pub struct RaftState {
+    /// Latest term the node has seen
+    pub current_term: usize,
+    /// Node voted for in the current term, if any
+    pub voted_for: Option<usize>,
+    /// Replicated log after the snapshot, where the entry at position i has
+    /// index snapshot_index + i + 1
+    log: Vec<RaftEntry>,
+    /// Index of the last entry included in the snapshot
+    pub snapshot_index: usize,
+    /// Term of the last entry included in the snapshot
+    pub snapshot_term: usize,
+    /// Coordinator state after applying every entry up to the snapshot index
+    pub snapshot: Vec<u8>,
+    /// Index of the last committed entry
+    pub commit_index: usize,
+    /// Index of the last entry applied to the coordinator state
+    pub last_applied: usize,
+    /// Current role of the node
+    pub role: RaftRole,
+    /// Votes received as a candidate in the current term
+    pub votes: Vec<usize>,
+    /// For each follower, index of the next entry to send (only for the leader)
+    pub next_index: HashMap<usize, usize>,
+    /// For each follower, index of the last entry known to be replicated (only for the leader)
+    pub match_index: HashMap<usize, usize>,
+    /// Last time the node heard from a valid leader or granted a vote
+    pub last_heard: Instant,
+    /// Files where the persistent part of the state is saved, without extension
+    filename: String,
+    /// Log file, opened to append new entries
+    log_file: File,
+}
+
+impl RaftState {
+    /// Loads the state of the node from its files, or starts from scratch if
+    /// there are none. An incomplete or corrupted tail of the log, left by a
+    /// crash in the middle of a write, is logged and cut off.
+    pub fn load(id: usize, logger: &Logger) -> RaftState {
+        fs::create_dir_all(RAFT_DIR).expect("Couldn't create raft directory");
+        let filename = format!("{}node-{}", RAFT_DIR, id);
+        let log_file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(format!("{}.log", filename))
+            .expect("Couldn't open raft log");
+        let mut state = RaftState {
+            current_term: 0,
+            voted_for: None,
+            log: vec![],
+            snapshot_index: 0,
+            snapshot_term: 0,
+            snapshot: vec![],
+            commit_index: 0,
+            last_applied: 0,
+            role: RaftRole::Follower,
+            votes: vec![],
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            last_heard: Instant::now(),
+            filename,
+            log_file,
+        };
+
+        if let Ok(contents) = fs::read(format!("{}.snapshot", state.filename)) {
+            match parse_snapshot(&contents) {
+                Some((index, term, snapshot)) => {
+                    state.snapshot_index = index;
+                    state.snapshot_term = term;
+                    state.snapshot = snapshot.to_vec();
+                    // Only committed entries are compacted
+                    state.commit_index = index;
+                }
+                None => logger.info("Discarded corrupted raft snapshot".to_string()),
+            }
+        }
+
+        let contents = fs::read(format!("{}.log", state.filename)).unwrap_or_default();
+        let (log, valid) = parse_log(&contents, state.snapshot_index);
+        state.log = log;
+        if valid < contents.len() {
+            logger.info(format!(
+                "Discarded {} bytes of torn raft log tail after {} entries",
+                contents.len() - valid,
+                state.last_index()
+            ));
+            state
+                .log_file
+                .set_len(valid as u64)
+                .and_then(|_| state.log_file.sync_data())
+                .expect("Couldn't truncate raft log");
+        }
+
+        match fs::read_to_string(format!("{}.state", state.filename))
+            .ok()
+            .and_then(|contents| parse_vote(&contents))
+        {
+            Some((term, voted_for)) => {
+                state.current_term = term;
+                state.voted_for = voted_for;
+            }
+            // Without its vote the node starts after the last term it knows of
+            None if state.last_index() > 0 => {
+                state.current_term = state.last_term() + 1;
+                logger.info(format!(
+                    "Missing raft term and vote, starting from term {}",
+                    state.current_term
+                ));
+            }
+            None => {}
+        }
+        state
+    }
+
+    /// Saves the term and the vote to the file of the node
+    pub fn save(&self) {
+        let vote = format!(
+            "{},{}\n",
+            self.current_term,
+            self.voted_for
+                .map(|id| id.to_string())
+                .unwrap_or_else(|| "-".to_string())
+        );
+        write_synced(&format!("{}.state", self.filename), vote.as_bytes())
+            .expect("Couldn't save raft state");
+    }
+
+    /// Stores the entries from the given index on, replacing the ones there
+    /// and after them, and waits until the log file has them
+    pub fn store(&mut self, index: usize, entries: Vec<RaftEntry>) {
+        let mut contents = String::new();
+        for (i, entry) in entries.iter().enumerate() {
+            contents.push_str(&entry_line(index + i, entry));
+        }
+        self.log_file
+            .write_all(contents.as_bytes())
+            .and_then(|_| self.log_file.sync_data())
+            .expect("Couldn't save raft log");
+        self.log.truncate(index - self.snapshot_index - 1);
+        self.log.extend(entries);
+    }
+
+    /// Replaces the applied entries by a snapshot of the coordinator state
+    /// after them, and rewrites the log file with the rest
+    pub fn compact(&mut self, snapshot: Vec<u8>) {
+        self.snapshot_term = self
+            .term_at(self.last_applied)
+            .unwrap_or(self.snapshot_term);
+        self.log.drain(..self.last_applied - self.snapshot_index);
+        self.snapshot_index = self.last_applied;
+        self.snapshot = snapshot;
+        self.save_snapshot();
+    }
+
+    /// Replaces the log up to the given index by a snapshot from the leader.
+    /// The entries after it are kept if the log has the last entry of the
+    /// snapshot, as they may still be committed.
+    pub fn install(&mut self, index: usize, term: usize, snapshot: Vec<u8>) {
+        if self.term_at(index) == Some(term) {
+            self.log.drain(..index - self.snapshot_index);
+        } else {
+            self.log.clear();
+        }
+        self.snapshot_index = index;
+        self.snapshot_term = term;
+        self.snapshot = snapshot;
+        self.commit_index = self.commit_index.max(index);
+        self.last_applied = index;
+        self.save_snapshot();
+    }
+
+    /// Saves the snapshot, and then the entries after it as the new log file
+    fn save_snapshot(&mut self) {
+        let mut contents = format!("{},{}\n", self.snapshot_index, self.snapshot_term).into_bytes();
+        contents.extend(&self.snapshot);
+        write_synced(&format!("{}.snapshot", self.filename), &contents)
+            .expect("Couldn't save raft snapshot");
+
+        let mut contents = String::new();
+        for (i, entry) in self.log.iter().enumerate() {
+            contents.push_str(&entry_line(self.snapshot_index + i + 1, entry));
+        }
+        let filename = format!("{}.log", self.filename);
+        write_synced(&filename, contents.as_bytes()).expect("Couldn't save raft log");
+        self.log_file = OpenOptions::new()
+            .append(true)
+            .open(filename)
+            .expect("Couldn't open raft log");
+    }
+
+    /// Index of the last entry of the log
+    pub fn last_index(&self) -> usize {
+        self.snapshot_index + self.log.len()
+    }
+
+    /// Term of the last entry of the log
+    pub fn last_term(&self) -> usize {
+        self.term_at(self.last_index()).unwrap_or(0)
+    }
+
+    /// Term of the entry at the given index, 0 for the index before the first
+    /// entry, or None if the log doesn't have it or it was compacted
+    pub fn term_at(&self, index: usize) -> Option<usize> {
+        if index == self.snapshot_index {
+            Some(self.snapshot_term)
+        } else if index < self.snapshot_index {
+            None
+        } else {
+            self.log
+                .get(index - self.snapshot_index - 1)
+                .map(|entry| entry.term)
+        }
+    }
+
+    /// Entry at the given index, which must be in the log after the snapshot
+    pub fn entry(&self, index: usize) -> &RaftEntry {
+        &self.log[index - self.snapshot_index - 1]
+    }
+
+    /// Up to `max` entries that follow the given index, which must not be
+    /// before the snapshot
+    pub fn entries_after(&self, index: usize, max: usize) -> &[RaftEntry] {
+        let start = index - self.snapshot_index;
+        &self.log[start..self.log.len().min(start + max)]
+    }
+
+    /// Moves to a newer term as a follower, forgetting the vote of the older one
+    pub fn step_down(&mut self, term: usize) {
+        if term > self.current_term {
+            self.current_term = term;
+            self.voted_for = None;
+        }
+        self.role = RaftRole::Follower;
+        self.save();
+    }
+}
+
+/// Line of the log file with the index and the term of an entry, and then its update
+fn entry_line(index: usize, entry: &RaftEntry) -> String {
+    let data: Vec<String> = entry.update.data.iter().map(u32::to_string).collect();
+    format!(
+        "{},{},{},{},{},{},{}\n",
+        index,
+        entry.term,
+        entry.update.status,
+        entry.update.id,
+        data.join(" "),
+        entry.update.time,
+        entry.update.payment
+    )
+}
+
+/// Parses a line of the log file into the index and the entry, or returns
+/// None if it's corrupted
+fn parse_entry(line: &str) -> Option<(usize, RaftEntry)> {
+    let fields: Vec<&str> = line.splitn(7, ',').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    let data = fields[4]
+        .split_whitespace()
+        .map(|value| value.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    let entry = RaftEntry {
+        term: fields[1].parse().ok()?,
+        update: StateUpdate {
+            status: fields[2].parse().ok()?,
+            id: fields[3].parse().ok()?,
+            time: fields[5].parse().ok()?,
+            payment: fields[6].to_string(),
+            data,
+        },
+    };
+    Some((fields[0].parse().ok()?, entry))
+}
+
+/// Parses the entries of a log file that follow the snapshot index, where an
+/// entry replaces the one with its index and every entry after it. Returns them
+/// with the amount of bytes of whole and valid entries, up to the first one
+/// that isn't.
+fn parse_log(contents: &[u8], snapshot_index: usize) -> (Vec<RaftEntry>, usize) {
+    let mut log = vec![];
+    let mut valid = 0;
+    while let Some(len) = contents[valid..].iter().position(|byte| *byte == b'\n') {
+        let line = &contents[valid..valid + len];
+        let (index, entry) = match std::str::from_utf8(line).ok().and_then(parse_entry) {
+            Some(entry) if entry.0 >= 1 && entry.0 <= snapshot_index + log.len() + 1 => entry,
+            _ => break,
+        };
+        if index > snapshot_index {
+            log.truncate(index - snapshot_index - 1);
+            log.push(entry);
+        }
+        valid += len + 1;
+    }
+    (log, valid)
+}
+
+/// Parses the term and the vote of the state file, or returns None if it's corrupted
+fn parse_vote(contents: &str) -> Option<(usize, Option<usize>)> {
+    let mut fields = contents.trim_end().split(',');
+    let term = fields.next()?.parse().ok()?;
+    let voted_for = match fields.next()? {
+        "-" => None,
+        id => Some(id.parse().ok()?),
+    };
+    match fields.next() {
+        Some(_) => None,
+        None => Some((term, voted_for)),
+    }
+}
+
+/// Parses the index and the term of the last entry of a snapshot file, followed
+/// by the snapshot, or returns None if it's corrupted
+fn parse_snapshot(contents: &[u8]) -> Option<(usize, usize, &[u8])> {
+    let len = contents.iter().position(|byte| *byte == b'\n')?;
+    let header = std::str::from_utf8(&contents[..len]).ok()?;
+    let (index, term) = header.split_once(',')?;
+    Some((
+        index.parse().ok()?,
+        term.parse().ok()?,
+        &contents[len + 1..],
+    ))
+}
+
+/// Replaces a file with the given contents, writing them to a temporary file
+/// that is synced and then renamed, so the file is never left half written
+fn write_synced(filename: &str, contents: &[u8]) -> io::Result<()> {
+    let tmp = format!("{}.tmp", filename);
+    let mut file = File::create(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(&tmp, filename)?;
+    File::open(RAFT_DIR)?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(term: usize, id: usize) -> RaftEntry {
+        RaftEntry {
+            term,
+            update: StateUpdate {
+                status: b'P',
+                id,
+                payment: format!("PAY-{},x", id),
+                time: 1,
+                data: vec![0, 10, 2, 30],
+            },
+        }
+    }
+
+    #[test]
+    fn entry_line_round_trip() {
+        let line = entry_line(3, &entry(2, 7));
+        assert_eq!(parse_entry(line.trim_end()), Some((3, entry(2, 7))));
+    }
+
+    #[test]
+    fn log_keeps_the_last_entry_of_each_index() {
+        let contents = [
+            entry_line(1, &entry(1, 0)),
+            entry_line(2, &entry(1, 1)),
+            entry_line(3, &entry(1, 2)),
+            entry_line(2, &entry(2, 3)),
+        ]
+        .concat();
+        let (log, valid) = parse_log(contents.as_bytes(), 0);
+        assert_eq!(log, vec![entry(1, 0), entry(2, 3)]);
+        assert_eq!(valid, contents.len());
+
+        let (log, _) = parse_log(contents.as_bytes(), 1);
+        assert_eq!(log, vec![entry(2, 3)]);
+    }
+
+    #[test]
+    fn torn_tail_is_cut_off() {
+        let whole = [entry_line(1, &entry(1, 0)), entry_line(2, &entry(1, 1))].concat();
+        let torn = entry_line(3, &entry(1, 2));
+        let contents = format!("{}{}", whole, &torn[..torn.len() / 2]);
+        let (log, valid) = parse_log(contents.as_bytes(), 0);
+        assert_eq!(log, vec![entry(1, 0), entry(1, 1)]);
+        assert_eq!(valid, whole.len());
+
+        // A gap in the indexes or a garbled line also ends the valid entries
+        let contents = format!("{}{}", whole, entry_line(4, &entry(1, 3)));
+        assert_eq!(parse_log(contents.as_bytes(), 0).1, whole.len());
+        let contents = format!("{}3,1,80\n{}", whole, torn);
+        assert_eq!(parse_log(contents.as_bytes(), 0).1, whole.len());
+        assert_eq!(parse_log(&[0xff, b'\n'], 0), (vec![], 0));
+    }
+
+    #[test]
+    fn corrupted_lines_are_rejected() {
+        assert_eq!(parse_entry(""), None);
+        assert_eq!(parse_entry("1,1,80,0"), None);
+        assert_eq!(parse_entry("1,x,80,0,,1,PAY"), None);
+        assert_eq!(parse_entry("1,1,80,0,1 y,1,PAY"), None);
+        assert_eq!(parse_vote("3,-\n"), Some((3, None)));
+        assert_eq!(parse_vote("3,2"), Some((3, Some(2))));
+        assert_eq!(parse_vote("3"), None);
+        assert_eq!(parse_vote("3,2,1"), None);
+        assert_eq!(parse_snapshot(b"4,2\nabc"), Some((4, 2, &b"abc"[..])));
+        assert_eq!(parse_snapshot(b"4,2"), None);
+        assert_eq!(parse_snapshot(b"4\nabc"), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! AlGlobo Reconcile - Check the agents against the coordinator
+//! ---
+//! This program reconciles the outcome ledger the leader writes, with a row per
+//! decided transaction, with the records of every agent of the agents.yaml
+//! config file, matching them by payment id.
+//!
+//! Start the program with `cargo run --bin reconcile` after a run, or with
+//! `cargo run --bin reconcile -- [--logs <dir>] [--ledger <file>]` to check the
+//! logs and the outcome ledger of another run. By default it reads `logs/` and
+//! `src/prices-outcomes.csv`, or `src/prices-outcomes.jsonl` if there is no CSV
+//! ledger.
+//!
+//! The journal of an agent only lives in its memory, so its record of each
+//! payment is the last state its primary logged: PREPARE, COMMIT or ABORT.
+//! These are reported as discrepancies:
+//! - A line of the ledger that can't be read, or a payment that is twice in it.
+//! - A payment that some agents committed and others aborted.
+//! - An agent with a different decision than the ledger.
+//! - An agent stuck in PREPARE, as it never got a decision.
+//! - An agent involved in a committed payment that has no record of it.
+//! - An agent that committed a payment the ledger doesn't have.
+//! - A payment of the ledger involving an agent the config doesn't have.
+//!
+//! An agent may have no record of an aborted payment, as the leader could
+//! have died before sending it the PREPARE.
+//!
+//! The program prints every discrepancy and exits with status 1 if there is
+//! any, so that a run can be gated on it.
+
+#![forbid(unsafe_code)]
+#![allow(dead_code)]
+mod communication;
+mod coordinator_state;
+mod discrepancy;
+mod frame_error;
+mod frame_reader;
+mod invalid_line;
+mod ledgers;
+mod log_line;
+pub mod logger;
+mod outcome;
+mod payment;
+mod payment_columns;
+mod payment_error;
+mod payments_format;
+mod reconcile_args;
+mod reconciliation;
+mod spool_reader;
+mod state_update;
+mod utils;
+mod validation_report;
+
+use std::{env, process};
+
+use ledgers::read_outcomes;
+use log_line::LogLine;
+use reconcile_args::ReconcileArgs;
+use reconciliation::Reconciliation;
+use utils::get_agents_names;
+
+/// Main function. Reconciles the outcome ledger with the logs of the agents
+/// and exits with status 1 if they disagree.
+fn main() {
+    let args = ReconcileArgs::parse(env::args().skip(1));
+    let agents = get_agents_names();
+    let ledger = args.ledger();
+    let outcomes = read_outcomes(&ledger, &agents);
+    let lines = LogLine::read_dir(&args.logs_dir);
+
+    let reconciliation = Reconciliation::new(&outcomes, &lines, &agents);
+    println!(
+        "Reconciled {} outcomes of {} with the logs of {} in {}: {} payments",
+        reconciliation.outcomes,
+        ledger,
+        agents.join(", "),
+        args.logs_dir,
+        reconciliation.payments
+    );
+    for discrepancy in &reconciliation.discrepancies {
+        println!("{}", discrepancy);
+    }
+    if !reconciliation.is_consistent() {
+        println!("{} discrepancies found", reconciliation.discrepancies.len());
+        process::exit(1);
+    }
+    println!("No discrepancies found");
+}
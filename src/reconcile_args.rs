@@ -0,0 +1,45 @@
+//! ReconcileArgs struct
+//!
+//! Command line arguments of the reconcile program.
+
+use crate::ledgers::{existing_ledger, OUTCOME_FILE};
+use crate::logger::PREFIX_PATH;
+
+/// ReconcileArgs struct
+#[derive(Debug, Clone)]
+pub struct ReconcileArgs {
+    /// Directory with the logs of the agents
+    pub logs_dir: String,
+    /// Outcome ledger of the coordinator, or None to use the default one
+    pub ledger: Option<String>,
+}
+
+impl ReconcileArgs {
+    /// Parses the arguments of the program, where `args` doesn't include the
+    /// program name. Accepts `--logs <dir>` and `--ledger <file>`.
+    pub fn parse(args: impl Iterator<Item = String>) -> ReconcileArgs {
+        let mut parsed = ReconcileArgs {
+            logs_dir: PREFIX_PATH.to_string(),
+            ledger: None,
+        };
+
+        let mut args = args;
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--logs" => parsed.logs_dir = args.next().expect("--logs needs a value"),
+                "--ledger" => parsed.ledger = Some(args.next().expect("--ledger needs a value")),
+                _ => panic!("Unknown argument {}", arg),
+            }
+        }
+        parsed
+    }
+
+    /// Outcome ledger to reconcile: the one given with `--ledger`, or else the
+    /// default one in CSV or JSON Lines
+    pub fn ledger(&self) -> String {
+        self.ledger
+            .clone()
+            .or_else(|| existing_ledger(OUTCOME_FILE))
+            .expect("No outcome ledger found, pass one with --ledger")
+    }
+}
@@ -0,0 +1,367 @@
+//! Reconciliation struct
+//!
+//! Check of the outcome ledger of the coordinator against the records of the
+//! agents, matched by payment id. As the journal of an agent only lives in
+//! memory, its record of a payment is the last state logged by the replica
+//! acting as its primary: PREPARE, COMMIT or ABORT.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::communication::{ABORT, COMMIT, PREPARE};
+use crate::discrepancy::Discrepancy;
+use crate::log_line::LogLine;
+use crate::outcome::{decision_name, Outcome};
+
+/// Reconciliation struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconciliation {
+    /// Amount of outcomes read from the ledger
+    pub outcomes: usize,
+    /// Amount of payments in the ledger or in the records of any agent
+    pub payments: usize,
+    /// Discrepancies found, in the order of the payment ids
+    pub discrepancies: Vec<Discrepancy>,
+}
+
+impl Reconciliation {
+    /// Reconciles the lines of an outcome ledger, each one an outcome or the
+    /// number of a line that can't be read, with the log lines of the agents
+    pub fn new(
+        outcomes: &[Result<Outcome, usize>],
+        lines: &[LogLine],
+        agents: &[String],
+    ) -> Reconciliation {
+        let mut discrepancies = vec![];
+        let mut ledger: BTreeMap<&str, &Outcome> = BTreeMap::new();
+        for outcome in outcomes {
+            match outcome {
+                Err(line) => discrepancies.push(Discrepancy::UnreadableLine(*line)),
+                Ok(outcome) => {
+                    if ledger.insert(&outcome.payment, outcome).is_some() {
+                        discrepancies.push(Discrepancy::RepeatedOutcome(outcome.payment.clone()));
+                    }
+                }
+            }
+        }
+
+        let states = agent_states(lines, agents);
+        let payments: BTreeSet<&str> = ledger
+            .keys()
+            .copied()
+            .chain(
+                states
+                    .iter()
+                    .flat_map(|agent| agent.keys().map(String::as_str)),
+            )
+            .collect();
+
+        for payment in &payments {
+            let with_state = |state: u8| -> Vec<String> {
+                agents
+                    .iter()
+                    .zip(&states)
+                    .filter(|(_, states)| states.get(*payment) == Some(&state))
+                    .map(|(agent, _)| agent.clone())
+                    .collect()
+            };
+            let outcome = ledger.get(payment);
+
+            for agent in with_state(PREPARE) {
+                discrepancies.push(Discrepancy::StuckInPrepare {
+                    payment: payment.to_string(),
+                    agent,
+                });
+            }
+
+            let committed = with_state(COMMIT);
+            let aborted = with_state(ABORT);
+            if !committed.is_empty() && !aborted.is_empty() {
+                discrepancies.push(Discrepancy::AgentsDisagree {
+                    payment: payment.to_string(),
+                    decision: outcome.map(|outcome| decision_name(outcome.decision).to_string()),
+                    committed,
+                    aborted,
+                });
+                continue;
+            }
+
+            let outcome = match outcome {
+                Some(outcome) => outcome,
+                None => {
+                    for agent in committed {
+                        discrepancies.push(Discrepancy::UnknownCommit {
+                            payment: payment.to_string(),
+                            agent,
+                        });
+                    }
+                    continue;
+                }
+            };
+            for (agent, states) in agents.iter().zip(&states) {
+                match states.get(*payment) {
+                    Some(&state)
+                        if (state == COMMIT || state == ABORT) && state != outcome.decision =>
+                    {
+                        discrepancies.push(Discrepancy::ConflictingDecision {
+                            payment: payment.to_string(),
+                            decision: decision_name(outcome.decision).to_string(),
+                            agent: agent.clone(),
+                            state: decision_name(state).to_string(),
+                        })
+                    }
+                    _ => {}
+                }
+            }
+            if outcome.decision == COMMIT {
+                for &agent in &outcome.participants {
+                    match states.get(agent) {
+                        None => discrepancies.push(Discrepancy::UnknownParticipant {
+                            payment: payment.to_string(),
+                            agent,
+                        }),
+                        Some(states) if !states.contains_key(*payment) => {
+                            discrepancies.push(Discrepancy::MissingAtAgent {
+                                payment: payment.to_string(),
+                                agent: agents[agent].clone(),
+                            })
+                        }
+                        Some(_) => {}
+                    }
+                }
+            }
+        }
+
+        Reconciliation {
+            outcomes: ledger.len(),
+            payments: payments.len(),
+            discrepancies,
+        }
+    }
+
+    /// Whether the ledger and the agents agree on every payment
+    pub fn is_consistent(&self) -> bool {
+        self.discrepancies.is_empty()
+    }
+}
+
+/// Last state logged for each payment by each agent, in the order of the given
+/// agent names. A repeated PREPARE or an ignored ABORT don't change it.
+pub fn agent_states(lines: &[LogLine], agents: &[String]) -> Vec<BTreeMap<String, u8>> {
+    let mut states = vec![BTreeMap::new(); agents.len()];
+    for line in lines {
+        let agent = match line.agent(agents) {
+            Some(agent) => agent,
+            None => continue,
+        };
+        let (payment, state) = match line.transaction() {
+            Some((payment, "PREPARE")) => (payment, PREPARE),
+            Some((payment, "COMMIT")) => (payment, COMMIT),
+            Some((payment, "ABORT")) => (payment, ABORT),
+            _ => continue,
+        };
+        states[agent].insert(payment.to_string(), state);
+    }
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec!["bank".to_string(), "hotel".to_string()]
+    }
+
+    fn outcome(payment: &str, decision: u8, participants: &[usize]) -> Result<Outcome, usize> {
+        Ok(Outcome {
+            payment: payment.to_string(),
+            participants: participants.to_vec(),
+            prices: vec![1; participants.len()],
+            votes: vec![],
+            decision,
+            started: 0,
+            finished: 0,
+            node: 0,
+        })
+    }
+
+    /// Log lines of the agents, in order
+    fn logs(lines: &[(&str, &str)]) -> Vec<LogLine> {
+        lines
+            .iter()
+            .map(|(log, msg)| {
+                let line = format!("2026-01-01 00:00:00 +00:00 | TRACE  | {} ", msg);
+                LogLine::parse(log, &line).expect("Line written by the logger")
+            })
+            .collect()
+    }
+
+    fn discrepancies(
+        outcomes: &[Result<Outcome, usize>],
+        lines: &[(&str, &str)],
+    ) -> Vec<Discrepancy> {
+        Reconciliation::new(outcomes, &logs(lines), &agents()).discrepancies
+    }
+
+    #[test]
+    fn agreeing_records_are_consistent() {
+        let reconciliation = Reconciliation::new(
+            &[
+                outcome("PAY-1", COMMIT, &[0, 1]),
+                outcome("PAY-2", ABORT, &[0, 1]),
+            ],
+            &logs(&[
+                ("bank", "Transaction PAY-1 | PREPARE"),
+                ("bank", "Transaction PAY-1 | COMMIT"),
+                ("hotel-1", "Transaction PAY-1 | COMMIT"),
+                ("hotel", "Transaction PAY-2 | ABORT"),
+            ]),
+            &agents(),
+        );
+        assert!(reconciliation.is_consistent());
+        assert_eq!(reconciliation.outcomes, 2);
+        assert_eq!(reconciliation.payments, 2);
+    }
+
+    #[test]
+    fn unreadable_and_repeated_lines() {
+        assert_eq!(
+            discrepancies(
+                &[
+                    outcome("PAY-1", ABORT, &[0]),
+                    Err(3),
+                    outcome("PAY-1", ABORT, &[0])
+                ],
+                &[]
+            ),
+            vec![
+                Discrepancy::UnreadableLine(3),
+                Discrepancy::RepeatedOutcome("PAY-1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn agents_that_disagree() {
+        let lines = [
+            ("bank", "Transaction PAY-1 | COMMIT"),
+            ("hotel", "Transaction PAY-1 | ABORT"),
+        ];
+        let disagree = |decision: Option<&str>| Discrepancy::AgentsDisagree {
+            payment: "PAY-1".to_string(),
+            decision: decision.map(str::to_string),
+            committed: vec!["bank".to_string()],
+            aborted: vec!["hotel".to_string()],
+        };
+        assert_eq!(discrepancies(&[], &lines), vec![disagree(None)]);
+        assert_eq!(
+            discrepancies(&[outcome("PAY-1", COMMIT, &[0, 1])], &lines),
+            vec![disagree(Some("COMMIT"))]
+        );
+    }
+
+    #[test]
+    fn agent_with_another_decision() {
+        assert_eq!(
+            discrepancies(
+                &[outcome("PAY-1", COMMIT, &[0, 1])],
+                &[
+                    ("bank", "Transaction PAY-1 | ABORT"),
+                    ("hotel", "Transaction PAY-1 | ABORT"),
+                ]
+            ),
+            ["bank", "hotel"]
+                .iter()
+                .map(|agent| Discrepancy::ConflictingDecision {
+                    payment: "PAY-1".to_string(),
+                    decision: "COMMIT".to_string(),
+                    agent: agent.to_string(),
+                    state: "ABORT".to_string(),
+                })
+                .collect::<Vec<Discrepancy>>()
+        );
+    }
+
+    #[test]
+    fn agent_stuck_in_prepare() {
+        assert_eq!(
+            discrepancies(
+                &[outcome("PAY-1", ABORT, &[0, 1])],
+                &[
+                    ("bank", "Transaction PAY-1 | PREPARE"),
+                    ("hotel", "Transaction PAY-1 | PREPARE"),
+                    ("hotel", "Transaction PAY-1 | ABORT"),
+                ]
+            ),
+            vec![Discrepancy::StuckInPrepare {
+                payment: "PAY-1".to_string(),
+                agent: "bank".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn committed_payment_missing_at_an_agent() {
+        assert_eq!(
+            discrepancies(
+                &[
+                    outcome("PAY-1", COMMIT, &[0, 1]),
+                    outcome("PAY-2", ABORT, &[0, 1])
+                ],
+                &[("bank", "Transaction PAY-1 | COMMIT")]
+            ),
+            vec![Discrepancy::MissingAtAgent {
+                payment: "PAY-1".to_string(),
+                agent: "hotel".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn commit_missing_from_the_ledger() {
+        assert_eq!(
+            discrepancies(
+                &[],
+                &[
+                    ("bank", "Transaction PAY-1 | COMMIT"),
+                    ("hotel", "Transaction PAY-2 | ABORT"),
+                ]
+            ),
+            vec![Discrepancy::UnknownCommit {
+                payment: "PAY-1".to_string(),
+                agent: "bank".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn participant_missing_from_the_config() {
+        assert_eq!(
+            discrepancies(
+                &[outcome("PAY-1", COMMIT, &[0, 2])],
+                &[("bank", "Transaction PAY-1 | COMMIT")]
+            ),
+            vec![Discrepancy::UnknownParticipant {
+                payment: "PAY-1".to_string(),
+                agent: 2,
+            }]
+        );
+    }
+
+    #[test]
+    fn ignored_aborts_keep_the_commit() {
+        let states = agent_states(
+            &logs(&[
+                ("bank", "Transaction PAY-1 | COMMIT"),
+                (
+                    "bank",
+                    "Transaction PAY-1 | Disagreement: ABORT of a committed payment, keeping the COMMIT",
+                ),
+                ("node-0", "Transaction PAY-2 | PREPARE"),
+            ]),
+            &agents(),
+        );
+        assert_eq!(states[0].get("PAY-1"), Some(&COMMIT));
+        assert!(states[1].is_empty());
+    }
+}
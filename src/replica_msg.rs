@@ -0,0 +1,119 @@
+//! Message used between the replicas of an agent
+//!
+//! The primary of an agent sends every change of its payments journal to its backups,
+//! along with periodic heartbeats, so that a backup can take over its place.
+//! Changes are numbered in the order the primary made them, and each backup
+//! acknowledges the last change it applied, so that the primary sends again
+//! the ones a backup missed. Before taking over, a backup polls the other
+//! replicas for their last change, to leave the place to a more up to date one.
+
+use crate::frame_error::FrameError;
+use crate::frame_reader::FrameReader;
+
+/// Replica message sent periodically so backups know the sender is alive
+pub const REPLICA_HEARTBEAT: u8 = b'H';
+/// Replica message carrying a transaction state change
+pub const REPLICA_STATE: u8 = b'S';
+/// Replica message telling backups to stop, as the coordinator finished
+pub const REPLICA_FINISH: u8 = b'F';
+/// Replica message with which a backup acknowledges the changes it applied
+pub const REPLICA_ACK: u8 = b'K';
+/// Replica message with which a backup about to take over asks the other
+/// replicas for their last change, which they answer with a REPLICA_ACK
+pub const REPLICA_POLL: u8 = b'P';
+
+/// The largest ReplicaMsg that can be received
+pub const REPLICA_MSG_MAX: usize = 65507;
+
+/// Message to communicate between replicas of the same agent: 23 bytes followed
+/// by the id of the payment
+pub struct ReplicaMsg {
+    /// 1 byte for the message kind
+    pub kind: u8,
+    /// 4 bytes for the replica index of the sender (0 is the original primary)
+    pub sender: u32,
+    /// Id of the payment whose state changed, empty for the other kinds
+    pub payment_id: String,
+    /// 1 byte for the new state of the payment
+    pub state: u8,
+    /// 1 byte for the vote given when the payment was prepared
+    pub vote: u8,
+    /// 8 bytes for the highest coordinator epoch seen by the sender
+    pub epoch: u64,
+    /// 8 bytes for the number of the change, or of the last change made by a
+    /// primary or applied by a backup for the other kinds
+    pub seq: u64,
+}
+
+impl ReplicaMsg {
+    /// Translate an array of bytes into a ReplicaMsg structure
+    pub fn from_bytes(msg: &[u8]) -> Result<ReplicaMsg, FrameError> {
+        let mut reader = FrameReader::new(msg);
+        let kind = reader.u8()?;
+        let sender = reader.u32()?;
+        let state = reader.u8()?;
+        let vote = reader.u8()?;
+        let epoch = reader.u64()?;
+        let seq = reader.u64()?;
+        let payment_id = reader.bytes(reader.remaining())?;
+
+        Ok(ReplicaMsg {
+            kind,
+            sender,
+            payment_id: String::from_utf8_lossy(payment_id).to_string(),
+            state,
+            vote,
+            epoch,
+            seq,
+        })
+    }
+
+    /// Translate a ReplicaMsg structure into an array of bytes
+    pub fn to_bytes(replica_msg: &ReplicaMsg) -> Vec<u8> {
+        let mut bytes = vec![replica_msg.kind];
+        bytes.extend(replica_msg.sender.to_be_bytes());
+        bytes.push(replica_msg.state);
+        bytes.push(replica_msg.vote);
+        bytes.extend(replica_msg.epoch.to_be_bytes());
+        bytes.extend(replica_msg.seq.to_be_bytes());
+        bytes.extend(replica_msg.payment_id.as_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg() -> ReplicaMsg {
+        ReplicaMsg {
+            kind: REPLICA_STATE,
+            sender: 1,
+            payment_id: "PAY-001".to_string(),
+            state: b'C',
+            vote: 1,
+            epoch: u64::MAX,
+            seq: 42,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = ReplicaMsg::to_bytes(&msg());
+        assert_eq!(bytes.len(), 23 + "PAY-001".len());
+        let decoded = ReplicaMsg::from_bytes(&bytes).expect("Valid message");
+        assert_eq!(ReplicaMsg::to_bytes(&decoded), bytes);
+        assert_eq!(decoded.payment_id, "PAY-001");
+        assert_eq!((decoded.epoch, decoded.seq), (u64::MAX, 42));
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = ReplicaMsg::to_bytes(&msg());
+        for len in 0..23 {
+            assert!(ReplicaMsg::from_bytes(&bytes[..len]).is_err(), "{}", len);
+        }
+        let heartbeat = ReplicaMsg::from_bytes(&bytes[..23]).expect("Valid message");
+        assert_eq!(heartbeat.payment_id, "");
+    }
+}
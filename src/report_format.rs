@@ -0,0 +1,32 @@
+//! ReportFormat enum
+//!
+//! Format a run report is printed or exported in.
+
+use std::path::Path;
+
+/// Extension of the Markdown reports
+pub const MARKDOWN_EXTENSION: &str = "md";
+/// Extension of the HTML reports
+pub const HTML_EXTENSION: &str = "html";
+
+/// ReportFormat enum
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReportFormat {
+    /// Plain text with aligned columns, for the terminal
+    Text,
+    /// Markdown with a table per section
+    Markdown,
+    /// A standalone HTML page with a table per section
+    Html,
+}
+
+impl ReportFormat {
+    /// Format of a file given its extension, `.md` or `.html`, or text otherwise
+    pub fn from_path(path: &str) -> ReportFormat {
+        match Path::new(path).extension() {
+            Some(extension) if extension == MARKDOWN_EXTENSION => ReportFormat::Markdown,
+            Some(extension) if extension == HTML_EXTENSION => ReportFormat::Html,
+            _ => ReportFormat::Text,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! ReportTable struct
+//!
+//! A section of a run report: a title and a table, which can be rendered in
+//! every report format.
+
+use crate::report_format::ReportFormat;
+
+/// ReportTable struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportTable {
+    /// Title of the section
+    pub title: String,
+    /// Name of each column
+    pub columns: Vec<String>,
+    /// Cells of each row, one per column
+    pub rows: Vec<Vec<String>>,
+}
+
+impl ReportTable {
+    /// Creates a section without rows
+    pub fn new(title: &str, columns: &[&str]) -> ReportTable {
+        ReportTable {
+            title: title.to_string(),
+            columns: columns.iter().map(|column| column.to_string()).collect(),
+            rows: vec![],
+        }
+    }
+
+    /// Adds a row, with a cell per column
+    pub fn push(&mut self, row: Vec<String>) {
+        self.rows.push(row);
+    }
+
+    /// Renders the section in the given format
+    pub fn render(&self, format: ReportFormat) -> String {
+        match format {
+            ReportFormat::Text => self.to_text(),
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Html => self.to_html(),
+        }
+    }
+
+    /// Title underlined and the columns padded to the widest cell
+    fn to_text(&self) -> String {
+        let mut text = format!("{}\n{}\n", self.title, "-".repeat(self.title.len()));
+        if self.rows.is_empty() {
+            return text + "None\n";
+        }
+        let widths: Vec<usize> = (0..self.columns.len())
+            .map(|i| {
+                self.rows
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(Some(self.columns[i].chars().count()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let line = |cells: &[String]| {
+            let padded: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+                .collect();
+            padded.join("  ").trim_end().to_string() + "\n"
+        };
+        text += &line(&self.columns);
+        for row in &self.rows {
+            text += &line(row);
+        }
+        text
+    }
+
+    /// A second level heading and a pipe table
+    fn to_markdown(&self) -> String {
+        let mut markdown = format!("## {}\n\n", self.title);
+        if self.rows.is_empty() {
+            return markdown + "None\n";
+        }
+        let line = |cells: &[String]| {
+            let escaped: Vec<String> = cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+            format!("| {} |\n", escaped.join(" | "))
+        };
+        markdown += &line(&self.columns);
+        markdown += &format!("|{}\n", "---|".repeat(self.columns.len()));
+        for row in &self.rows {
+            markdown += &line(row);
+        }
+        markdown
+    }
+
+    /// A second level heading and a table
+    fn to_html(&self) -> String {
+        let mut html = format!("<h2>{}</h2>\n", escape_html(&self.title));
+        if self.rows.is_empty() {
+            return html + "<p>None</p>\n";
+        }
+        let line = |tag: &str, cells: &[String]| {
+            let cells: Vec<String> = cells
+                .iter()
+                .map(|cell| format!("<{}>{}</{}>", tag, escape_html(cell), tag))
+                .collect();
+            format!("<tr>{}</tr>\n", cells.join(""))
+        };
+        html += "<table>\n";
+        html += &line("th", &self.columns);
+        for row in &self.rows {
+            html += &line("td", row);
+        }
+        html + "</table>\n"
+    }
+}
+
+/// Escapes the characters with a meaning in HTML
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> ReportTable {
+        let mut table = ReportTable::new("Agents", &["Agent", "Amount"]);
+        table.push(vec!["bank".to_string(), "121".to_string()]);
+        table.push(vec!["a|b<c>".to_string(), "7".to_string()]);
+        table
+    }
+
+    #[test]
+    fn pads_the_columns_of_text() {
+        assert_eq!(
+            table().render(ReportFormat::Text),
+            "Agents\n------\nAgent   Amount\nbank    121\na|b<c>  7\n"
+        );
+    }
+
+    #[test]
+    fn escapes_the_cells_of_markdown() {
+        assert_eq!(
+            table().render(ReportFormat::Markdown),
+            "## Agents\n\n| Agent | Amount |\n|---|---|\n| bank | 121 |\n| a\\|b<c> | 7 |\n"
+        );
+    }
+
+    #[test]
+    fn escapes_the_cells_of_html() {
+        let html = table().render(ReportFormat::Html);
+        assert!(html.starts_with("<h2>Agents</h2>\n<table>\n<tr><th>Agent</th>"));
+        assert!(html.contains("<tr><td>a|b&lt;c&gt;</td><td>7</td></tr>\n"));
+        assert_eq!(escape_html("\"&\""), "&quot;&amp;&quot;");
+    }
+
+    #[test]
+    fn empty_tables_say_so() {
+        let table = ReportTable::new("Leaders", &["Since"]);
+        assert_eq!(table.render(ReportFormat::Text), "Leaders\n-------\nNone\n");
+        assert_eq!(table.render(ReportFormat::Markdown), "## Leaders\n\nNone\n");
+        assert_eq!(
+            table.render(ReportFormat::Html),
+            "<h2>Leaders</h2>\n<p>None</p>\n"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! RingElection struct
+//!
+//! Ring election algorithm: the ELECTION message goes around the ring collecting
+//! the candidacy of every node alive (its id, how up to date its state is and its
+//! priority), and when it gets back to the node that started it the most up to
+//! date node wins, breaking ties by priority and then by id. A COORDINATOR
+//! message then goes around the ring so every node learns the new leader.
+
+use std::net::SocketAddr;
+use std::thread;
+
+use crate::alglobo_node::{AlgloboNode, MSG_ACK, MSG_COORDINATOR, MSG_ELECTION, TIMEOUT};
+use crate::election::{is_candidacies, rank, Election, CANDIDACY_FIELDS};
+
+/// RingElection struct
+pub struct RingElection {}
+
+impl RingElection {
+    /// Sends the message to the next node in the ring. It waits for an ACK,
+    /// and if it doesn't receive one it will try to send the message to the
+    /// next node in the ring. If no node answers before getting back to this
+    /// one, every other node is down and this one becomes the leader.
+    fn safe_send_next(node: &AlgloboNode, msg: &[u8], id: usize) {
+        if node.is_stopped() {
+            return;
+        }
+        node.logger()
+            .trace(format!("Running safe_send_next for id {}", id));
+        let next_id = node.cluster().next(id);
+        if next_id == node.id() {
+            node.logger()
+                .info("Degraded cluster: no other node answered, leading alone".to_string());
+            node.set_leader(node.id());
+            return;
+        }
+        if !node.send_with_ack(msg, next_id, TIMEOUT) {
+            RingElection::safe_send_next(node, msg, next_id)
+        }
+    }
+
+    /// Id of the node that wins an election with the given candidacies, one
+    /// after the other
+    fn winner(candidacies: &[usize]) -> usize {
+        candidacies
+            .chunks_exact(CANDIDACY_FIELDS)
+            .max_by_key(|candidacy| rank(candidacy))
+            .expect("Unable to get winner")[0]
+    }
+
+    /// Sends the message to the next node in the ring on a new thread, so the
+    /// responder can keep receiving the ACKs.
+    fn spawn_send_next(node: &AlgloboNode, msg: Vec<u8>) {
+        let clone = node.clone();
+        thread::Builder::new()
+            .name(format!("Node{}-Sender", node.id()))
+            .spawn(move || RingElection::safe_send_next(&clone, &msg, clone.id()))
+            .expect("node sender thread creation failed");
+    }
+}
+
+impl Election for RingElection {
+    fn name(&self) -> &'static str {
+        "ring"
+    }
+
+    /// Start a new leader election sending a new MSG_ELECTION to the next node.
+    fn start(&self, node: &AlgloboNode) {
+        RingElection::safe_send_next(
+            node,
+            &AlgloboNode::ids_to_msg(MSG_ELECTION, &node.candidacy()),
+            node.id(),
+        );
+    }
+
+    fn handle(
+        &self,
+        node: &AlgloboNode,
+        msg_type: u8,
+        mut ids: Vec<usize>,
+        from: SocketAddr,
+    ) -> bool {
+        match msg_type {
+            MSG_ELECTION => {
+                node.logger()
+                    .trace(format!("Got ELECTION from {} with ids {:?}", from, ids));
+                node.send_to(&AlgloboNode::ids_to_msg(MSG_ACK, &[node.id()]), from);
+                // The message has the candidacy of each node it went through
+                if ids
+                    .chunks_exact(CANDIDACY_FIELDS)
+                    .any(|candidacy| candidacy[0] == node.id())
+                {
+                    let winner = RingElection::winner(&ids);
+                    node.send_to(&AlgloboNode::ids_to_msg(MSG_COORDINATOR, &[winner]), from);
+                } else {
+                    ids.extend(node.candidacy());
+                    RingElection::spawn_send_next(
+                        node,
+                        AlgloboNode::ids_to_msg(MSG_ELECTION, &ids),
+                    );
+                }
+                true
+            }
+            MSG_COORDINATOR => {
+                node.logger()
+                    .trace(format!("Got COORDINATOR from {} with ids {:?}", from, ids));
+                node.set_leader(ids[0]);
+                node.send_to(&AlgloboNode::ids_to_msg(MSG_ACK, &[node.id()]), from);
+                node.logger()
+                    .trace(format!("Sent ACK to {} with ids {:?}", from, ids));
+                if !ids[1..].contains(&node.id()) {
+                    ids.push(node.id());
+                    RingElection::spawn_send_next(
+                        node,
+                        AlgloboNode::ids_to_msg(MSG_COORDINATOR, &ids),
+                    );
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// An ELECTION has the candidacy of each node it went through, and a
+    /// COORDINATOR has the leader followed by the nodes it went through
+    fn valid_fields(&self, msg_type: u8, fields: &[usize]) -> bool {
+        match msg_type {
+            MSG_ELECTION => is_candidacies(fields),
+            MSG_COORDINATOR => !fields.is_empty(),
+            _ => true,
+        }
+    }
+
+    /// The COORDINATOR message goes around the ring, as after an election
+    fn announce(&self, node: &AlgloboNode) {
+        RingElection::spawn_send_next(node, AlgloboNode::ids_to_msg(MSG_COORDINATOR, &[node.id()]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_most_up_to_date_node_wins() {
+        assert_eq!(RingElection::winner(&[0, 7, 0, 1, 9, 0, 2, 8, 5]), 1);
+    }
+
+    #[test]
+    fn ties_break_by_priority_and_then_by_id() {
+        assert_eq!(RingElection::winner(&[3, 9, 0, 1, 9, 2, 2, 9, 1]), 1);
+        assert_eq!(RingElection::winner(&[0, 9, 2, 4, 9, 2, 2, 9, 2]), 4);
+        assert_eq!(RingElection::winner(&[3, 9, 0]), 3);
+    }
+
+    #[test]
+    fn elections_carry_whole_candidacies() {
+        let ring = RingElection {};
+        assert!(ring.valid_fields(MSG_ELECTION, &[0, 7, 0, 1, 9, 0]));
+        assert!(!ring.valid_fields(MSG_ELECTION, &[0, 7]));
+        assert!(ring.valid_fields(MSG_COORDINATOR, &[4, 0, 1]));
+        assert!(!ring.valid_fields(MSG_COORDINATOR, &[]));
+    }
+}
@@ -0,0 +1,511 @@
+//! RunReport struct
+//!
+//! Summary of a run of alglobo and the agents, built from the lines of their
+//! logs and the payments of the failure ledger: how many transactions were
+//! committed and aborted, the amount committed by each agent, why each payment
+//! was aborted, when the leader changed, how long the elections took and when
+//! an agent was left without a primary.
+//!
+//! The decisions are the ones the leaders logged, and the amounts the ones the
+//! agents logged, as each agent only gets its own price.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, Duration, FixedOffset};
+
+use crate::communication::{ABORT, COMMIT};
+use crate::log_line::LogLine;
+use crate::logger::SEPARATOR;
+use crate::payment::Payment;
+use crate::report_format::ReportFormat;
+use crate::report_table::{escape_html, ReportTable};
+
+/// Title of the report
+const TITLE: &str = "Run report";
+/// Format of the times of the report
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.3f";
+/// Longest time between the nodes finishing the same election, as each one
+/// logs its own end of it
+const ELECTION_SPREAD_MS: i64 = 1000;
+
+/// Time of a log line
+type Time = DateTime<FixedOffset>;
+
+/// RunReport struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunReport {
+    /// Name of every agent of the agents config file
+    agents: Vec<String>,
+    /// Time of the first and the last lines of the logs, if there are any
+    period: Option<(Time, Time)>,
+    /// Last decision a leader logged for each payment id, COMMIT or ABORT
+    decisions: BTreeMap<String, u8>,
+    /// For each agent: the amount committed, and how many payments it committed
+    /// and aborted and voted ERR for
+    totals: Vec<(u64, usize, usize, usize)>,
+    /// Why each aborted payment was aborted, by payment id
+    causes: BTreeMap<String, String>,
+    /// When each node started leading, with its id and epoch
+    leaders: Vec<(Time, usize, u64)>,
+    /// When each election finished, with its algorithm, the leader elected, the
+    /// longest time a node took to finish it in ms and how many nodes did
+    elections: Vec<(Time, String, usize, u64, usize)>,
+    /// When each agent lost its primary, and when a backup took over if one did
+    downtimes: Vec<(String, Time, Option<Time>)>,
+    /// Payments of the failure ledger
+    retry: Vec<Payment>,
+}
+
+impl RunReport {
+    /// Builds the report from the log lines of a run, sorted by time, and the
+    /// payments of its failure ledger
+    pub fn new(lines: &[LogLine], agents: &[String], retry: Vec<Payment>) -> RunReport {
+        let mut report = RunReport {
+            agents: agents.to_vec(),
+            period: lines
+                .first()
+                .zip(lines.last())
+                .map(|(first, last)| (first.time, last.time)),
+            decisions: BTreeMap::new(),
+            totals: vec![(0, 0, 0, 0); agents.len()],
+            causes: BTreeMap::new(),
+            leaders: vec![],
+            elections: vec![],
+            downtimes: vec![],
+            retry,
+        };
+
+        // Price and last state of each payment at each agent
+        let mut prices: HashMap<(usize, String), u32> = HashMap::new();
+        let mut states: HashMap<(usize, String), u8> = HashMap::new();
+        // Agents that voted ERR for each payment, and the payments for which the
+        // leader couldn't reach an agent
+        let mut rejected: HashMap<String, Vec<String>> = HashMap::new();
+        let mut unreachable: HashSet<String> = HashSet::new();
+        // Node that prepared and node that decided each payment
+        let mut prepared_by: HashMap<String, usize> = HashMap::new();
+        let mut decided_by: HashMap<String, usize> = HashMap::new();
+        // Logs of the replicas that started as primary, and when each agent
+        // lost its primary, if it's still without one
+        let mut primaries: HashSet<String> = HashSet::new();
+        let mut down_since: HashMap<String, usize> = HashMap::new();
+
+        for line in lines {
+            if let Some(node) = line.node() {
+                report.read_node_line(
+                    line,
+                    node,
+                    &mut unreachable,
+                    &mut prepared_by,
+                    &mut decided_by,
+                );
+            } else if let Some(index) = line.agent(agents) {
+                let agent = &agents[index];
+                if line.msg.starts_with("Started on ") {
+                    primaries.insert(line.log.clone());
+                    if let Some(downtime) = down_since.remove(agent) {
+                        report.downtimes[downtime].2 = Some(line.time);
+                    }
+                } else if line.msg == "Got killed"
+                    && primaries.contains(&line.log)
+                    && !down_since.contains_key(agent)
+                {
+                    down_since.insert(agent.clone(), report.downtimes.len());
+                    report.downtimes.push((agent.clone(), line.time, None));
+                } else if let Some((id, price, vote)) = parse_agent_payment(&line.msg) {
+                    prices.insert((index, id.to_string()), price);
+                    if vote == "ERR" {
+                        let agents = rejected.entry(id.to_string()).or_default();
+                        if !agents.contains(agent) {
+                            agents.push(agent.clone());
+                        }
+                    }
+                } else if let Some((id, "COMMIT")) = line.transaction() {
+                    states.insert((index, id.to_string()), COMMIT);
+                } else if let Some((id, "ABORT")) = line.transaction() {
+                    states.insert((index, id.to_string()), ABORT);
+                }
+            }
+        }
+
+        for ((agent, id), state) in states {
+            let totals = &mut report.totals[agent];
+            if state == COMMIT {
+                totals.0 += prices.get(&(agent, id)).copied().unwrap_or(0) as u64;
+                totals.1 += 1;
+            } else {
+                totals.2 += 1;
+            }
+        }
+        for (agent, totals) in agents.iter().zip(report.totals.iter_mut()) {
+            totals.3 = rejected
+                .values()
+                .filter(|rejecting| rejecting.contains(agent))
+                .count();
+        }
+
+        for (id, decision) in &report.decisions {
+            if *decision != ABORT {
+                continue;
+            }
+            let cause = if let Some(rejecting) = rejected.get(id) {
+                format!("{} voted ERR", rejecting.join(", "))
+            } else if unreachable.contains(id) {
+                "An agent was unreachable".to_string()
+            } else {
+                match (prepared_by.get(id), decided_by.get(id)) {
+                    (Some(prepared), Some(decided)) if prepared != decided => format!(
+                        "Leader {} stopped before deciding, node {} aborted it",
+                        prepared, decided
+                    ),
+                    _ => "Unknown".to_string(),
+                }
+            };
+            report.causes.insert(id.clone(), cause);
+        }
+        report
+    }
+
+    /// Reads a line of the log of an alglobo node
+    fn read_node_line(
+        &mut self,
+        line: &LogLine,
+        node: usize,
+        unreachable: &mut HashSet<String>,
+        prepared_by: &mut HashMap<String, usize>,
+        decided_by: &mut HashMap<String, usize>,
+    ) {
+        if let Some((id, msg)) = line.transaction() {
+            match msg {
+                "PREPARE" => {
+                    prepared_by.insert(id.to_string(), node);
+                }
+                "COMMIT" | "ABORT" => {
+                    let decision = if msg == "COMMIT" { COMMIT } else { ABORT };
+                    self.decisions.insert(id.to_string(), decision);
+                    decided_by.insert(id.to_string(), node);
+                }
+                "Could not connect to agent" | "Connection with agent suddenly closed" => {
+                    unreachable.insert(id.to_string());
+                }
+                _ => {}
+            }
+        } else if let Some(epoch) = line.msg.strip_prefix("Leading with epoch ") {
+            if let Ok(epoch) = epoch.parse() {
+                self.leaders.push((line.time, node, epoch));
+            }
+        } else if let Some((algorithm, ms, leader)) = parse_election(&line.msg) {
+            match self.elections.last_mut() {
+                Some(election)
+                    if election.1 == algorithm
+                        && election.2 == leader
+                        && (line.time - election.0).num_milliseconds() <= ELECTION_SPREAD_MS =>
+                {
+                    election.3 = election.3.max(ms);
+                    election.4 += 1;
+                }
+                _ => self
+                    .elections
+                    .push((line.time, algorithm.to_string(), leader, ms, 1)),
+            }
+        }
+    }
+
+    /// Sections of the report
+    pub fn tables(&self) -> Vec<ReportTable> {
+        let committed = self.decisions.values().filter(|d| **d == COMMIT).count();
+        let mut summary = ReportTable::new("Summary", &["Metric", "Value"]);
+        if let Some((first, last)) = self.period {
+            summary.push(vec!["Logs from".to_string(), format_time(&first)]);
+            summary.push(vec!["Logs until".to_string(), format_time(&last)]);
+        }
+        for (name, value) in [
+            ("Transactions decided", self.decisions.len()),
+            ("Committed", committed),
+            ("Aborted", self.decisions.len() - committed),
+            ("Leader changes", self.leaders.len().saturating_sub(1)),
+            ("Elections", self.elections.len()),
+            ("Agent downtime windows", self.downtimes.len()),
+            ("Payments to retry", self.retry.len()),
+        ] {
+            summary.push(vec![name.to_string(), value.to_string()]);
+        }
+
+        let mut totals = ReportTable::new(
+            "Agents",
+            &[
+                "Agent",
+                "Amount committed",
+                "Committed",
+                "Aborted",
+                "ERR votes",
+            ],
+        );
+        for (agent, (amount, committed, aborted, rejected)) in self.agents.iter().zip(&self.totals)
+        {
+            totals.push(vec![
+                agent.clone(),
+                amount.to_string(),
+                committed.to_string(),
+                aborted.to_string(),
+                rejected.to_string(),
+            ]);
+        }
+
+        let mut causes = ReportTable::new("Abort causes", &["Payment", "Cause"]);
+        for (id, cause) in &self.causes {
+            causes.push(vec![id.clone(), cause.clone()]);
+        }
+
+        let mut leaders = ReportTable::new("Leaders", &["Since", "Leader", "Epoch", "Previous"]);
+        let mut previous = None;
+        for (time, node, epoch) in &self.leaders {
+            leaders.push(vec![
+                format_time(time),
+                node.to_string(),
+                epoch.to_string(),
+                previous.map_or("-".to_string(), |node: usize| node.to_string()),
+            ]);
+            previous = Some(*node);
+        }
+
+        let mut elections = ReportTable::new(
+            "Elections",
+            &["Finished", "Algorithm", "Leader", "Duration", "Nodes"],
+        );
+        for (time, algorithm, leader, ms, nodes) in &self.elections {
+            elections.push(vec![
+                format_time(time),
+                algorithm.clone(),
+                leader.to_string(),
+                format!("{} ms", ms),
+                nodes.to_string(),
+            ]);
+        }
+
+        let mut downtimes =
+            ReportTable::new("Agent downtime", &["Agent", "Down", "Back", "Duration"]);
+        for (agent, down, back) in &self.downtimes {
+            let end = back.or_else(|| self.period.map(|(_, last)| last));
+            downtimes.push(vec![
+                agent.clone(),
+                format_time(down),
+                back.map_or("Never".to_string(), |back| format_time(&back)),
+                end.map_or("-".to_string(), |end| format_duration(end - *down)),
+            ]);
+        }
+
+        let mut retry = ReportTable::new("Payments to retry", &["Payment", "Amounts", "Cause"]);
+        for payment in &self.retry {
+            retry.push(vec![
+                payment.id.clone(),
+                payment.describe(&self.agents),
+                self.causes
+                    .get(&payment.id)
+                    .cloned()
+                    .unwrap_or_else(|| "Not aborted in these logs".to_string()),
+            ]);
+        }
+
+        vec![
+            summary, totals, causes, leaders, elections, downtimes, retry,
+        ]
+    }
+
+    /// Renders the whole report in the given format
+    pub fn render(&self, format: ReportFormat) -> String {
+        let sections: Vec<String> = self
+            .tables()
+            .iter()
+            .map(|table| table.render(format))
+            .collect();
+        match format {
+            ReportFormat::Text => format!(
+                "{}\n{}\n\n{}",
+                TITLE,
+                "=".repeat(TITLE.len()),
+                sections.join("\n")
+            ),
+            ReportFormat::Markdown => format!("# {}\n\n{}", TITLE, sections.join("\n")),
+            ReportFormat::Html => format!(
+                "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{0}</title>\n\
+                 <style>table {{ border-collapse: collapse; }} th, td {{ border: 1px solid #999; padding: 2px 8px; }}</style>\n\
+                 </head>\n<body>\n<h1>{0}</h1>\n{1}</body>\n</html>\n",
+                escape_html(TITLE),
+                sections.join("")
+            ),
+        }
+    }
+}
+
+/// Payment id, price and vote of a line of an agent like `Payment PAY-001 of $121 | OK`
+fn parse_agent_payment(msg: &str) -> Option<(&str, u32, &str)> {
+    let (id, rest) = msg.strip_prefix("Payment ")?.split_once(" of $")?;
+    let (price, vote) = rest.split_once(SEPARATOR)?;
+    Some((id, price.parse().ok()?, vote))
+}
+
+/// Algorithm, duration in ms and leader of a line of a node like
+/// `Election (ring) finished in 3 ms with leader 4`
+fn parse_election(msg: &str) -> Option<(&str, u64, usize)> {
+    let (algorithm, rest) = msg
+        .strip_prefix("Election (")?
+        .split_once(") finished in ")?;
+    let (ms, leader) = rest.split_once(" ms with leader ")?;
+    Some((algorithm, ms.parse().ok()?, leader.parse().ok()?))
+}
+
+/// Formats the time of a log line
+fn format_time(time: &Time) -> String {
+    time.format(TIME_FORMAT).to_string()
+}
+
+/// Formats a duration in seconds
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3} s", duration.num_milliseconds() as f64 / 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn agents() -> Vec<String> {
+        vec!["bank".to_string(), "hotel".to_string()]
+    }
+
+    /// Logs of a run with a committed payment, one aborted because the hotel
+    /// voted ERR, and one aborted by the next leader after the first one was
+    /// killed, while the bank lost its primary
+    fn run() -> Vec<LogLine> {
+        [
+            ("node-0", "Election (ring) finished in 3 ms with leader 0"),
+            ("node-1", "Election (ring) finished in 5 ms with leader 0"),
+            ("node-0", "Leading with epoch 1"),
+            ("bank", "Started on 127.0.0.1:9000 with sucess rate 1"),
+            ("node-0", "Transaction PAY-1 | PREPARE"),
+            ("bank", "Payment PAY-1 of $100 | OK"),
+            ("hotel", "Payment PAY-1 of $50 | OK"),
+            ("node-0", "Transaction PAY-1 | COMMIT"),
+            ("bank", "Transaction PAY-1 | COMMIT"),
+            ("hotel", "Transaction PAY-1 | COMMIT"),
+            ("node-0", "Transaction PAY-2 | PREPARE"),
+            ("bank", "Payment PAY-2 of $30 | OK"),
+            ("hotel", "Payment PAY-2 of $20 | ERR"),
+            ("node-0", "Transaction PAY-2 | ABORT"),
+            ("bank", "Transaction PAY-2 | ABORT"),
+            ("hotel", "Transaction PAY-2 | ABORT"),
+            ("node-0", "Transaction PAY-3 | PREPARE"),
+            ("bank", "Payment PAY-3 of $10 | OK"),
+            ("bank", "Got killed"),
+            ("node-0", "Got killed"),
+            ("node-1", "Election (bully) finished in 7 ms with leader 1"),
+            ("node-1", "Leading with epoch 2"),
+            ("bank-1", "Started on 127.0.0.1:9001 with sucess rate 1"),
+            ("node-1", "Transaction PAY-3 | ABORT"),
+            ("bank-1", "Transaction PAY-3 | ABORT"),
+        ]
+        .iter()
+        .enumerate()
+        .map(|(second, (log, msg))| {
+            let line = format!("2026-01-01 00:00:{:02} +00:00 | INFO   | {} ", second, msg);
+            LogLine::parse(log, &line).expect("Line written by the logger")
+        })
+        .collect()
+    }
+
+    fn rows(report: &RunReport, title: &str) -> Vec<Vec<String>> {
+        report
+            .tables()
+            .into_iter()
+            .find(|table| table.title == title)
+            .expect("Table of the report")
+            .rows
+    }
+
+    fn row(cells: &[&str]) -> Vec<String> {
+        cells.iter().map(|cell| cell.to_string()).collect()
+    }
+
+    #[test]
+    fn counts_the_decisions_of_the_leaders() {
+        let report = RunReport::new(&run(), &agents(), vec![]);
+        assert_eq!(
+            rows(&report, "Summary")[2..],
+            [
+                row(&["Transactions decided", "3"]),
+                row(&["Committed", "1"]),
+                row(&["Aborted", "2"]),
+                row(&["Leader changes", "1"]),
+                row(&["Elections", "2"]),
+                row(&["Agent downtime windows", "1"]),
+                row(&["Payments to retry", "0"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_up_the_amounts_of_each_agent() {
+        let report = RunReport::new(&run(), &agents(), vec![]);
+        assert_eq!(
+            rows(&report, "Agents"),
+            vec![
+                row(&["bank", "100", "1", "2", "0"]),
+                row(&["hotel", "50", "1", "1", "1"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn explains_each_abort() {
+        let report = RunReport::new(&run(), &agents(), vec![]);
+        assert_eq!(
+            rows(&report, "Abort causes"),
+            vec![
+                row(&["PAY-2", "hotel voted ERR"]),
+                row(&[
+                    "PAY-3",
+                    "Leader 0 stopped before deciding, node 1 aborted it"
+                ]),
+            ]
+        );
+    }
+
+    #[test]
+    fn follows_the_leaders_and_the_agents() {
+        let report = RunReport::new(&run(), &agents(), vec![]);
+        assert_eq!(
+            rows(&report, "Elections"),
+            vec![
+                row(&["2026-01-01 00:00:00.000", "ring", "0", "5 ms", "2"]),
+                row(&["2026-01-01 00:00:20.000", "bully", "1", "7 ms", "1"]),
+            ]
+        );
+        assert_eq!(
+            rows(&report, "Leaders")[1],
+            row(&["2026-01-01 00:00:21.000", "1", "2", "0"])
+        );
+        assert_eq!(
+            rows(&report, "Agent downtime"),
+            vec![row(&[
+                "bank",
+                "2026-01-01 00:00:18.000",
+                "2026-01-01 00:00:22.000",
+                "4.000 s"
+            ])]
+        );
+    }
+
+    #[test]
+    fn empty_logs_have_empty_totals() {
+        let report = RunReport::new(&[], &agents(), vec![]);
+        assert_eq!(
+            rows(&report, "Summary")[0],
+            row(&["Transactions decided", "0"])
+        );
+        assert_eq!(
+            rows(&report, "Agents")[1],
+            row(&["hotel", "0", "0", "0", "0"])
+        );
+        assert!(rows(&report, "Abort causes").is_empty());
+    }
+}
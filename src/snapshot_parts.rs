@@ -0,0 +1,63 @@
+//! SnapshotParts struct
+//!
+//! Parts of a snapshot of the coordinator state received so far. The leader
+//! splits snapshots into parts that fit in a datagram, and a node only applies
+//! a snapshot once it got all of them. A lost part makes the node ask for the
+//! snapshot again, when the next update shows that it's behind.
+
+/// SnapshotParts struct
+#[derive(Debug, Default)]
+pub struct SnapshotParts {
+    /// Epoch and sequence number of the state of the snapshot
+    version: (usize, usize),
+    /// Each part of the snapshot, if it arrived
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl SnapshotParts {
+    /// Adds a part of the snapshot of the given version, dropping the parts of
+    /// any other one. Returns the whole snapshot if it was the last part missing.
+    pub fn add(
+        &mut self,
+        version: (usize, usize),
+        part: usize,
+        count: usize,
+        bytes: &[u8],
+    ) -> Option<Vec<u8>> {
+        if version != self.version || count != self.parts.len() {
+            self.version = version;
+            self.parts = vec![None; count];
+        }
+        self.parts[part] = Some(bytes.to_vec());
+        if self.parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let snapshot = self.parts.drain(..).flatten().flatten().collect();
+        Some(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_the_parts_in_order() {
+        let mut parts = SnapshotParts::default();
+        assert_eq!(parts.add((1, 5), 2, 3, b"ef"), None);
+        assert_eq!(parts.add((1, 5), 0, 3, b"ab"), None);
+        assert_eq!(parts.add((1, 5), 0, 3, b"ab"), None);
+        assert_eq!(parts.add((1, 5), 1, 3, b"cd"), Some(b"abcdef".to_vec()));
+    }
+
+    #[test]
+    fn drops_the_parts_of_other_versions() {
+        let mut parts = SnapshotParts::default();
+        assert_eq!(parts.add((1, 5), 0, 2, b"ab"), None);
+        assert_eq!(parts.add((1, 6), 1, 2, b"CD"), None);
+        assert_eq!(parts.add((1, 6), 0, 2, b"AB"), Some(b"ABCD".to_vec()));
+        // A different amount of parts is also another snapshot
+        assert_eq!(parts.add((2, 0), 0, 2, b"ab"), None);
+        assert_eq!(parts.add((2, 0), 0, 1, b"xy"), Some(b"xy".to_vec()));
+    }
+}
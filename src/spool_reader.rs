@@ -0,0 +1,213 @@
+//! SpoolReader struct
+//!
+//! Reads the payments of a spool directory as they arrive. The files of the
+//! spool are only appended to, so the reader keeps how far it read each one and
+//! only reads the lines added since, instead of the whole spool on every poll.
+//! Lines are only read once complete, as the last one may still be being
+//! written.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+use crate::payment::Payment;
+use crate::payment_columns::PaymentColumns;
+use crate::payment_error::PaymentError;
+use crate::payments_format::PaymentsFormat;
+use crate::validation_report::ValidationReport;
+
+/// Bytes read of a spool file, amount of lines read, and the result of
+/// parsing its header if it's a CSV file and it was read
+type SpoolFile = (u64, usize, Option<Result<PaymentColumns, PaymentError>>);
+
+/// SpoolReader struct
+pub struct SpoolReader {
+    /// Spool directory
+    dir: String,
+    /// Name of every agent of the agents config file
+    agents: Vec<String>,
+    /// How far each file of the spool was read
+    files: HashMap<PathBuf, SpoolFile>,
+    /// Every line read from the spool
+    report: ValidationReport,
+}
+
+impl SpoolReader {
+    /// Creates a reader of the spool directory that didn't read anything yet
+    pub fn new(dir: &str, agents: &[String]) -> SpoolReader {
+        SpoolReader {
+            dir: dir.to_string(),
+            agents: agents.to_vec(),
+            files: HashMap::new(),
+            report: ValidationReport::default(),
+        }
+    }
+
+    /// Reads the lines added to the spool since the last read, in the name
+    /// order of its `.csv` and `.jsonl` files, skipping hidden ones. Returns
+    /// true if any line was read.
+    pub fn read(&mut self) -> bool {
+        let mut files: Vec<PathBuf> = fs::read_dir(&self.dir)
+            .expect("Couldn't read spool directory")
+            .map(|entry| entry.expect("Couldn't read spool directory").path())
+            .filter(|file| {
+                file.extension().is_some_and(|extension| {
+                    extension == PaymentsFormat::Csv.extension()
+                        || extension == PaymentsFormat::JsonLines.extension()
+                }) && !file
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+            })
+            .collect();
+        files.sort();
+
+        let mut read = false;
+        for file in files {
+            let (offset, lines, columns) = self.files.entry(file.clone()).or_default();
+            let mut contents = String::new();
+            File::open(&file)
+                .and_then(|mut opened| {
+                    opened.seek(SeekFrom::Start(*offset))?;
+                    opened.read_to_string(&mut contents)
+                })
+                .expect("Couldn't read spool file");
+            let complete = match contents.rfind('\n') {
+                Some(end) => &contents[..=end],
+                None => continue,
+            };
+
+            let filename = file.to_string_lossy();
+            Payment::validate_lines(
+                &filename,
+                PaymentsFormat::from_path(&filename),
+                complete,
+                *lines + 1,
+                columns,
+                &self.agents,
+                &mut self.report,
+            );
+            *offset += complete.len() as u64;
+            *lines += complete.lines().count();
+            read = true;
+        }
+        read
+    }
+
+    /// Every line read from the spool so far
+    pub fn report(&self) -> &ValidationReport {
+        &self.report
+    }
+
+    /// Takes the lines read from the spool
+    pub fn into_report(self) -> ValidationReport {
+        self.report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn agents() -> Vec<String> {
+        vec![
+            "bank".to_string(),
+            "airline".to_string(),
+            "hotel".to_string(),
+        ]
+    }
+
+    /// Empty spool directory of a test in the temporary directory
+    fn spool(test: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("spool-{}-{}", test, std::process::id()));
+        let _ignore = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).expect("Spool created");
+        dir.to_string_lossy().into_owned()
+    }
+
+    fn append(spool: &str, file: &str, contents: &str) {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(PathBuf::from(spool).join(file))
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .expect("Spool file written");
+    }
+
+    fn ids(reader: &SpoolReader) -> Vec<&str> {
+        reader
+            .report()
+            .payments
+            .iter()
+            .map(|payment| payment.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn reads_the_files_in_name_order() {
+        let dir = spool("order");
+        append(
+            &dir,
+            "b.jsonl",
+            "{\"id\": \"PAY-2\", \"amounts\": {\"bank\": 1}}\n",
+        );
+        append(&dir, "a.csv", "id,bank,airline,hotel\nPAY-1,1,2,3\n");
+        append(&dir, ".c.csv", "id,bank,airline,hotel\nPAY-3,1,2,3\n");
+        append(&dir, "d.txt", "id,bank,airline,hotel\nPAY-4,1,2,3\n");
+
+        let mut reader = SpoolReader::new(&dir, &agents());
+        assert!(reader.read());
+        assert_eq!(ids(&reader), vec!["PAY-1", "PAY-2"]);
+        assert!(reader.report().is_valid());
+    }
+
+    #[test]
+    fn resumes_where_it_stopped() {
+        let dir = spool("resume");
+        append(&dir, "a.csv", "id,bank,airline,hotel\nPAY-1,1,2,3\n");
+        let mut reader = SpoolReader::new(&dir, &agents());
+        assert!(reader.read());
+        assert!(!reader.read());
+
+        append(&dir, "a.csv", "PAY-2,1,2,3\nPAY-3,1,x,3\n");
+        assert!(reader.read());
+        assert_eq!(ids(&reader), vec!["PAY-1", "PAY-2"]);
+        let invalid = &reader.report().invalid;
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].number, 4);
+        assert_eq!(reader.into_report().ids["PAY-2"].1, 3);
+    }
+
+    #[test]
+    fn waits_for_the_last_line_to_be_complete() {
+        let dir = spool("partial");
+        append(
+            &dir,
+            "a.csv",
+            "id,bank,airline,hotel\nPAY-1,1,2,3\nPAY-2,1,",
+        );
+        let mut reader = SpoolReader::new(&dir, &agents());
+        assert!(reader.read());
+        assert_eq!(ids(&reader), vec!["PAY-1"]);
+        assert!(!reader.read());
+
+        append(&dir, "a.csv", "2,3\n");
+        assert!(reader.read());
+        assert_eq!(ids(&reader), vec!["PAY-1", "PAY-2"]);
+        assert!(reader.report().is_valid());
+    }
+
+    #[test]
+    fn files_without_a_complete_line_are_read_later() {
+        let dir = spool("incomplete");
+        append(&dir, "a.csv", "id,bank,air");
+        let mut reader = SpoolReader::new(&dir, &agents());
+        assert!(!reader.read());
+
+        append(&dir, "a.csv", "line,hotel\nPAY-1,1,2,3\n");
+        assert!(reader.read());
+        assert_eq!(ids(&reader), vec!["PAY-1"]);
+    }
+}
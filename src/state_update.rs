@@ -0,0 +1,205 @@
+//! StateUpdate struct
+//!
+//! A change of the coordinator state, which the leader replicates to every node
+//! before acting on it.
+
+use crate::frame_error::FrameError;
+use crate::frame_reader::FrameReader;
+use crate::utils::now_millis;
+
+/// Update with the fingerprint of the payments file as data, and its amount of rows as id
+pub const SOURCE: u8 = b'I';
+/// Update with the vote of each agent involved in the prepared transaction as data
+pub const VOTES: u8 = b'V';
+
+/// StateUpdate struct
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateUpdate {
+    /// Kind of update: a transaction status (PREPARE, COMMIT or ABORT), SOURCE or VOTES
+    pub status: u8,
+    /// Number of the transaction, counting the ones started before it, or the
+    /// amount of rows for a SOURCE
+    pub id: usize,
+    /// Id of the payment of the transaction, empty for a SOURCE
+    pub payment: String,
+    /// Milliseconds since the Unix epoch when the leader made the update
+    pub time: u64,
+    /// Index of each agent involved in the transaction followed by its price
+    /// for a PREPARE, the id of the node that decided for a COMMIT or ABORT,
+    /// or the data of the other kinds
+    pub data: Vec<u32>,
+}
+
+impl StateUpdate {
+    /// Creates an update made now, without data
+    pub fn new(status: u8, id: usize, payment: &str) -> StateUpdate {
+        StateUpdate {
+            status,
+            id,
+            payment: payment.to_string(),
+            time: now_millis(),
+            data: vec![],
+        }
+    }
+
+    /// Translate the update into an array of bytes, with fixed-width big endian
+    /// numbers and the payment id preceded by its length
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.status];
+        bytes.extend((self.id as u64).to_be_bytes());
+        bytes.extend(self.time.to_be_bytes());
+        bytes.extend((self.payment.len() as u64).to_be_bytes());
+        bytes.extend(self.payment.as_bytes());
+        bytes.extend((self.data.len() as u64).to_be_bytes());
+        for value in &self.data {
+            bytes.extend(value.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// Translate an array of bytes into the update, which has to take the
+    /// whole array
+    pub fn from_bytes(bytes: &[u8]) -> Result<StateUpdate, FrameError> {
+        let mut reader = FrameReader::new(bytes);
+        let update = StateUpdate::read(&mut reader)?;
+        reader.finish()?;
+        Ok(update)
+    }
+
+    /// Reads the update at the position of a frame, for frames that carry
+    /// more fields after it
+    pub fn read(reader: &mut FrameReader) -> Result<StateUpdate, FrameError> {
+        let status = reader.u8()?;
+        let id = reader.usize()?;
+        let time = reader.u64()?;
+        let payment = reader.string()?;
+        let count = reader.count(4)?;
+        let data = (0..count).map(|_| reader.u32()).collect::<Result<_, _>>()?;
+        Ok(StateUpdate {
+            status,
+            id,
+            payment,
+            time,
+            data,
+        })
+    }
+
+    /// Translate the update into a list of numbers, for control messages, with
+    /// a number per byte of the payment id
+    pub fn to_fields(&self) -> Vec<usize> {
+        let mut fields = vec![
+            self.status as usize,
+            self.id,
+            self.time as usize,
+            self.payment.len(),
+        ];
+        fields.extend(self.payment.bytes().map(usize::from));
+        fields.push(self.data.len());
+        fields.extend(self.data.iter().map(|value| *value as usize));
+        fields
+    }
+
+    /// Translate the start of a list of numbers into the update, returning it
+    /// along with the amount of numbers it took, or None if the numbers are
+    /// too few for the lengths they announce
+    pub fn from_fields(fields: &[usize]) -> Option<(StateUpdate, usize)> {
+        let length = *fields.get(3)?;
+        let start = 4usize.checked_add(length)?.checked_add(1)?;
+        let payment: Vec<u8> = fields
+            .get(4..start - 1)?
+            .iter()
+            .map(|byte| *byte as u8)
+            .collect();
+        let count = *fields.get(start - 1)?;
+        let end = start.checked_add(count)?;
+        let update = StateUpdate {
+            status: fields[0] as u8,
+            id: fields[1],
+            time: fields[2] as u64,
+            payment: String::from_utf8_lossy(&payment).to_string(),
+            data: fields
+                .get(start..end)?
+                .iter()
+                .map(|value| *value as u32)
+                .collect(),
+        };
+        Some((update, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update() -> StateUpdate {
+        StateUpdate {
+            status: b'P',
+            id: 3,
+            payment: "PAY-003".to_string(),
+            time: 1_600_000_000_000,
+            data: vec![0, 121, 2, 433],
+        }
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        assert_eq!(StateUpdate::from_bytes(&update().to_bytes()), Ok(update()));
+    }
+
+    #[test]
+    fn fields_round_trip() {
+        let mut fields = update().to_fields();
+        let len = fields.len();
+        fields.push(7);
+        assert_eq!(StateUpdate::from_fields(&fields), Some((update(), len)));
+    }
+
+    #[test]
+    fn truncated_bytes() {
+        let bytes = update().to_bytes();
+        for len in 0..bytes.len() {
+            assert!(StateUpdate::from_bytes(&bytes[..len]).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut bytes = update().to_bytes();
+        bytes.push(0);
+        assert_eq!(
+            StateUpdate::from_bytes(&bytes),
+            Err(FrameError::TrailingBytes(1))
+        );
+    }
+
+    #[test]
+    fn lengths_beyond_the_frame() {
+        // A payment id longer than the rest of the frame
+        let mut bytes = vec![b'P'];
+        bytes.extend(&[0; 16]);
+        bytes.extend(u64::MAX.to_be_bytes());
+        assert!(StateUpdate::from_bytes(&bytes).is_err());
+
+        // More data than the rest of the frame
+        let mut bytes = update().to_bytes();
+        let count = bytes.len() - 4 * 4 - 8;
+        bytes[count..count + 8].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(StateUpdate::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_fields() {
+        let fields = update().to_fields();
+        for len in 0..fields.len() {
+            assert_eq!(StateUpdate::from_fields(&fields[..len]), None, "{}", len);
+        }
+        assert_eq!(
+            StateUpdate::from_fields(&[b'P' as usize, 0, 0, usize::MAX]),
+            None
+        );
+        assert_eq!(
+            StateUpdate::from_fields(&[b'P' as usize, 0, 0, 0, usize::MAX]),
+            None
+        );
+    }
+}
@@ -1,7 +1,9 @@
 //! Global utils related to reading and parsing of csv and yaml files
 
 use std::fs::{File, OpenOptions};
-use std::io::{Read, Write};
+use std::io::Write;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::Path;
 
 use serde_yaml::{self, Sequence};
 use std::convert::TryInto;
@@ -16,26 +18,73 @@ pub fn get_agents() -> Sequence {
     serde_yaml::from_reader(agents_config).expect("Couldn't parse agents config yaml")
 }
 
-/// Returns every port present on the agent config file
-pub fn get_agents_ports() -> Vec<u16> {
+/// Returns the addresses of every agent present on the agent config file, with
+/// the primary first and then its backups
+pub fn get_agents_addrs() -> Vec<Vec<SocketAddr>> {
     let agents = get_agents();
-    let mut ports = Vec::new();
+    let mut addrs = Vec::new();
     for agent in agents {
-        let port = agent_get_port(&agent);
-        ports.push(port);
+        addrs.push(agent_get_addrs(&agent));
     }
-    ports
+    addrs
+}
+
+/// Resolves a host:port string, where the host can also be a hostname, into
+/// a socket address
+pub fn resolve_addr(addr: &str) -> SocketAddr {
+    addr.to_socket_addrs()
+        .unwrap_or_else(|_| panic!("Couldn't resolve address {}", addr))
+        .next()
+        .unwrap_or_else(|| panic!("Address {} doesn't resolve to any host", addr))
 }
 
 /// Parses a yaml port into a number
-pub fn agent_get_port(agent: &serde_yaml::Value) -> u16 {
-    agent["port"]
-        .as_u64()
+fn yaml_to_port(port: &serde_yaml::Value) -> u16 {
+    port.as_u64()
         .expect("Agent port must be an unsigned integer")
         .try_into()
         .expect("Agent port must be a valid port number")
 }
 
+/// Parses a yaml address, either a host:port string or a port on localhost
+fn yaml_to_addr(addr: &serde_yaml::Value) -> SocketAddr {
+    match addr.as_str() {
+        Some(addr) => resolve_addr(addr),
+        None => SocketAddr::from(([127, 0, 0, 1], yaml_to_port(addr))),
+    }
+}
+
+/// Parses the yaml addresses of an agent, where the first one belongs to the
+/// primary and the rest to its backups. An agent can either have a list of
+/// `addrs`, a list of `ports` on localhost or a single `port`
+pub fn agent_get_addrs(agent: &serde_yaml::Value) -> Vec<SocketAddr> {
+    match agent["addrs"]
+        .as_sequence()
+        .or_else(|| agent["ports"].as_sequence())
+    {
+        Some(addrs) => addrs.iter().map(yaml_to_addr).collect(),
+        None => vec![yaml_to_addr(&agent["port"])],
+    }
+}
+
+/// Parses the yaml addresses each replica of an agent binds to, which are
+/// its advertised addresses unless a list of `binds` is given
+pub fn agent_get_binds(agent: &serde_yaml::Value) -> Vec<SocketAddr> {
+    let addrs = agent_get_addrs(agent);
+    match agent["binds"].as_sequence() {
+        Some(binds) if binds.len() != addrs.len() => {
+            panic!("Agent must have a bind address for each of its addresses")
+        }
+        Some(binds) => binds.iter().map(yaml_to_addr).collect(),
+        None => addrs,
+    }
+}
+
+/// Returns the name of every agent present on the agent config file
+pub fn get_agents_names() -> Vec<String> {
+    get_agents().iter().map(agent_get_name).collect()
+}
+
 /// Parses a yaml name into a string
 pub fn agent_get_name(agent: &serde_yaml::Value) -> String {
     agent["name"]
@@ -51,22 +100,18 @@ pub fn agent_get_success_rate(agent: &serde_yaml::Value) -> f64 {
         .expect("Agent successrate must be a float")
 }
 
-/// Parses a csv into a vector of a vector of numbers
-pub fn csv_to_prices(filename: &str) -> Vec<Vec<u32>> {
-    let mut file = File::open(filename).expect("File not found");
-    let mut contents = String::new();
-
-    file.read_to_string(&mut contents)
-        .expect("Couldn't read file");
-    let mut result = Vec::new();
-    for line in contents.lines() {
-        let price: Vec<u32> = line
-            .split(',')
-            .map(|x| x.parse::<u32>().expect("Couldn't parse to u32"))
-            .collect();
-        result.push(price);
-    }
-    result
+/// Returns a FNV-1a hash of the name of a payments file or spool directory, to
+/// tell if two nodes are processing the same payments. Its contents aren't
+/// hashed, as payments can be added or edited while the cluster runs, and the
+/// directory isn't either, as the nodes can run on different hosts.
+pub fn fingerprint(filename: &str) -> u32 {
+    let name = match Path::new(filename).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => filename.to_string(),
+    };
+    name.bytes().fold(0x811c_9dc5, |hash: u32, byte| {
+        (hash ^ byte as u32).wrapping_mul(0x0100_0193)
+    })
 }
 
 /// Creates or overwrites a csv
@@ -79,15 +124,27 @@ pub fn create_empty_csv(filename: &str) -> File {
         .expect("Failed to create empty csv file")
 }
 
-/// Writes an array of numbers as a new csv line
-pub fn write_to_csv(mut file: &File, numbers: &[u32]) {
-    let line = numbers
-        .iter()
-        .map(|&n| n.to_string())
-        .collect::<Vec<String>>()
-        .join(",");
+/// Opens a csv to write at its end, creating it if it doesn't exist
+pub fn open_csv_to_append(filename: &str) -> File {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(filename)
+        .expect("Failed to open csv file")
+}
+
+/// Writes a line at the end of a csv
+pub fn write_csv_line(mut file: &File, line: &str) {
     file.write_all(line.as_bytes())
         .expect("Failed to write to csv file");
     file.write_all("\n".as_bytes())
         .expect("Failed to write to csv file");
 }
+
+/// Milliseconds since the Unix epoch
+pub fn now_millis() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .expect("Clock is before the epoch")
+        .as_millis() as u64
+}
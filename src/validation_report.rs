@@ -0,0 +1,203 @@
+//! ValidationReport struct
+//!
+//! Result of checking every line of a payments file, or of a spool directory,
+//! before processing any payment: the valid payments, and the line number and
+//! reason of every invalid line.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::invalid_line::InvalidLine;
+use crate::payment::Payment;
+use crate::payment_error::PaymentError;
+
+/// ValidationReport struct
+#[derive(Debug, Clone, Default)]
+pub struct ValidationReport {
+    /// Every valid payment, in order
+    pub payments: Vec<Payment>,
+    /// Every invalid line, in order
+    pub invalid: Vec<InvalidLine>,
+    /// File, line and position in the payments of every valid payment, by id
+    pub ids: HashMap<String, (String, usize, usize)>,
+}
+
+impl ValidationReport {
+    /// Whether every line is valid
+    pub fn is_valid(&self) -> bool {
+        self.invalid.is_empty()
+    }
+
+    /// Checks the id of a payment against the valid ones. Returns true if it's
+    /// new, false if the same payment was already added, as a payment can be
+    /// listed again, or an error if a different payment has the id.
+    pub fn check_id(&self, payment: &Payment) -> Result<bool, PaymentError> {
+        match self.ids.get(&payment.id) {
+            Some((_, _, position)) if self.payments[*position] == *payment => Ok(false),
+            Some((file, line, _)) => Err(PaymentError::DuplicateId {
+                file: file.clone(),
+                line: *line,
+            }),
+            None => Ok(true),
+        }
+    }
+
+    /// Adds a parsed line, which is invalid if its payment couldn't be parsed
+    /// or a different one has its id, and is skipped if the same payment was
+    /// already added
+    pub fn add(
+        &mut self,
+        file: &str,
+        number: usize,
+        content: &str,
+        payment: Result<Payment, PaymentError>,
+    ) {
+        match payment.and_then(|payment| self.check_id(&payment).map(|new| (payment, new))) {
+            Ok((payment, true)) => {
+                self.ids.insert(
+                    payment.id.clone(),
+                    (file.to_string(), number, self.payments.len()),
+                );
+                self.payments.push(payment);
+            }
+            Ok((_, false)) => {}
+            Err(err) => self.add_invalid(file, number, content, err),
+        }
+    }
+
+    /// Adds an invalid line
+    pub fn add_invalid(&mut self, file: &str, number: usize, content: &str, error: PaymentError) {
+        self.invalid.push(InvalidLine {
+            file: file.to_string(),
+            number,
+            content: content.to_string(),
+            error,
+        });
+    }
+
+    /// Payments to process, in order. Invalid lines are skipped if they're
+    /// quarantined, or else every file with an invalid line is rejected, and
+    /// none of its payments are processed until it's fixed.
+    pub fn accepted(&self, quarantine: bool) -> Vec<Payment> {
+        let rejected: HashSet<&str> = match quarantine {
+            true => HashSet::new(),
+            false => self.invalid.iter().map(|line| line.file.as_str()).collect(),
+        };
+        self.payments
+            .iter()
+            .filter(|payment| !rejected.contains(self.ids[&payment.id].0.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} valid payments, {} invalid lines",
+            self.payments.len(),
+            self.invalid.len()
+        )?;
+        for line in &self.invalid {
+            write!(f, "\n  {}, line {}: {}", line.file, line.number, line.error)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payment(id: &str, price: u32) -> Payment {
+        Payment {
+            id: id.to_string(),
+            participants: vec![0],
+            prices: vec![price],
+            currency: None,
+            metadata: None,
+        }
+    }
+
+    fn ids(payments: &[Payment]) -> Vec<&str> {
+        payments.iter().map(|payment| payment.id.as_str()).collect()
+    }
+
+    /// Two files of a spool, with an invalid line in the second one after a
+    /// valid payment
+    fn spool_report() -> ValidationReport {
+        let mut report = ValidationReport::default();
+        report.add("a.csv", 2, "1,10", Ok(payment("1", 10)));
+        report.add("b.csv", 2, "2,20", Ok(payment("2", 20)));
+        report.add(
+            "b.csv",
+            3,
+            "3,x",
+            Err(PaymentError::InvalidPrice("bank".to_string())),
+        );
+        report.add("a.csv", 3, "4,40", Ok(payment("4", 40)));
+        report
+    }
+
+    #[test]
+    fn the_same_payment_can_be_listed_again() {
+        let mut report = ValidationReport::default();
+        report.add("a.csv", 2, "1,10", Ok(payment("1", 10)));
+        report.add("b.csv", 5, "1,10", Ok(payment("1", 10)));
+        assert!(report.is_valid());
+        assert_eq!(ids(&report.payments), vec!["1"]);
+        assert_eq!(report.ids["1"], ("a.csv".to_string(), 2, 0));
+    }
+
+    #[test]
+    fn a_different_payment_cant_reuse_an_id() {
+        let mut report = ValidationReport::default();
+        report.add("a.csv", 2, "1,10", Ok(payment("1", 10)));
+        report.add("b.csv", 5, "1,11", Ok(payment("1", 11)));
+        assert!(!report.is_valid());
+        assert_eq!(ids(&report.payments), vec!["1"]);
+        assert_eq!(
+            report.invalid,
+            vec![InvalidLine {
+                file: "b.csv".to_string(),
+                number: 5,
+                content: "1,11".to_string(),
+                error: PaymentError::DuplicateId {
+                    file: "a.csv".to_string(),
+                    line: 2,
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn rejects_every_payment_of_a_file_with_an_invalid_line() {
+        let report = spool_report();
+        assert!(!report.is_valid());
+        assert_eq!(ids(&report.accepted(false)), vec!["1", "4"]);
+    }
+
+    #[test]
+    fn quarantine_only_skips_the_invalid_lines() {
+        let report = spool_report();
+        assert_eq!(ids(&report.accepted(true)), vec!["1", "2", "4"]);
+    }
+
+    #[test]
+    fn valid_reports_accept_every_payment() {
+        let mut report = ValidationReport::default();
+        report.add("a.csv", 2, "1,10", Ok(payment("1", 10)));
+        report.add("a.csv", 3, "2,20", Ok(payment("2", 20)));
+        assert!(report.is_valid());
+        assert_eq!(report.accepted(false), report.payments);
+    }
+
+    #[test]
+    fn displays_every_invalid_line() {
+        assert_eq!(
+            spool_report().to_string(),
+            "3 valid payments, 1 invalid lines\n  b.csv, line 3: invalid price for bank"
+        );
+    }
+}
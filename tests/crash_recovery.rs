@@ -0,0 +1,271 @@
+//! Crash recovery test
+//!
+//! Kills the leader at each point of the commit of a transaction (see
+//! src/crash_point.rs) and checks that every agent ends up with the same
+//! decision for every transaction of the payments file, and that the outcome
+//! ledger reconciles with the agents. Runs the alglobo and agents binaries, so
+//! it needs the crash-points feature: `cargo test --features crash-points`.
+//!
+//! Each crash point runs at the same time in its own temporary directory, with
+//! a copy of the configs on free ports, so the logs, ledgers and raft logs of
+//! the working tree aren't touched. The directory of a failed run is kept.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::net::{TcpListener, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, Stdio};
+use std::thread::{self, sleep};
+use std::time::{Duration, Instant};
+
+use serde_yaml::Value;
+
+/// Payments file processed on each run, relative to the directory of the run
+const PAYMENTS: &str = "src/prices.csv";
+/// Configs copied to the directory of each run
+const AGENTS_CONFIG: &str = "src/agents.yaml";
+const CLUSTER_CONFIG: &str = "src/cluster.yaml";
+/// Every crash point, in the order the leader goes through them
+const CRASH_POINTS: [&str; 4] = [
+    "before-decision",
+    "before-phase-two",
+    "during-phase-two",
+    "after-phase-two",
+];
+/// Longest a run of the payments can take
+const RUN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Path of a file of the repository
+fn repo_file(path: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(path)
+}
+
+fn read_yaml(path: &str) -> Value {
+    let file = fs::File::open(repo_file(path)).expect("Couldn't open config file");
+    serde_yaml::from_reader(file).expect("Couldn't parse config yaml")
+}
+
+/// Names of the agents of the agents config file
+fn agent_names() -> Vec<String> {
+    read_yaml(AGENTS_CONFIG)
+        .as_sequence()
+        .expect("Agents config isn't a list")
+        .iter()
+        .map(|agent| {
+            agent["name"]
+                .as_str()
+                .expect("Agent without name")
+                .to_string()
+        })
+        .collect()
+}
+
+/// Ports of localhost free for both TCP and UDP, as the agents bind both
+fn free_ports(amount: usize) -> Vec<u16> {
+    // The sockets are kept until every port is picked, so none is picked twice
+    let mut sockets = vec![];
+    while sockets.len() < amount {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Couldn't bind a free port");
+        let port = listener.local_addr().expect("Bound socket").port();
+        if let Ok(socket) = UdpSocket::bind(("127.0.0.1", port)) {
+            sockets.push((port, listener, socket));
+        }
+    }
+    sockets.into_iter().map(|(port, _, _)| port).collect()
+}
+
+/// Creates the directory of a run, with the payments file and the configs of
+/// the repository moved to free ports
+fn run_dir(point: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("crash-recovery-{}-{}", process::id(), point));
+    let _ignore = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("src")).expect("Couldn't create run directory");
+    fs::copy(repo_file(PAYMENTS), dir.join(PAYMENTS)).expect("Couldn't copy payments file");
+
+    let mut agents = read_yaml(AGENTS_CONFIG);
+    for agent in agents
+        .as_sequence_mut()
+        .expect("Agents config isn't a list")
+    {
+        let replicas = agent["ports"].as_sequence().map_or(1, Vec::len);
+        let agent = agent.as_mapping_mut().expect("Agent isn't a map");
+        agent.remove(&Value::from("port"));
+        agent.insert(
+            Value::from("ports"),
+            Value::Sequence(free_ports(replicas).into_iter().map(Value::from).collect()),
+        );
+    }
+    let mut cluster = read_yaml(CLUSTER_CONFIG);
+    let nodes = cluster["nodes"].as_sequence().map_or(0, Vec::len);
+    let mut ports = free_ports(2 * nodes).into_iter();
+    for node in cluster
+        .as_mapping_mut()
+        .and_then(|cluster| cluster.get_mut(&Value::from("nodes")))
+        .and_then(Value::as_sequence_mut)
+        .expect("Cluster config without nodes")
+    {
+        let node = node.as_mapping_mut().expect("Node isn't a map");
+        for socket in ["ctrl", "data"] {
+            let port = ports.next().expect("A port per socket");
+            node.insert(
+                Value::from(socket),
+                Value::from(format!("127.0.0.1:{}", port)),
+            );
+        }
+    }
+    for (path, config) in [(AGENTS_CONFIG, agents), (CLUSTER_CONFIG, cluster)] {
+        let yaml = serde_yaml::to_string(&config).expect("Couldn't write config yaml");
+        fs::write(dir.join(path), yaml).expect("Couldn't write config file");
+    }
+    dir
+}
+
+/// Every transaction an agent logged with the given operations, by the id of
+/// its payment, keeping the last operation of each one
+fn transactions(dir: &Path, agent: &str, operations: &[&str]) -> BTreeMap<String, String> {
+    let log = fs::read_to_string(dir.join(format!("logs/{}.log", agent))).unwrap_or_default();
+    let mut transactions = BTreeMap::new();
+    for line in log.lines() {
+        let fields: Vec<&str> = line.split(" | ").map(str::trim).collect();
+        for pair in fields.windows(2) {
+            if let Some(id) = pair[0].strip_prefix("Transaction ") {
+                if operations.contains(&pair[1]) {
+                    transactions.insert(id.to_string(), pair[1].to_string());
+                }
+            }
+        }
+    }
+    transactions
+}
+
+/// Waits for a process to end, killing it after the timeout. Returns whether
+/// it ended on its own.
+fn wait_for(child: &mut Child, timeout: Duration) -> bool {
+    let start = Instant::now();
+    while start.elapsed() < timeout {
+        if let Ok(Some(_)) = child.try_wait() {
+            return true;
+        }
+        sleep(Duration::from_millis(100));
+    }
+    let _ignore = child.kill();
+    let _ignore = child.wait();
+    false
+}
+
+/// Processes the payments in the directory of a run with the leader crashing
+/// at the given point, and returns what went wrong, if anything
+fn check_run(dir: &Path, point: &str, agents: &[String], rows: usize) -> Option<String> {
+    let mut agents_process = Command::new(env!("CARGO_BIN_EXE_agents"))
+        .current_dir(dir)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Couldn't start the agents");
+    sleep(Duration::from_secs(1));
+    let mut alglobo = Command::new(env!("CARGO_BIN_EXE_alglobo"))
+        .current_dir(dir)
+        .arg(PAYMENTS)
+        .env("ALGLOBO_CRASH_AT", point)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Couldn't start alglobo");
+    let finished = wait_for(&mut alglobo, RUN_TIMEOUT);
+    let _ignore = agents_process.kill();
+    let _ignore = agents_process.wait();
+    if !finished {
+        return Some("the payments weren't processed in time".to_string());
+    }
+
+    let crashed = fs::read_dir(dir.join("logs"))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("node-"))
+        .any(|entry| {
+            fs::read_to_string(entry.path())
+                .unwrap_or_default()
+                .contains(&format!("Crash injected {}", point))
+        });
+    if !crashed {
+        return Some("no leader crashed".to_string());
+    }
+
+    // Each agent must decide every transaction it prepared, as a payment may
+    // not involve every agent
+    let mut decisions: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for agent in agents {
+        let decided = transactions(dir, agent, &["COMMIT", "ABORT"]);
+        let prepared = transactions(dir, agent, &["PREPARE"]);
+        if prepared.keys().any(|id| !decided.contains_key(id)) {
+            return Some(format!(
+                "{} didn't get a decision for every transaction",
+                agent
+            ));
+        }
+        for (id, decision) in decided {
+            decisions.entry(id).or_default().insert(decision);
+        }
+    }
+    if decisions.len() != rows {
+        return Some("some transaction wasn't decided".to_string());
+    }
+    if decisions.values().any(|decision| decision.len() > 1) {
+        return Some("the agents disagree on a decision".to_string());
+    }
+
+    let reconciled = Command::new(env!("CARGO_BIN_EXE_reconcile"))
+        .current_dir(dir)
+        .stdout(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success());
+    if !reconciled {
+        return Some("the outcome ledger doesn't reconcile with the agents".to_string());
+    }
+    None
+}
+
+/// Runs the payments with a crash at the given point in a directory of its
+/// own, which is removed unless the run failed
+fn run_with_crash(point: &str, agents: &[String], rows: usize) -> Option<String> {
+    let dir = run_dir(point);
+    match check_run(&dir, point, agents, rows) {
+        Some(failure) => Some(format!("{} (see {})", failure, dir.display())),
+        None => {
+            let _ignore = fs::remove_dir_all(&dir);
+            None
+        }
+    }
+}
+
+#[test]
+fn decisions_survive_a_leader_crash_at_every_point() {
+    let agents = agent_names();
+    // Every line but the header is a payment
+    let rows = fs::read_to_string(repo_file(PAYMENTS))
+        .expect("Couldn't read payments file")
+        .lines()
+        .filter(|line| !line.is_empty())
+        .count()
+        - 1;
+
+    let runs: Vec<_> = CRASH_POINTS
+        .iter()
+        .map(|point| {
+            let agents = agents.clone();
+            let run = thread::spawn(move || run_with_crash(point, &agents, rows));
+            (point, run)
+        })
+        .collect();
+    let failures: Vec<String> = runs
+        .into_iter()
+        .filter_map(|(point, run)| {
+            run.join()
+                .expect("Run panicked")
+                .map(|failure| format!("{}: {}", point, failure))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}